@@ -0,0 +1,216 @@
+use crate::Argument;
+use rust_decimal::Decimal;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let operation = match self {
+            Operation::Add => "add",
+            Operation::Sub => "subtract",
+            Operation::Mul => "multiply",
+            Operation::Div => "divide",
+            Operation::Rem => "take the remainder of",
+        };
+        write!(f, "{}", operation)
+    }
+}
+
+/// Failed `Argument::checked_*` operation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArithmeticError {
+    /// The result doesn't fit the result's variant, or a float became infinite
+    Overflow(Operation),
+    DivisionByZero,
+    /// Not numbers, or a `Decimal` and a float
+    Unsupported {
+        operation: Operation,
+        left: &'static str,
+        right: &'static str,
+    },
+}
+
+impl Display for ArithmeticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithmeticError::Overflow(operation) => write!(f, "overflow, failed to {}", operation),
+            ArithmeticError::DivisionByZero => write!(f, "division by zero"),
+            ArithmeticError::Unsupported {
+                operation,
+                left,
+                right,
+            } => write!(f, "cannot {} {} and {}", operation, left, right),
+        }
+    }
+}
+
+impl Error for ArithmeticError {}
+
+/// Both sides converted to the wider variant, `Int` < `Long` < `Decimal` and `Float` < `Double`. Integers mixed with
+/// floats become a `Float` if the integer converts exactly, a `Double` otherwise. Decimals don't mix with floats, which would lose
+/// their exactness.
+enum Operands {
+    Int(i32, i32),
+    Long(i64, i64),
+    Float(f32, f32),
+    Double(f64, f64),
+    Decimal(Decimal, Decimal),
+}
+
+impl Operands {
+    fn of(left: &Argument, right: &Argument) -> Option<Operands> {
+        let operands = match (left, right) {
+            (Argument::Int(a), Argument::Int(b)) => Operands::Int(*a, *b),
+            (Argument::Int(a), Argument::Float(b)) => match exact_float(*a) {
+                Some(a) => Operands::Float(a, *b),
+                None => Operands::Double((*a).into(), (*b).into()),
+            },
+            (Argument::Float(a), Argument::Int(b)) => match exact_float(*b) {
+                Some(b) => Operands::Float(*a, b),
+                None => Operands::Double((*a).into(), (*b).into()),
+            },
+            (Argument::Float(a), Argument::Float(b)) => Operands::Float(*a, *b),
+            (Argument::Decimal(a), b) => Operands::Decimal(*a, decimal(b)?),
+            (a, Argument::Decimal(b)) => Operands::Decimal(decimal(a)?, *b),
+            (a, b) => match (integer(a), integer(b)) {
+                (Some(a), Some(b)) => Operands::Long(a, b),
+                _ => Operands::Double(double(a)?, double(b)?),
+            },
+        };
+        Some(operands)
+    }
+}
+
+/// `None` if `f32` would round `i`, every `i32` up to 2^24 fits
+fn exact_float(i: i32) -> Option<f32> {
+    let f = i as f32;
+    (f as i64 == i64::from(i)).then_some(f)
+}
+
+fn integer(argument: &Argument) -> Option<i64> {
+    match argument {
+        Argument::Int(i) => Some((*i).into()),
+        Argument::Long(l) => Some(*l),
+        _ => None,
+    }
+}
+
+fn double(argument: &Argument) -> Option<f64> {
+    match argument {
+        Argument::Float(f) => Some((*f).into()),
+        Argument::Double(d) => Some(*d),
+        a => integer(a).map(|i| i as f64),
+    }
+}
+
+fn decimal(argument: &Argument) -> Option<Decimal> {
+    match argument {
+        Argument::Decimal(d) => Some(*d),
+        a => integer(a).map(Decimal::from),
+    }
+}
+
+/// `checked_*` on integers and `Decimal`, which share the same method names
+macro_rules! checked {
+    ($a:expr, $b:expr, $zero:expr, $operation:expr) => {{
+        let (a, b) = ($a, $b);
+        match $operation {
+            Operation::Div | Operation::Rem if b == $zero => Err(ArithmeticError::DivisionByZero),
+            operation => match operation {
+                Operation::Add => a.checked_add(b),
+                Operation::Sub => a.checked_sub(b),
+                Operation::Mul => a.checked_mul(b),
+                Operation::Div => a.checked_div(b),
+                Operation::Rem => a.checked_rem(b),
+            }
+            .ok_or(ArithmeticError::Overflow(operation)),
+        }
+    }};
+}
+
+/// Infinite results are only allowed if an operand was already infinite or `NaN`
+fn float(a: f64, b: f64, operation: Operation) -> Result<f64, ArithmeticError> {
+    let result = match operation {
+        Operation::Div | Operation::Rem if b == 0.0 => return Err(ArithmeticError::DivisionByZero),
+        Operation::Add => a + b,
+        Operation::Sub => a - b,
+        Operation::Mul => a * b,
+        Operation::Div => a / b,
+        Operation::Rem => a % b,
+    };
+    finite(result, a, b, operation)
+}
+
+fn finite<T: Into<f64> + Copy>(
+    result: T,
+    a: f64,
+    b: f64,
+    operation: Operation,
+) -> Result<T, ArithmeticError> {
+    if result.into().is_finite() || !a.is_finite() || !b.is_finite() {
+        Ok(result)
+    } else {
+        Err(ArithmeticError::Overflow(operation))
+    }
+}
+
+impl Argument {
+    /// Applies `operation` to two numbers, see `checked_add`.
+    pub fn checked(
+        &self,
+        operation: Operation,
+        other: &Argument,
+    ) -> Result<Argument, ArithmeticError> {
+        let operands = Operands::of(self, other).ok_or(ArithmeticError::Unsupported {
+            operation,
+            left: self.variant_name(),
+            right: other.variant_name(),
+        })?;
+        match operands {
+            Operands::Int(a, b) => checked!(a, b, 0, operation).map(Argument::Int),
+            Operands::Long(a, b) => checked!(a, b, 0, operation).map(Argument::Long),
+            Operands::Decimal(a, b) => {
+                checked!(a, b, Decimal::ZERO, operation).map(Argument::Decimal)
+            }
+            Operands::Double(a, b) => float(a, b, operation).map(Argument::Double),
+            // exact, f64 holds every f32 result before it's rounded back
+            Operands::Float(a, b) => {
+                let (a, b) = (a.into(), b.into());
+                let result = float(a, b, operation)? as f32;
+                finite(result, a, b, operation).map(Argument::Float)
+            }
+        }
+    }
+
+    /// Sum of two numbers in the wider of their variants, `Int(1) + Long(2)` is `Long(3)`. Fails instead of
+    /// wrapping on overflow.
+    pub fn checked_add(&self, other: &Argument) -> Result<Argument, ArithmeticError> {
+        self.checked(Operation::Add, other)
+    }
+
+    pub fn checked_sub(&self, other: &Argument) -> Result<Argument, ArithmeticError> {
+        self.checked(Operation::Sub, other)
+    }
+
+    pub fn checked_mul(&self, other: &Argument) -> Result<Argument, ArithmeticError> {
+        self.checked(Operation::Mul, other)
+    }
+
+    /// Integer division truncates, `Int(7) / Int(2)` is `Int(3)`, any division by zero fails.
+    pub fn checked_div(&self, other: &Argument) -> Result<Argument, ArithmeticError> {
+        self.checked(Operation::Div, other)
+    }
+
+    pub fn checked_rem(&self, other: &Argument) -> Result<Argument, ArithmeticError> {
+        self.checked(Operation::Rem, other)
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    Argument, CallContext, Definition, ErrorKind, FunctionSignature, InitializationArgs, RigzError,
+    RuntimeStatus,
+};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// `Module` for I/O bound functions, calls return a future instead of blocking the thread.
+///
+/// Used by `Runtime::invoke_symbol_async` and `run_async`, which call `SyncModule`s directly. Futures are `Send` so
+/// they can run on a multi-threaded executor.
+pub trait AsyncModule: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn root(&self) -> PathBuf;
+
+    fn function_call<'a>(
+        &'a self,
+        name: &'a str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: Argument,
+        context: &'a CallContext<'a>,
+    ) -> BoxFuture<'a, RuntimeStatus<Argument>>;
+
+    fn initialize(&self, args: InitializationArgs) -> BoxFuture<'_, RuntimeStatus<()>> {
+        let status = if args.all_errors_fatal {
+            RuntimeStatus::Err(RigzError::new(
+                ErrorKind::Initialization,
+                "Initialization Function Not Found",
+            ))
+        } else {
+            RuntimeStatus::NotFound
+        };
+        Box::pin(std::future::ready(status))
+    }
+
+    /// Functions this module defines, empty if the module can't list them. May be incomplete before `initialize`.
+    fn functions(&self) -> Vec<FunctionSignature> {
+        Vec::new()
+    }
+
+    /// Like `Module::on_file_start`
+    fn on_file_start<'a>(&'a self, _file: &'a str) -> BoxFuture<'a, RuntimeStatus<()>> {
+        Box::pin(std::future::ready(RuntimeStatus::Ok(())))
+    }
+
+    /// Like `Module::on_file_end`
+    fn on_file_end<'a>(
+        &'a self,
+        _file: &'a str,
+        _result: &'a Argument,
+    ) -> BoxFuture<'a, RuntimeStatus<()>> {
+        Box::pin(std::future::ready(RuntimeStatus::Ok(())))
+    }
+
+    /// Like `Module::shutdown`
+    fn shutdown(&self) -> BoxFuture<'_, RuntimeStatus<()>> {
+        Box::pin(std::future::ready(RuntimeStatus::Ok(())))
+    }
+
+    /// Like `Module::reload`
+    fn reload(&self, _args: InitializationArgs) -> BoxFuture<'_, RuntimeStatus<()>> {
+        Box::pin(std::future::ready(RuntimeStatus::Ok(())))
+    }
+}
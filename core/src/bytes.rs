@@ -0,0 +1,56 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{Error, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt::Formatter;
+
+/// `Argument::Bytes` as base64 in human readable formats, raw bytes otherwise.
+pub(crate) fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    } else {
+        serializer.serialize_bytes(bytes)
+    }
+}
+
+/// Base64 or a list of bytes in human readable formats, `{"Bytes": [222, 173]}` in JSON, raw bytes otherwise.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+/// Accepts base64, raw bytes, or a list of bytes
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_str("base64 string, bytes, or a list of bytes")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Vec<u8>, E> {
+        STANDARD
+            .decode(v)
+            .map_err(|e| E::custom(format!("invalid base64: {}", e)))
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
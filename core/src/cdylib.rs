@@ -0,0 +1,185 @@
+use crate::{Argument, Definition, InitializationArgs};
+use serde::{Deserialize, Serialize};
+
+/// Version of the C ABI between the runtime and cdylib modules, see `modules/cdylib/rigz_module.h`, and of the wasm
+/// module ABI in rigz_wasm. Modules exporting another `rigz_abi_version` aren't loaded.
+pub const ABI_VERSION: u32 = 1;
+
+/// JSON passed to `rigz_function_call`, it returns a `RuntimeStatus<Argument>` as JSON.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CallRequest {
+    pub name: String,
+    pub arguments: Vec<Argument>,
+    pub definition: Definition,
+    pub prior_result: Argument,
+    /// `None` outside a file
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+/// JSON passed to `rigz_hook`, tagged by `hook`. It returns a `RuntimeStatus<()>` as JSON.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "hook", rename_all = "snake_case")]
+pub enum HookRequest {
+    Initialize { args: InitializationArgs },
+    OnFileStart { file: String },
+    OnFileEnd { file: String, result: Argument },
+    Shutdown,
+    Reload { args: InitializationArgs },
+}
+
+/// Used by `export_module!`, the `extern "C"` functions it generates call these.
+#[cfg(feature = "cdylib")]
+pub mod export {
+    use super::{CallRequest, HookRequest};
+    use crate::{CallContext, Module, RigzError, RuntimeStatus, Scope};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::any::Any;
+    use std::ffi::{c_char, CStr, CString};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// # Safety
+    /// `request` has to be a NUL terminated string, or null.
+    pub unsafe fn function_call<M: Module + ?Sized>(
+        module: &M,
+        request: *const c_char,
+    ) -> *mut c_char {
+        respond(request, |request: CallRequest| {
+            let scope = Scope::default();
+            let context = CallContext::detached(&scope);
+            let context = match &request.file {
+                Some(file) => context.with_file(file),
+                None => context,
+            };
+            module.function_call(
+                &request.name,
+                request.arguments,
+                request.definition,
+                request.prior_result,
+                &context,
+            )
+        })
+    }
+
+    /// # Safety
+    /// `request` has to be a NUL terminated string, or null.
+    pub unsafe fn hook<M: Module + ?Sized>(module: &M, request: *const c_char) -> *mut c_char {
+        respond(request, |request: HookRequest| match request {
+            HookRequest::Initialize { args } => module.initialize(args),
+            HookRequest::OnFileStart { file } => module.on_file_start(&file),
+            HookRequest::OnFileEnd { file, result } => module.on_file_end(&file, &result),
+            HookRequest::Shutdown => module.shutdown(),
+            HookRequest::Reload { args } => module.reload(args),
+        })
+    }
+
+    pub fn functions<M: Module + ?Sized>(module: &M) -> *mut c_char {
+        let functions = catch_unwind(AssertUnwindSafe(|| module.functions())).unwrap_or_default();
+        to_c_string(&functions)
+    }
+
+    /// # Safety
+    /// `s` has to be a string returned by one of the functions above, or null.
+    pub unsafe fn free(s: *mut c_char) {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    }
+
+    /// Parses `request`, runs `f` and returns its status as JSON. Panics are returned as errors, they can't unwind
+    /// into the runtime.
+    unsafe fn respond<R: DeserializeOwned, T: Serialize>(
+        request: *const c_char,
+        f: impl FnOnce(R) -> RuntimeStatus<T>,
+    ) -> *mut c_char {
+        let status = catch_unwind(AssertUnwindSafe(|| {
+            if request.is_null() {
+                return RuntimeStatus::Err(RigzError::runtime("request is null"));
+            }
+            let request = CStr::from_ptr(request).to_string_lossy();
+            match serde_json::from_str(&request) {
+                Ok(request) => f(request),
+                Err(e) => RuntimeStatus::Err(RigzError::runtime(format!("invalid request: {}", e))),
+            }
+        }))
+        .unwrap_or_else(|panic| RuntimeStatus::Err(RigzError::runtime(panic_message(panic))));
+        to_c_string(&status)
+    }
+
+    fn panic_message(panic: Box<dyn Any + Send>) -> String {
+        let message = match panic.downcast::<String>() {
+            Ok(message) => *message,
+            Err(panic) => match panic.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        format!("module panicked: {}", message)
+    }
+
+    /// JSON escapes NUL, so the string never has one inside it
+    fn to_c_string<T: Serialize>(value: &T) -> *mut c_char {
+        let json = serde_json::to_string(value).unwrap_or_else(|e| {
+            let error = RigzError::runtime(format!("failed to serialize response: {}", e));
+            serde_json::to_string(&RuntimeStatus::<()>::Err(error))
+                .expect("RigzError serializes to JSON")
+        });
+        CString::new(json).expect("JSON has no NUL").into_raw()
+    }
+}
+
+/// Exports a `Module` through the C ABI, for crates built as a `cdylib`. Takes an expression creating the module,
+/// which is evaluated on the first call. Needs the `cdylib` feature.
+///
+/// ```ignore
+/// rigz_core::export_module!(Math::default());
+/// ```
+#[cfg(feature = "cdylib")]
+#[macro_export]
+macro_rules! export_module {
+    ($module:expr) => {
+        fn __rigz_module() -> &'static dyn $crate::SyncModule {
+            static MODULE: ::std::sync::OnceLock<::std::boxed::Box<dyn $crate::SyncModule>> =
+                ::std::sync::OnceLock::new();
+            MODULE
+                .get_or_init(|| ::std::boxed::Box::new($module))
+                .as_ref()
+        }
+
+        #[no_mangle]
+        pub extern "C" fn rigz_abi_version() -> u32 {
+            $crate::ABI_VERSION
+        }
+
+        /// # Safety
+        /// `request` has to be a NUL terminated string.
+        #[no_mangle]
+        pub unsafe extern "C" fn rigz_function_call(
+            request: *const ::std::ffi::c_char,
+        ) -> *mut ::std::ffi::c_char {
+            $crate::cdylib_export::function_call(__rigz_module(), request)
+        }
+
+        /// # Safety
+        /// `request` has to be a NUL terminated string.
+        #[no_mangle]
+        pub unsafe extern "C" fn rigz_hook(
+            request: *const ::std::ffi::c_char,
+        ) -> *mut ::std::ffi::c_char {
+            $crate::cdylib_export::hook(__rigz_module(), request)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn rigz_functions() -> *mut ::std::ffi::c_char {
+            $crate::cdylib_export::functions(__rigz_module())
+        }
+
+        /// # Safety
+        /// `s` has to be a string returned by this library.
+        #[no_mangle]
+        pub unsafe extern "C" fn rigz_free(s: *mut ::std::ffi::c_char) {
+            $crate::cdylib_export::free(s)
+        }
+    };
+}
@@ -0,0 +1,362 @@
+use crate::{Argument, Definition, RigzError};
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Numbers compare by value whatever their variant, `Int(1)`, `Long(1)`, `Double(1.0)` and `Decimal(1.00)` are
+/// equal. `NaN` equals itself and sorts after every other number.
+///
+/// Values of different kinds sort in the order `None`, `Bool`, numbers, `String`, `Bytes`, `DateTime`, `List`,
+/// `Object`, `Definition`, `FunctionCall`, `File`, `Error`, so any list of arguments can be sorted. Objects compare
+/// their entries sorted by key.
+impl Ord for Argument {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let (Some(a), Some(b)) = (Number::of(self), Number::of(other)) {
+            return a.cmp(&b);
+        }
+        match (self, other) {
+            (Argument::None, Argument::None) => Ordering::Equal,
+            (Argument::Bool(a), Argument::Bool(b)) => a.cmp(b),
+            (Argument::String(a), Argument::String(b)) => a.cmp(b),
+            (Argument::Bytes(a), Argument::Bytes(b)) => a.cmp(b),
+            (Argument::DateTime(a), Argument::DateTime(b)) => a.cmp(b),
+            (Argument::List(a), Argument::List(b)) => a.cmp(b),
+            (Argument::Object(a), Argument::Object(b)) => compare_objects(a, b),
+            (Argument::Definition(a), Argument::Definition(b)) => a.cmp(b),
+            (Argument::FunctionCall(a), Argument::FunctionCall(b)) => a.cmp(b),
+            (Argument::File(a), Argument::File(b)) => a.cmp(b),
+            (Argument::Error(a), Argument::Error(b)) => a.cmp(b),
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+}
+
+impl PartialOrd for Argument {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Argument {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Argument {}
+
+/// Equal numbers hash the same, integral values as an `i64` and other values a `Decimal` holds exactly as a `Decimal`.
+impl Hash for Argument {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Some(number) = Number::of(self) {
+            number.hash(state);
+            return;
+        }
+        rank(self).hash(state);
+        match self {
+            Argument::Bool(b) => b.hash(state),
+            Argument::String(s) => s.hash(state),
+            Argument::Bytes(b) => b.hash(state),
+            Argument::DateTime(d) => d.hash(state),
+            Argument::List(l) => l.hash(state),
+            Argument::Object(o) => hash_object(o, state),
+            Argument::Definition(d) => d.hash(state),
+            Argument::FunctionCall(fc) => fc.hash(state),
+            Argument::File(f) => Hash::hash(f, state),
+            Argument::Error(e) => e.hash(state),
+            _ => {}
+        }
+    }
+}
+
+fn rank(argument: &Argument) -> u8 {
+    match argument {
+        Argument::None => 0,
+        Argument::Bool(_) => 1,
+        Argument::Int(_)
+        | Argument::Long(_)
+        | Argument::Float(_)
+        | Argument::Double(_)
+        | Argument::Decimal(_) => 2,
+        Argument::String(_) => 3,
+        Argument::Bytes(_) => 4,
+        Argument::DateTime(_) => 5,
+        Argument::List(_) => 6,
+        Argument::Object(_) => 7,
+        Argument::Definition(_) => 8,
+        Argument::FunctionCall(_) => 9,
+        Argument::File(_) => 10,
+        Argument::Error(_) => 11,
+    }
+}
+
+fn sorted_entries(object: &HashMap<String, Argument>) -> Vec<(&String, &Argument)> {
+    let mut entries: Vec<_> = object.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+fn compare_objects(a: &HashMap<String, Argument>, b: &HashMap<String, Argument>) -> Ordering {
+    sorted_entries(a).cmp(&sorted_entries(b))
+}
+
+fn hash_object<H: Hasher>(object: &HashMap<String, Argument>, state: &mut H) {
+    sorted_entries(object).hash(state)
+}
+
+/// `Int` and `Long` are `Integer`, `Float` is widened to a `Double`
+#[derive(Clone, Copy)]
+enum Number {
+    Integer(i64),
+    Double(f64),
+    Decimal(Decimal),
+}
+
+impl Number {
+    fn of(argument: &Argument) -> Option<Number> {
+        let number = match argument {
+            Argument::Int(i) => Number::Integer((*i).into()),
+            Argument::Long(l) => Number::Integer(*l),
+            Argument::Float(f) => Number::Double((*f).into()),
+            Argument::Double(d) => Number::Double(*d),
+            Argument::Decimal(d) => Number::Decimal(*d),
+            _ => return None,
+        };
+        Some(number)
+    }
+}
+
+impl Ord for Number {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (*self, *other) {
+            (Number::Integer(a), Number::Integer(b)) => a.cmp(&b),
+            (Number::Decimal(a), Number::Decimal(b)) => a.cmp(&b),
+            (Number::Double(a), Number::Double(b)) => compare_doubles(a, b),
+            (Number::Integer(a), Number::Decimal(b)) => Decimal::from(a).cmp(&b),
+            (Number::Integer(a), Number::Double(b)) => compare_integer_double(a, b),
+            (Number::Decimal(a), Number::Double(b)) => compare_decimal_double(a, b),
+            (a, b) => b.cmp(&a).reverse(),
+        }
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Number {}
+
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match *self {
+            Number::Integer(i) => hash_integer(i, state),
+            Number::Decimal(d) => hash_decimal(d, state),
+            Number::Double(d) if d.is_nan() => state.write_u8(1),
+            Number::Double(d) => {
+                match integral_double(d).or_else(|| exact_decimal(d).map(Number::Decimal)) {
+                    Some(Number::Integer(i)) => hash_integer(i, state),
+                    Some(Number::Decimal(d)) => hash_decimal(d, state),
+                    _ => {
+                        state.write_u8(2);
+                        d.to_bits().hash(state)
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn hash_integer<H: Hasher>(i: i64, state: &mut H) {
+    state.write_u8(0);
+    i.hash(state)
+}
+
+fn hash_decimal<H: Hasher>(d: Decimal, state: &mut H) {
+    let d = d.normalize();
+    match i64::try_from(d) {
+        Ok(i) if d.is_integer() => hash_integer(i, state),
+        _ => {
+            state.write_u8(3);
+            d.hash(state)
+        }
+    }
+}
+
+/// `Some(Integer)` if `d` is a whole number in range of `i64`
+fn integral_double(d: f64) -> Option<Number> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    if d.fract() == 0.0 && (-LIMIT..LIMIT).contains(&d) {
+        Some(Number::Integer(d as i64))
+    } else {
+        None
+    }
+}
+
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).expect("NaN checked above"),
+    }
+}
+
+/// Exact, unlike `a as f64`, which rounds integers above 2^53
+fn compare_integer_double(a: i64, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Less;
+    }
+    let whole = b.trunc();
+    match integral_double(whole) {
+        Some(Number::Integer(whole_integer)) => a
+            .cmp(&whole_integer)
+            .then_with(|| compare_doubles(0.0, b - whole)),
+        _ if b > 0.0 => Ordering::Less,
+        _ => Ordering::Greater,
+    }
+}
+
+/// Exact, a `Double` only equals a `Decimal` holding exactly the same value
+fn compare_decimal_double(a: Decimal, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Less;
+    }
+    if b.is_infinite() {
+        return if b > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        };
+    }
+    let sign = |zero: bool, negative: bool| match (zero, negative) {
+        (true, _) => 0,
+        (false, true) => -1,
+        (false, false) => 1,
+    };
+    let a_sign = sign(a.is_zero(), a.is_sign_negative());
+    let b_sign = sign(b == 0.0, b < 0.0);
+    if a_sign != b_sign || a_sign == 0 {
+        return a_sign.cmp(&b_sign);
+    }
+    let magnitude = compare_magnitudes(a.mantissa().unsigned_abs(), a.scale(), b.abs());
+    if a_sign < 0 {
+        magnitude.reverse()
+    } else {
+        magnitude
+    }
+}
+
+/// Compares `mantissa / 10^scale` with the finite, positive `b = n * 2^exponent` as
+/// `mantissa` against `n * 5^scale * 2^(scale + exponent)`, which can't overflow as `n < 2^53` and `scale <= 28`
+fn compare_magnitudes(mantissa: u128, scale: u32, b: f64) -> Ordering {
+    let bits = b.to_bits();
+    let fraction = u128::from(bits & ((1 << 52) - 1));
+    let (n, exponent) = match (bits >> 52) & 0x7ff {
+        0 => (fraction, -1074),
+        e => (fraction | 1 << 52, e as i32 - 1075),
+    };
+    let scaled = n * 5u128.pow(scale);
+    let shift = scale as i32 + exponent;
+    if shift >= 0 {
+        let shift = shift as u32;
+        if shift > scaled.leading_zeros() {
+            return Ordering::Less;
+        }
+        return mantissa.cmp(&(scaled << shift));
+    }
+    let shift = shift.unsigned_abs();
+    if shift >= 128 {
+        return mantissa.cmp(&0).then(Ordering::Less);
+    }
+    let exact = scaled & ((1 << shift) - 1) == 0;
+    mantissa.cmp(&(scaled >> shift)).then(if exact {
+        Ordering::Equal
+    } else {
+        Ordering::Less
+    })
+}
+
+/// The `Decimal` equal to `d`, if there is one
+fn exact_decimal(d: f64) -> Option<Decimal> {
+    Decimal::from_f64_retain(d).filter(|decimal| compare_decimal_double(*decimal, d).is_eq())
+}
+
+impl Ord for Definition {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Definition::None, Definition::None) => Ordering::Equal,
+            (Definition::One(a), Definition::One(b)) => compare_objects(a, b),
+            (Definition::Many(a), Definition::Many(b)) => a.cmp(b),
+            (Definition::None, _) => Ordering::Less,
+            (_, Definition::None) => Ordering::Greater,
+            (Definition::One(_), _) => Ordering::Less,
+            (_, Definition::One(_)) => Ordering::Greater,
+        }
+    }
+}
+
+impl PartialOrd for Definition {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for Definition {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Definition::None => {}
+            Definition::One(o) => hash_object(o, state),
+            Definition::Many(l) => l.hash(state),
+        }
+    }
+}
+
+impl Ord for RigzError {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (
+            self.kind,
+            &self.message,
+            &self.module,
+            &self.function,
+            &self.span,
+            &self.cause,
+        )
+            .cmp(&(
+                other.kind,
+                &other.message,
+                &other.module,
+                &other.function,
+                &other.span,
+                &other.cause,
+            ))
+            .then_with(|| compare_objects(&self.data, &other.data))
+    }
+}
+
+impl PartialOrd for RigzError {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for RigzError {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.message.hash(state);
+        self.module.hash(state);
+        self.function.hash(state);
+        self.span.hash(state);
+        self.cause.hash(state);
+        hash_object(&self.data, state);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    Argument, Capability, Definition, FunctionCall, InitializationArgs, ModulePermissions,
+    PermissionDenied, RigzError, RuntimeStatus, SourceSpan,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Calls made through a `CallContext` inside other calls before `invoke_symbol` fails, so a module calling itself
+/// errors instead of overflowing the stack.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+/// Resolves and calls a symbol for a module, the runtime implements it.
+pub trait Invoker: Sync {
+    fn invoke(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: &Argument,
+        context: &CallContext,
+    ) -> RuntimeStatus<Argument>;
+}
+
+/// For modules called outside a runtime, every symbol is `NotFound`
+struct Detached;
+
+impl Invoker for Detached {
+    fn invoke(
+        &self,
+        _name: &str,
+        _arguments: Vec<Argument>,
+        _definition: Definition,
+        _prior_result: &Argument,
+        _context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
+        RuntimeStatus::NotFound
+    }
+}
+
+/// Variables shared by every call in a file, a module can set one for a later call or another module to read.
+#[derive(Debug, Default)]
+pub struct Scope {
+    variables: Mutex<HashMap<String, Argument>>,
+}
+
+impl Scope {
+    pub fn get(&self, name: &str) -> Option<Argument> {
+        self.variables().get(name).cloned()
+    }
+
+    /// Returns the previous value
+    pub fn set(&self, name: impl Into<String>, value: Argument) -> Option<Argument> {
+        self.variables().insert(name.into(), value)
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Argument> {
+        self.variables().remove(name)
+    }
+
+    /// Sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.variables().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn variables(&self) -> std::sync::MutexGuard<'_, HashMap<String, Argument>> {
+        self.variables
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Passed to every `function_call`, what a module needs to call back into the runtime: other modules' functions,
+/// the variables in scope, the file and span being run and the run's arguments.
+#[derive(Clone)]
+pub struct CallContext<'a> {
+    /// `None` outside a file, like `Runtime::invoke_symbol`
+    pub file: Option<&'a str>,
+    pub span: Option<SourceSpan>,
+    pub scope: &'a Scope,
+    /// The run's `RunArgs`
+    pub args: InitializationArgs,
+    /// The called module's, `None` if it isn't restricted
+    pub permissions: Option<&'a ModulePermissions>,
+    invoker: &'a dyn Invoker,
+    depth: usize,
+}
+
+impl<'a> CallContext<'a> {
+    pub fn new(invoker: &'a dyn Invoker, scope: &'a Scope, args: InitializationArgs) -> Self {
+        CallContext {
+            file: None,
+            span: None,
+            scope,
+            args,
+            permissions: None,
+            invoker,
+            depth: 0,
+        }
+    }
+
+    /// Without a runtime, `invoke_symbol` is always `NotFound`. For calling a module directly, like in its tests.
+    pub fn detached(scope: &'a Scope) -> Self {
+        CallContext::new(&Detached, scope, InitializationArgs::default())
+    }
+
+    pub fn with_file(mut self, file: &'a str) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    pub fn with_span(mut self, span: SourceSpan) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_permissions(mut self, permissions: Option<&'a ModulePermissions>) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Whether the called module may use `capability`, modules without permissions can do anything. Lua modules are
+    /// checked by their sandbox. For native modules the runtime only checks the `RigzFile`s in a call's arguments,
+    /// anything else is up to the module, it has to call this before touching files, processes, the environment or
+    /// the network.
+    pub fn check(&self, capability: Capability) -> Result<(), PermissionDenied> {
+        match self.permissions {
+            None => Ok(()),
+            Some(permissions) => permissions.check(capability),
+        }
+    }
+
+    /// Calls nested in the call this context was passed to, 0 for calls in a file
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Calls `name` like a call in the file would, any module can handle it. The call gets this context's file,
+    /// span and scope.
+    pub fn invoke_symbol(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: &Argument,
+    ) -> RuntimeStatus<Argument> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return RuntimeStatus::Err(RigzError::call_depth_exceeded(name));
+        }
+        let nested = CallContext {
+            depth: self.depth + 1,
+            ..self.clone()
+        };
+        self.invoker
+            .invoke(name, arguments, definition, prior_result, &nested)
+    }
+
+    /// Runs a lazy block, an `Argument::FunctionCall`, and any call it returns. Each returned call counts as one more
+    /// level of depth, so a call returning itself fails at `MAX_CALL_DEPTH`.
+    pub fn evaluate(
+        &self,
+        function_call: FunctionCall,
+        prior_result: &Argument,
+    ) -> RuntimeStatus<Argument> {
+        let mut function_call = function_call;
+        let mut context = self.clone();
+        loop {
+            let result = context.invoke_symbol(
+                &function_call.name,
+                function_call.args,
+                function_call.definition,
+                prior_result,
+            );
+            match result {
+                RuntimeStatus::Ok(Argument::FunctionCall(next)) => {
+                    function_call = next;
+                    context.depth += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{Argument, Definition};
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::path::PathBuf;
+
+/// Failed conversion from an `Argument`, `path` is where in a nested value it failed, outermost first.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConversionError {
+    pub expected: String,
+    pub found: String,
+    pub path: Vec<String>,
+}
+
+impl ConversionError {
+    pub fn new(expected: impl Into<String>, found: &Argument) -> Self {
+        ConversionError {
+            expected: expected.into(),
+            found: found.variant_name().to_string(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Adds the field, key, or index this error happened under.
+    pub fn at(mut self, segment: impl Display) -> Self {
+        self.path.insert(0, segment.to_string());
+        self
+    }
+}
+
+impl Display for ConversionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)?;
+        if !self.path.is_empty() {
+            write!(f, " at `{}`", self.path.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConversionError {}
+
+/// Error from `to_argument` or `from_argument`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SerdeError {
+    pub message: String,
+}
+
+impl SerdeError {
+    pub fn new(message: impl Into<String>) -> Self {
+        SerdeError {
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for SerdeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for SerdeError {}
+
+impl serde::ser::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeError::new(msg.to_string())
+    }
+}
+
+impl serde::de::Error for SerdeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerdeError::new(msg.to_string())
+    }
+}
+
+pub trait FromArgument: Sized {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError>;
+}
+
+pub trait IntoArgument {
+    fn into_argument(self) -> Argument;
+}
+
+impl Argument {
+    /// `T::from_argument(self)`, for use with turbofish: `argument.extract::<Vec<String>>()`
+    pub fn extract<T: FromArgument>(self) -> Result<T, ConversionError> {
+        T::from_argument(self)
+    }
+}
+
+impl FromArgument for Argument {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        Ok(argument)
+    }
+}
+
+impl IntoArgument for Argument {
+    fn into_argument(self) -> Argument {
+        self
+    }
+}
+
+/// `None` is an empty definition
+impl FromArgument for Definition {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::None => Ok(Definition::None),
+            Argument::Definition(d) => Ok(d),
+            a => Err(ConversionError::new("Definition", &a)),
+        }
+    }
+}
+
+impl IntoArgument for Definition {
+    fn into_argument(self) -> Argument {
+        match self {
+            Definition::None => Argument::None,
+            d => Argument::Definition(d),
+        }
+    }
+}
+
+impl FromArgument for () {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::None => Ok(()),
+            a => Err(ConversionError::new("None", &a)),
+        }
+    }
+}
+
+impl IntoArgument for () {
+    fn into_argument(self) -> Argument {
+        Argument::None
+    }
+}
+
+impl FromArgument for bool {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::Bool(b) => Ok(b),
+            a => Err(ConversionError::new("Bool", &a)),
+        }
+    }
+}
+
+impl IntoArgument for bool {
+    fn into_argument(self) -> Argument {
+        Argument::Bool(self)
+    }
+}
+
+impl FromArgument for String {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::String(s) => Ok(s),
+            a => Err(ConversionError::new("String", &a)),
+        }
+    }
+}
+
+impl IntoArgument for String {
+    fn into_argument(self) -> Argument {
+        Argument::String(self)
+    }
+}
+
+impl IntoArgument for &str {
+    fn into_argument(self) -> Argument {
+        Argument::String(self.to_string())
+    }
+}
+
+impl FromArgument for PathBuf {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::String(s) => Ok(PathBuf::from(s)),
+            Argument::File(f) => Ok(f.file),
+            a => Err(ConversionError::new("String or File", &a)),
+        }
+    }
+}
+
+impl IntoArgument for PathBuf {
+    fn into_argument(self) -> Argument {
+        Argument::String(self.to_string_lossy().to_string())
+    }
+}
+
+/// Accepts a `DateTime` or an RFC 3339 `String`
+impl FromArgument for DateTime<FixedOffset> {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::DateTime(d) => Ok(d),
+            Argument::String(s) => DateTime::parse_from_rfc3339(&s).map_err(|_| ConversionError {
+                expected: "DateTime or RFC 3339 String".to_string(),
+                found: format!("String({})", s),
+                path: Vec::new(),
+            }),
+            a => Err(ConversionError::new("DateTime or RFC 3339 String", &a)),
+        }
+    }
+}
+
+impl IntoArgument for DateTime<FixedOffset> {
+    fn into_argument(self) -> Argument {
+        Argument::DateTime(self)
+    }
+}
+
+impl FromArgument for DateTime<Utc> {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        DateTime::<FixedOffset>::from_argument(argument).map(|d| d.to_utc())
+    }
+}
+
+impl IntoArgument for DateTime<Utc> {
+    fn into_argument(self) -> Argument {
+        Argument::DateTime(self.fixed_offset())
+    }
+}
+
+/// Accepts a `Decimal`, an `Int` or `Long`, or a `String` like `"19.99"`, never a float
+impl FromArgument for Decimal {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::Decimal(d) => Ok(d),
+            Argument::Int(i) => Ok(i.into()),
+            Argument::Long(l) => Ok(l.into()),
+            Argument::String(s) => s.parse().map_err(|_| ConversionError {
+                expected: "Decimal".to_string(),
+                found: format!("String({})", s),
+                path: Vec::new(),
+            }),
+            a => Err(ConversionError::new("Decimal, Int, Long or String", &a)),
+        }
+    }
+}
+
+impl IntoArgument for Decimal {
+    fn into_argument(self) -> Argument {
+        Argument::Decimal(self)
+    }
+}
+
+/// Integers accept `Int` and `Long`, Lua returns every integer as a `Long`, as long as the value fits.
+macro_rules! integer {
+    ($($t:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl FromArgument for $t {
+                fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+                    let value = match &argument {
+                        Argument::Int(i) => <$t>::try_from(*i).ok(),
+                        Argument::Long(l) => <$t>::try_from(*l).ok(),
+                        a => return Err(ConversionError::new("Int or Long", a)),
+                    };
+                    value.ok_or_else(|| ConversionError {
+                        expected: format!("{} in range of {}", stringify!($variant), stringify!($t)),
+                        found: format!("{}({})", argument.variant_name(), argument),
+                        path: Vec::new(),
+                    })
+                }
+            }
+
+            impl IntoArgument for $t {
+                fn into_argument(self) -> Argument {
+                    Argument::$variant(self.into())
+                }
+            }
+        )*
+    };
+}
+
+integer! {
+    i8 => Int,
+    i16 => Int,
+    i32 => Int,
+    u8 => Int,
+    u16 => Int,
+    i64 => Long,
+    u32 => Long,
+}
+
+macro_rules! unsigned {
+    ($($t:ty),*) => {
+        $(
+            impl FromArgument for $t {
+                fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+                    let value = match &argument {
+                        Argument::Int(i) => <$t>::try_from(*i).ok(),
+                        Argument::Long(l) => <$t>::try_from(*l).ok(),
+                        a => return Err(ConversionError::new("Int or Long", a)),
+                    };
+                    value.ok_or_else(|| ConversionError {
+                        expected: format!("Long in range of {}", stringify!($t)),
+                        found: format!("{}({})", argument.variant_name(), argument),
+                        path: Vec::new(),
+                    })
+                }
+            }
+
+            /// Values above `i64::MAX` become a `Double`
+            impl IntoArgument for $t {
+                fn into_argument(self) -> Argument {
+                    match i64::try_from(self) {
+                        Ok(l) => Argument::Long(l),
+                        Err(_) => Argument::Double(self as f64),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+unsigned!(u64, usize);
+
+impl FromArgument for f32 {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::Float(f) => Ok(f),
+            Argument::Double(d) => Ok(d as f32),
+            Argument::Int(i) => Ok(i as f32),
+            Argument::Long(l) => Ok(l as f32),
+            a => Err(ConversionError::new("Float or Double", &a)),
+        }
+    }
+}
+
+impl IntoArgument for f32 {
+    fn into_argument(self) -> Argument {
+        Argument::Float(self)
+    }
+}
+
+impl FromArgument for f64 {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::Double(d) => Ok(d),
+            Argument::Float(f) => Ok(f.into()),
+            Argument::Int(i) => Ok(i.into()),
+            Argument::Long(l) => Ok(l as f64),
+            a => Err(ConversionError::new("Double or Float", &a)),
+        }
+    }
+}
+
+impl IntoArgument for f64 {
+    fn into_argument(self) -> Argument {
+        Argument::Double(self)
+    }
+}
+
+impl<T: FromArgument> FromArgument for Option<T> {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        match argument {
+            Argument::None => Ok(None),
+            a => T::from_argument(a).map(Some),
+        }
+    }
+}
+
+impl<T: IntoArgument> IntoArgument for Option<T> {
+    fn into_argument(self) -> Argument {
+        match self {
+            None => Argument::None,
+            Some(v) => v.into_argument(),
+        }
+    }
+}
+
+/// Accepts a `List`, `Definition::Many`, or `Bytes` as a list of `Int`
+impl<T: FromArgument> FromArgument for Vec<T> {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        let list = match argument {
+            Argument::List(l) => l,
+            Argument::Definition(Definition::Many(l)) => l,
+            Argument::Bytes(b) => b.into_iter().map(|b| Argument::Int(b.into())).collect(),
+            a => return Err(ConversionError::new("List", &a)),
+        };
+        list.into_iter()
+            .enumerate()
+            .map(|(index, a)| T::from_argument(a).map_err(|e| e.at(index)))
+            .collect()
+    }
+}
+
+impl<T: IntoArgument> IntoArgument for Vec<T> {
+    fn into_argument(self) -> Argument {
+        Argument::List(self.into_iter().map(IntoArgument::into_argument).collect())
+    }
+}
+
+/// Accepts an `Object` or `Definition::One`, keys are converted from `String`
+impl<K, V> FromArgument for HashMap<K, V>
+where
+    K: FromArgument + Eq + Hash,
+    V: FromArgument,
+{
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        let object = match argument {
+            Argument::Object(o) => o,
+            Argument::Definition(Definition::One(o)) => o,
+            a => return Err(ConversionError::new("Object", &a)),
+        };
+        let mut results = HashMap::with_capacity(object.len());
+        for (key, value) in object {
+            let value = V::from_argument(value).map_err(|e| e.at(&key))?;
+            let key = K::from_argument(Argument::String(key.clone())).map_err(|e| e.at(&key))?;
+            results.insert(key, value);
+        }
+        Ok(results)
+    }
+}
+
+impl<K: Display, V: IntoArgument> IntoArgument for HashMap<K, V> {
+    fn into_argument(self) -> Argument {
+        Argument::Object(
+            self.into_iter()
+                .map(|(k, v)| (k.to_string(), v.into_argument()))
+                .collect(),
+        )
+    }
+}
+
+/// Tuples are a `List` with exactly one element per field
+macro_rules! tuple {
+    ($len:literal => $($t:ident $index:tt),+) => {
+        impl<$($t: FromArgument),+> FromArgument for ($($t,)+) {
+            fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+                let list = match argument {
+                    Argument::List(l) if l.len() == $len => l,
+                    Argument::List(l) => {
+                        return Err(ConversionError {
+                            expected: format!("List of {} elements", $len),
+                            found: format!("List of {} elements", l.len()),
+                            path: Vec::new(),
+                        })
+                    }
+                    a => return Err(ConversionError::new(format!("List of {} elements", $len), &a)),
+                };
+                let mut values = list.into_iter();
+                Ok(($(
+                    $t::from_argument(values.next().expect("length checked above"))
+                        .map_err(|e| e.at($index))?,
+                )+))
+            }
+        }
+
+        impl<$($t: IntoArgument),+> IntoArgument for ($($t,)+) {
+            fn into_argument(self) -> Argument {
+                Argument::List(vec![$(self.$index.into_argument()),+])
+            }
+        }
+    };
+}
+
+tuple!(1 => A 0);
+tuple!(2 => A 0, B 1);
+tuple!(3 => A 0, B 1, C 2);
+tuple!(4 => A 0, B 1, C 2, D 3);
+tuple!(5 => A 0, B 1, C 2, D 3, E 4);
+tuple!(6 => A 0, B 1, C 2, D 3, E 4, F 5);
@@ -0,0 +1,273 @@
+use crate::{Argument, Definition, SerdeError};
+use serde::de::value::{MapDeserializer, SeqDeserializer, StringDeserializer};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, EnumAccess, Error, IntoDeserializer, Unexpected,
+    VariantAccess, Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserializer};
+use std::collections::HashMap;
+
+/// Converts an `Argument` to any `DeserializeOwned` type, the inverse of `to_argument`.
+///
+/// Integers and floats convert between sizes as long as the value fits, an `Object` or `Definition::One` can be read
+/// as a struct or map, and an `Argument::Error` fails with its message.
+pub fn from_argument<T: DeserializeOwned>(argument: Argument) -> Result<T, SerdeError> {
+    T::deserialize(argument)
+}
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Argument {
+    type Deserializer = Argument;
+
+    fn into_deserializer(self) -> Argument {
+        self
+    }
+}
+
+fn visit_object<'de, V: Visitor<'de>>(
+    object: HashMap<String, Argument>,
+    visitor: V,
+) -> Result<V::Value, SerdeError> {
+    let mut map = MapDeserializer::new(object.into_iter().map(|(k, v)| (Key(k), v)));
+    let value = visitor.visit_map(&mut map)?;
+    map.end()?;
+    Ok(value)
+}
+
+fn visit_list<'de, V: Visitor<'de>>(
+    list: Vec<Argument>,
+    visitor: V,
+) -> Result<V::Value, SerdeError> {
+    let mut seq = SeqDeserializer::new(list.into_iter());
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+impl<'de> Deserializer<'de> for Argument {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self {
+            Argument::None => visitor.visit_unit(),
+            Argument::Int(i) => visitor.visit_i32(i),
+            Argument::Long(l) => visitor.visit_i64(l),
+            Argument::Float(f) => visitor.visit_f32(f),
+            Argument::Double(d) => visitor.visit_f64(d),
+            Argument::Bool(b) => visitor.visit_bool(b),
+            Argument::String(s) => visitor.visit_string(s),
+            Argument::Bytes(b) => visitor.visit_byte_buf(b),
+            Argument::DateTime(d) => visitor.visit_string(d.to_rfc3339()),
+            Argument::Decimal(d) => visitor.visit_string(d.to_string()),
+            Argument::File(f) => visitor.visit_string(f.file.to_string_lossy().to_string()),
+            Argument::Object(o) => visit_object(o, visitor),
+            Argument::List(l) => visit_list(l, visitor),
+            Argument::FunctionCall(fc) => {
+                let mut object = HashMap::with_capacity(3);
+                object.insert("name".to_string(), Argument::String(fc.name));
+                object.insert("args".to_string(), Argument::List(fc.args));
+                object.insert(
+                    "definition".to_string(),
+                    Argument::Definition(fc.definition),
+                );
+                visit_object(object, visitor)
+            }
+            Argument::Definition(Definition::None) => visitor.visit_unit(),
+            Argument::Definition(Definition::One(o)) => visit_object(o, visitor),
+            Argument::Definition(Definition::Many(l)) => visit_list(l, visitor),
+            Argument::Error(e) => Err(SerdeError::new(e.to_string())),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self {
+            Argument::None | Argument::Definition(Definition::None) => visitor.visit_none(),
+            a => visitor.visit_some(a),
+        }
+    }
+
+    /// `Bytes` are a list of `u8` so they can be read into a `Vec<u8>`
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self {
+            Argument::Bytes(b) => visit_list(
+                b.into_iter().map(|b| Argument::Int(b.into())).collect(),
+                visitor,
+            ),
+            a => a.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// A `String` is a unit variant, an `Object` with one key is the variant with that name
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self {
+            Argument::String(s) => visitor.visit_enum(Key(s)),
+            Argument::Object(o) if o.len() == 1 => {
+                let (variant, value) = o.into_iter().next().expect("length checked above");
+                visitor.visit_enum(Variant { variant, value })
+            }
+            a => Err(SerdeError::new(format!(
+                "expected String or Object with one key for enum {}, found {}",
+                name,
+                a.variant_name()
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Variant {
+    variant: String,
+    value: Argument,
+}
+
+impl<'de> EnumAccess<'de> for Variant {
+    type Error = SerdeError;
+    type Variant = Argument;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Argument), SerdeError> {
+        let variant = seed.deserialize(Key(self.variant))?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Argument {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        match self {
+            Argument::None => Ok(()),
+            a => Err(SerdeError::new(format!(
+                "expected None for unit variant, found {}",
+                a.variant_name()
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        self.deserialize_any(visitor)
+    }
+}
+
+/// `Object` key, parsed when a number or bool is expected so maps like `HashMap<u32, T>` work.
+struct Key(String);
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Key {
+    type Deserializer = Key;
+
+    fn into_deserializer(self) -> Key {
+        self
+    }
+}
+
+macro_rules! parse_key {
+    ($($method:ident => $visit:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                match self.0.parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => Err(SerdeError::invalid_value(Unexpected::Str(&self.0), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Key {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_string(self.0)
+    }
+
+    parse_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_enum(self)
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> EnumAccess<'de> for Key {
+    type Error = SerdeError;
+    type Variant = Argument;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Argument), SerdeError> {
+        let deserializer: StringDeserializer<SerdeError> = self.0.into_deserializer();
+        let variant = seed.deserialize(deserializer)?;
+        Ok((variant, Argument::None))
+    }
+}
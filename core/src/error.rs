@@ -0,0 +1,209 @@
+use crate::{Argument, ArithmeticError, ConversionError, PathError, SerdeError, MAX_CALL_DEPTH};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// A function failed while running, the default
+    #[default]
+    Runtime,
+    /// No module defines the function
+    NotFound,
+    /// Arguments or definition don't match what the function expects
+    InvalidArgument,
+    /// Module setup, loading sources or `initialize`, failed
+    Initialization,
+    Io,
+    Parse,
+    /// A module tried something its permissions don't allow
+    PermissionDenied,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            ErrorKind::Runtime => "runtime",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::InvalidArgument => "invalid_argument",
+            ErrorKind::Initialization => "initialization",
+            ErrorKind::Io => "io",
+            ErrorKind::Parse => "parse",
+            ErrorKind::PermissionDenied => "permission_denied",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+/// Location of the call that failed, `line` and `column` are 1-based.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct SourceSpan {
+    pub file: String,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for SourceSpan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// Error passed between modules and the runtime, in `RuntimeStatus::Err` and `Argument::Error`.
+///
+/// `module` and `function` are where it happened, `cause` is the error that led to this one, and `data` holds any
+/// extra values a module wants to return with it. `Display` leaves out the causes, they're returned by `source`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RigzError {
+    pub kind: ErrorKind,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cause: Option<Box<RigzError>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub data: HashMap<String, Argument>,
+}
+
+impl RigzError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        RigzError {
+            kind,
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn runtime(message: impl Into<String>) -> Self {
+        RigzError::new(ErrorKind::Runtime, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        RigzError::new(ErrorKind::NotFound, message)
+    }
+
+    /// Calling `name` would nest calls deeper than `MAX_CALL_DEPTH`
+    pub fn call_depth_exceeded(name: &str) -> Self {
+        RigzError::runtime(format!(
+            "Call depth exceeded {} calling {}",
+            MAX_CALL_DEPTH, name
+        ))
+        .with_function(name)
+    }
+
+    /// Sets the module unless one is already set, the innermost location wins.
+    pub fn with_module(mut self, module: impl Into<String>) -> Self {
+        self.module.get_or_insert_with(|| module.into());
+        self
+    }
+
+    /// Sets the function unless one is already set, the innermost location wins.
+    pub fn with_function(mut self, function: impl Into<String>) -> Self {
+        self.function.get_or_insert_with(|| function.into());
+        self
+    }
+
+    /// Sets the span unless one is already set, the innermost location wins.
+    pub fn with_span(mut self, span: SourceSpan) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: RigzError) -> Self {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    pub fn with_data(mut self, key: impl Into<String>, value: Argument) -> Self {
+        self.data.insert(key.into(), value);
+        self
+    }
+
+    /// This error followed by each cause, outermost first
+    pub fn chain(&self) -> impl Iterator<Item = &RigzError> {
+        std::iter::successors(Some(self), |e| e.cause.as_deref())
+    }
+
+    /// `module.function`, either part if only one is set
+    pub fn location(&self) -> Option<String> {
+        match (&self.module, &self.function) {
+            (Some(m), Some(f)) => Some(format!("{}.{}", m, f)),
+            (Some(m), None) => Some(m.clone()),
+            (None, Some(f)) => Some(f.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+impl Display for RigzError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(location) = self.location() {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RigzError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.cause
+            .as_deref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<String> for RigzError {
+    fn from(value: String) -> Self {
+        RigzError::runtime(value)
+    }
+}
+
+impl From<&str> for RigzError {
+    fn from(value: &str) -> Self {
+        RigzError::runtime(value)
+    }
+}
+
+impl From<ConversionError> for RigzError {
+    fn from(value: ConversionError) -> Self {
+        RigzError::new(ErrorKind::InvalidArgument, value.to_string())
+    }
+}
+
+/// `Unsupported` operands are an `InvalidArgument`, overflow and division by zero a `Runtime` error
+impl From<ArithmeticError> for RigzError {
+    fn from(value: ArithmeticError) -> Self {
+        let kind = match value {
+            ArithmeticError::Unsupported { .. } => ErrorKind::InvalidArgument,
+            _ => ErrorKind::Runtime,
+        };
+        RigzError::new(kind, value.to_string())
+    }
+}
+
+impl From<PathError> for RigzError {
+    fn from(value: PathError) -> Self {
+        RigzError::new(ErrorKind::InvalidArgument, value.to_string())
+    }
+}
+
+impl From<SerdeError> for RigzError {
+    fn from(value: SerdeError) -> Self {
+        RigzError::new(ErrorKind::InvalidArgument, value.message)
+    }
+}
+
+impl From<std::io::Error> for RigzError {
+    fn from(value: std::io::Error) -> Self {
+        RigzError::new(ErrorKind::Io, value.to_string())
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+
+/// How a `RigzFile` is opened, `Write` truncates the file and `Append` writes to its end, both create it if missing.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FileMode {
+    #[default]
+    Read,
+    Write,
+    Append,
+}
+
+impl Display for FileMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            FileMode::Read => "read",
+            FileMode::Write => "write",
+            FileMode::Append => "append",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+/// Path to a file and the mode to open it in, the file is only opened on first read or write.
+///
+/// `Read` and `Write` go through a handle that's kept open, `chunks` and `hash` open their own so they always start
+/// at the beginning. Clones share the path and mode but not the handle. A `Write` file and its clones truncate it
+/// once, whichever opens it first, the others append. Serializes as its path and mode, without touching the
+/// filesystem.
+#[derive(Debug)]
+pub struct RigzFile {
+    pub file: PathBuf,
+    mode: FileMode,
+    handle: Option<File>,
+    truncated: Arc<AtomicBool>,
+}
+
+impl RigzFile {
+    /// Opened for reading
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        RigzFile {
+            file: file.into(),
+            mode: FileMode::Read,
+            handle: None,
+            truncated: Arc::default(),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: FileMode) -> Self {
+        if mode != self.mode {
+            self.handle = None;
+            self.truncated = Arc::default();
+        }
+        self.mode = mode;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.file
+    }
+
+    pub fn mode(&self) -> FileMode {
+        self.mode
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// The open handle, opening the file first if needed.
+    pub fn handle(&mut self) -> Result<&mut File> {
+        if self.handle.is_none() {
+            self.handle = Some(self.open()?);
+        }
+        Ok(self.handle.as_mut().expect("handle opened above"))
+    }
+
+    /// Takes the handle, opening the file first if needed.
+    pub fn file(mut self) -> Result<File> {
+        match self.handle.take() {
+            Some(file) => Ok(file),
+            None => self.open(),
+        }
+    }
+
+    fn open(&self) -> Result<File> {
+        let mut options = OpenOptions::new();
+        match self.mode {
+            FileMode::Read => options.read(true),
+            FileMode::Write if !self.truncated.swap(true, AtomicOrdering::SeqCst) => {
+                options.write(true).create(true).truncate(true)
+            }
+            FileMode::Write | FileMode::Append => options.append(true).create(true),
+        };
+        options.open(&self.file).map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!("failed to open {} for {}: {}", self, self.mode, e),
+            )
+        })
+    }
+
+    pub fn metadata(&self) -> Result<FileMetadata> {
+        let metadata = std::fs::metadata(&self.file)?;
+        Ok(FileMetadata {
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            readonly: metadata.permissions().readonly(),
+        })
+    }
+
+    /// Reads the file in chunks of up to `chunk_size` bytes, independent of the handle used by `Read`.
+    pub fn chunks(&self, chunk_size: usize) -> Result<Chunks> {
+        if chunk_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "chunk size must be greater than 0",
+            ));
+        }
+        Ok(Chunks {
+            file: File::open(&self.file)?,
+            chunk_size,
+            done: false,
+        })
+    }
+
+    /// SHA-256 of the contents as lowercase hex, read in chunks.
+    pub fn hash(&self) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for chunk in self.chunks(64 * 1024)? {
+            hasher.update(chunk?);
+        }
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+}
+
+impl Display for RigzFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.to_str().unwrap_or("<invalid-utf>"))
+    }
+}
+
+impl Clone for RigzFile {
+    fn clone(&self) -> Self {
+        RigzFile {
+            file: self.file.clone(),
+            mode: self.mode,
+            handle: None,
+            truncated: self.truncated.clone(),
+        }
+    }
+}
+
+impl PartialEq for RigzFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.file == other.file && self.mode == other.mode
+    }
+}
+
+impl Eq for RigzFile {}
+
+impl Hash for RigzFile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.file.hash(state);
+        self.mode.hash(state);
+    }
+}
+
+impl Ord for RigzFile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.file
+            .cmp(&other.file)
+            .then_with(|| self.mode.cmp(&other.mode))
+    }
+}
+
+impl PartialOrd for RigzFile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Read for RigzFile {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.mode != FileMode::Read {
+            return Err(not_opened_for(self, "reading"));
+        }
+        self.handle()?.read(buf)
+    }
+}
+
+impl Write for RigzFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.mode == FileMode::Read {
+            return Err(not_opened_for(self, "writing"));
+        }
+        self.handle()?.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.handle {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn not_opened_for(file: &RigzFile, action: &str) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!("{} is opened for {}, not {}", file, file.mode, action),
+    )
+}
+
+/// Iterator over the contents of a file, returned by `RigzFile::chunks`. Stops after the first error.
+pub struct Chunks {
+    file: File,
+    chunk_size: usize,
+    done: bool,
+}
+
+impl Iterator for Chunks {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        match (&mut self.file)
+            .take(self.chunk_size as u64)
+            .read_to_end(&mut chunk)
+        {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(_) => Some(Ok(chunk)),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct FileRepr<P> {
+    file: P,
+    #[serde(default)]
+    mode: FileMode,
+}
+
+impl Serialize for RigzFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        FileRepr {
+            file: &self.file,
+            mode: self.mode,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RigzFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let repr = FileRepr::<PathBuf>::deserialize(deserializer)?;
+        Ok(RigzFile::new(repr.file).with_mode(repr.mode))
+    }
+}
@@ -1,11 +1,57 @@
+// lets `#[derive(FromArgument, IntoArgument)]` refer to `::rigz_core` in this crate's tests
+#[cfg(test)]
+extern crate self as rigz_core;
+
+mod arithmetic;
+mod async_module;
+mod bytes;
+mod cdylib;
+mod compare;
+mod context;
+mod convert;
+mod de;
+mod error;
+mod file;
+mod path;
+mod permissions;
+mod schema;
+mod ser;
+mod signature;
+
+pub use crate::arithmetic::{ArithmeticError, Operation};
+pub use crate::async_module::{AsyncModule, BoxFuture};
+#[cfg(feature = "cdylib")]
+#[doc(hidden)]
+pub use crate::cdylib::export as cdylib_export;
+pub use crate::cdylib::{CallRequest, HookRequest, ABI_VERSION};
+pub use crate::context::{CallContext, Invoker, Scope, MAX_CALL_DEPTH};
+pub use crate::convert::{ConversionError, FromArgument, IntoArgument, SerdeError};
+pub use crate::de::from_argument;
+pub use crate::error::{ErrorKind, RigzError, SourceSpan};
+pub use crate::file::{Chunks, FileMetadata, FileMode, RigzFile};
+pub use crate::path::{ArgumentPath, PathError, PathSegment};
+pub use crate::permissions::{
+    Capability, Denial, ModulePermissions, PermissionDenied, Permissions,
+};
+pub use crate::schema::{DefinitionSchema, FieldSchema};
+pub use crate::ser::to_argument;
+pub use crate::signature::{ArgumentType, FunctionSignature, Parameter};
+pub use chrono::{DateTime, FixedOffset, Utc};
+#[cfg(feature = "derive")]
+pub use rigz_macros::{rigz_function, rigz_module, FromArgument, IntoArgument};
+pub use rust_decimal::Decimal;
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Result;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
 use std::path::PathBuf;
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+/// Values passed to and returned from module functions.
+///
+/// `Bytes` serialize as a base64 string in human readable formats like JSON, `DateTime` as RFC 3339 and `Decimal` as a
+/// string, so no precision is lost. Numbers compare by value across variants, see `Ord`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Argument {
     None,
     Int(i32),
@@ -14,57 +60,18 @@ pub enum Argument {
     Double(f64),
     Bool(bool),
     String(String),
+    Bytes(#[serde(with = "bytes")] Vec<u8>),
+    DateTime(DateTime<FixedOffset>),
+    Decimal(Decimal),
     File(RigzFile),
     Object(HashMap<String, Argument>),
     List(Vec<Argument>),
     FunctionCall(FunctionCall),
     Definition(Definition),
-    Error(String),
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct RigzFile {
-    pub file: PathBuf,
-    #[serde(skip_serializing, skip_deserializing)]
-    internal: Option<File>,
-}
-
-impl RigzFile {
-    pub fn file(self) -> Option<File> {
-        self.internal
-    }
-}
-
-impl Display for RigzFile {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{}", self.file.to_str().unwrap_or("<invalid-utf>"))
-    }
-}
-
-impl Clone for RigzFile {
-    fn clone(&self) -> Self {
-        let file = self.file.clone();
-        if file.exists() {
-            RigzFile {
-                internal: Some(File::open(&file).expect("Failed to open file")),
-                file,
-            }
-        } else {
-            RigzFile {
-                file,
-                internal: None,
-            }
-        }
-    }
+    Error(RigzError),
 }
 
-impl PartialEq for RigzFile {
-    fn eq(&self, other: &Self) -> bool {
-        self.file == other.file
-    }
-}
-
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[repr(C)]
 pub enum Definition {
     None,
@@ -72,6 +79,29 @@ pub enum Definition {
     Many(Vec<Argument>),
 }
 
+impl Argument {
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Argument::None => "None",
+            Argument::Int(_) => "Int",
+            Argument::Long(_) => "Long",
+            Argument::Float(_) => "Float",
+            Argument::Double(_) => "Double",
+            Argument::Bool(_) => "Bool",
+            Argument::String(_) => "String",
+            Argument::Bytes(_) => "Bytes",
+            Argument::DateTime(_) => "DateTime",
+            Argument::Decimal(_) => "Decimal",
+            Argument::File(_) => "File",
+            Argument::Object(_) => "Object",
+            Argument::List(_) => "List",
+            Argument::FunctionCall(_) => "FunctionCall",
+            Argument::Definition(_) => "Definition",
+            Argument::Error(_) => "Error",
+        }
+    }
+}
+
 impl Display for Argument {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         match self {
@@ -82,28 +112,42 @@ impl Display for Argument {
             Argument::Double(d) => write!(f, "{}", d),
             Argument::Bool(b) => write!(f, "{}", b),
             Argument::String(s) => write!(f, "{}", s),
+            Argument::Bytes(b) => {
+                for byte in b {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Argument::DateTime(d) => write!(f, "{}", d.to_rfc3339()),
+            Argument::Decimal(d) => write!(f, "{}", d),
             Argument::Object(o) => write!(f, "{:?}", o),
             Argument::List(l) => write!(f, "{:?}", l),
             Argument::FunctionCall(fc) => write!(f, "{:?}", fc),
             Argument::Definition(d) => write!(f, "{:?}", d),
-            Argument::Error(e) => write!(f, "Error: {}", e),
+            Argument::Error(e) => {
+                write!(f, "Error: {}", e)?;
+                for cause in e.chain().skip(1) {
+                    write!(f, ", caused by: {}", cause)?;
+                }
+                Ok(())
+            }
             Argument::File(file) => write!(f, "{}", file),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
 pub struct FunctionCall {
     pub name: String,
     pub args: Vec<Argument>,
     pub definition: Definition,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum RuntimeStatus<T> {
     Ok(T),
     NotFound,
-    Err(String),
+    Err(RigzError),
 }
 
 pub trait Module {
@@ -111,28 +155,976 @@ pub trait Module {
 
     fn root(&self) -> PathBuf;
 
+    /// `context` calls other modules' functions and reads the scope, file and span of the call.
     fn function_call(
         &self,
         name: &str,
         arguments: Vec<Argument>,
         definition: Definition,
         prior_result: Argument,
+        context: &CallContext,
     ) -> RuntimeStatus<Argument>;
 
     fn initialize(&self, args: InitializationArgs) -> RuntimeStatus<()> {
         if args.all_errors_fatal {
-            RuntimeStatus::Err("Initialization Function Not Found".into())
+            RuntimeStatus::Err(RigzError::new(
+                ErrorKind::Initialization,
+                "Initialization Function Not Found",
+            ))
         } else {
             RuntimeStatus::NotFound
         }
     }
+
+    /// Functions this module defines, empty if the module can't list them. May be incomplete before `initialize`.
+    fn functions(&self) -> Vec<FunctionSignature> {
+        Vec::new()
+    }
+
+    /// Called before the first call in `file`, on the thread that runs it.
+    fn on_file_start(&self, _file: &str) -> RuntimeStatus<()> {
+        RuntimeStatus::Ok(())
+    }
+
+    /// Called after `file` runs, even if it failed, `result` is its last value or the error it failed with.
+    fn on_file_end(&self, _file: &str, _result: &Argument) -> RuntimeStatus<()> {
+        RuntimeStatus::Ok(())
+    }
+
+    /// Called once after every file has run, to flush output and close connections.
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        RuntimeStatus::Ok(())
+    }
+
+    /// Reloads sources and config without restarting the runtime, `args` are the ones `initialize` would get.
+    fn reload(&self, _args: InitializationArgs) -> RuntimeStatus<()> {
+        RuntimeStatus::Ok(())
+    }
 }
 
+/// `Module` that can be shared between threads, any `Module + Send + Sync` is one.
+pub trait SyncModule: Module + Send + Sync {}
+
+impl<T: Module + Send + Sync + ?Sized> SyncModule for T {}
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
 pub struct InitializationArgs {
     pub all_errors_fatal: bool,
     pub ignore_symbol_not_found: bool,
     pub prefer_none_over_prior_result: bool,
     pub require_aliases: bool,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn primitives_round_trip() {
+        assert_eq!(Argument::Int(3).extract::<u8>(), Ok(3));
+        assert_eq!(Argument::Long(3).extract::<i32>(), Ok(3));
+        assert_eq!(Argument::Int(2).extract::<f64>(), Ok(2.0));
+        assert_eq!(Argument::None.extract::<Option<String>>(), Ok(None));
+        assert_eq!(
+            Argument::String("/tmp".into()).extract::<PathBuf>(),
+            Ok(PathBuf::from("/tmp"))
+        );
+        assert_eq!(
+            (1, "a", true).into_argument(),
+            Argument::List(vec![
+                Argument::Int(1),
+                Argument::String("a".into()),
+                Argument::Bool(true)
+            ])
+        );
+        let map: HashMap<String, Vec<i64>> = HashMap::from([("a".to_string(), vec![1, 2])]);
+        assert_eq!(map.clone().into_argument().extract(), Ok(map));
+    }
+
+    #[test]
+    fn conversion_errors_name_variants() {
+        let error = Argument::String("1".into()).extract::<i32>().unwrap_err();
+        assert_eq!(error.to_string(), "expected Int or Long, found String");
+
+        let error = Argument::Int(300).extract::<u8>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected Int in range of u8, found Int(300)"
+        );
+
+        let nested = Argument::Object(HashMap::from([(
+            "ports".to_string(),
+            Argument::List(vec![Argument::Int(80), Argument::Bool(true)]),
+        )]));
+        let error = nested.extract::<HashMap<String, Vec<i32>>>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected Int or Long, found Bool at `ports.1`"
+        );
+
+        let error = Argument::List(vec![Argument::None])
+            .extract::<(i32, i32)>()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected List of 2 elements, found List of 1 elements"
+        );
+    }
+
+    #[derive(Debug, PartialEq, FromArgument, IntoArgument)]
+    struct Retry {
+        attempts: i32,
+        delay: Option<f64>,
+        mode: Mode,
+    }
+
+    #[derive(Debug, PartialEq, FromArgument, IntoArgument)]
+    enum Mode {
+        Fixed,
+        Backoff { factor: f64 },
+        Jitter(i32, i32),
+    }
+
+    #[derive(Debug, PartialEq, FromArgument, IntoArgument)]
+    struct Name(String);
+
+    #[test]
+    fn derive_round_trip() {
+        let retry = Retry {
+            attempts: 3,
+            delay: None,
+            mode: Mode::Backoff { factor: 1.5 },
+        };
+        let argument = retry.into_argument();
+        match &argument {
+            Argument::Object(o) => {
+                assert_eq!(o.get("attempts"), Some(&Argument::Int(3)));
+                assert_eq!(o.get("delay"), Some(&Argument::None));
+            }
+            a => panic!("Unexpected argument: {:?}", a),
+        }
+        assert_eq!(
+            argument.extract(),
+            Ok(Retry {
+                attempts: 3,
+                delay: None,
+                mode: Mode::Backoff { factor: 1.5 },
+            })
+        );
+
+        assert_eq!(
+            Mode::Fixed.into_argument(),
+            Argument::String("Fixed".into())
+        );
+        assert_eq!(
+            Mode::Jitter(1, 2).into_argument().extract(),
+            Ok(Mode::Jitter(1, 2))
+        );
+        assert_eq!(
+            Name("a".into()).into_argument(),
+            Argument::String("a".into())
+        );
+    }
+
+    #[test]
+    fn derive_errors_name_field_and_variant() {
+        let argument = Argument::Object(HashMap::from([
+            ("attempts".to_string(), Argument::Int(1)),
+            ("mode".to_string(), Argument::String("Linear".into())),
+        ]));
+        let error = argument.extract::<Retry>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected one of `Fixed`, `Backoff`, `Jitter` for Mode, found String(\"Linear\") at `mode`"
+        );
+
+        let argument = Argument::Object(HashMap::from([
+            ("attempts".to_string(), Argument::Int(1)),
+            (
+                "mode".to_string(),
+                Argument::Object(HashMap::from([(
+                    "Backoff".to_string(),
+                    Argument::Object(HashMap::new()),
+                )])),
+            ),
+        ]));
+        let error = argument.extract::<Retry>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "expected Double or Float, found None at `mode.Backoff.factor`"
+        );
+
+        let error = Argument::List(vec![]).extract::<Retry>().unwrap_err();
+        assert_eq!(error.to_string(), "expected Object for Retry, found List");
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Deployment {
+        name: String,
+        replicas: u32,
+        ports: Vec<u16>,
+        labels: HashMap<String, String>,
+        limits: HashMap<u8, f64>,
+        region: Option<String>,
+        strategy: Strategy,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    enum Strategy {
+        Recreate,
+        Rolling { surge: i32 },
+        Canary(u8),
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let deployment = Deployment {
+            name: "web".into(),
+            replicas: 3,
+            ports: vec![80, 443],
+            labels: HashMap::from([("app".to_string(), "web".to_string())]),
+            limits: HashMap::from([(1, 0.5)]),
+            region: None,
+            strategy: Strategy::Rolling { surge: 1 },
+        };
+        let argument = to_argument(&deployment).unwrap();
+        match &argument {
+            Argument::Object(o) => {
+                assert_eq!(o.get("replicas"), Some(&Argument::Long(3)));
+                assert_eq!(
+                    o.get("ports"),
+                    Some(&Argument::List(vec![Argument::Int(80), Argument::Int(443)]))
+                );
+                assert_eq!(o.get("region"), Some(&Argument::None));
+            }
+            a => panic!("Unexpected argument: {:?}", a),
+        }
+        assert_eq!(from_argument::<Deployment>(argument), Ok(deployment));
+
+        for strategy in [Strategy::Recreate, Strategy::Canary(10)] {
+            let argument = to_argument(&strategy).unwrap();
+            assert_eq!(from_argument::<Strategy>(argument), Ok(strategy));
+        }
+        assert_eq!(
+            to_argument(&Strategy::Recreate),
+            Ok(Argument::String("Recreate".into()))
+        );
+    }
+
+    #[test]
+    fn serde_reads_module_arguments() {
+        // Lua returns every integer as a Long, and definitions arrive as `Definition::One`
+        let argument = Argument::Definition(Definition::One(HashMap::from([
+            ("name".to_string(), Argument::String("api".into())),
+            ("replicas".to_string(), Argument::Long(2)),
+            (
+                "ports".to_string(),
+                Argument::List(vec![Argument::Long(8080)]),
+            ),
+            ("labels".to_string(), Argument::Object(HashMap::new())),
+            (
+                "limits".to_string(),
+                Argument::Object(HashMap::from([("2".to_string(), Argument::Int(1))])),
+            ),
+            ("region".to_string(), Argument::String("us".into())),
+            (
+                "strategy".to_string(),
+                Argument::Object(HashMap::from([("Canary".to_string(), Argument::Long(5))])),
+            ),
+        ])));
+        let deployment: Deployment = from_argument(argument).unwrap();
+        assert_eq!(deployment.replicas, 2);
+        assert_eq!(deployment.ports, vec![8080]);
+        assert_eq!(deployment.limits.get(&2), Some(&1.0));
+        assert_eq!(deployment.region, Some("us".to_string()));
+        assert_eq!(deployment.strategy, Strategy::Canary(5));
+
+        let error = from_argument::<u8>(Argument::Long(300)).unwrap_err();
+        assert!(error.message.contains("300"), "{}", error);
+        let error = from_argument::<String>(Argument::Error("lookup failed".into())).unwrap_err();
+        assert_eq!(error.message, "lookup failed");
+    }
+
+    #[test]
+    fn rigz_error_chain_and_json() {
+        let error = RigzError::runtime("Lua Execution Failed")
+            .with_module("lua")
+            .with_function("fail")
+            .with_module("ignored")
+            .with_cause(RigzError::runtime("boom").with_data("code", Argument::Int(2)));
+        assert_eq!(error.to_string(), "lua.fail: Lua Execution Failed");
+        assert_eq!(
+            error
+                .chain()
+                .map(|e| e.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Lua Execution Failed", "boom"]
+        );
+        assert_eq!(
+            Argument::Error(error.clone()).to_string(),
+            "Error: lua.fail: Lua Execution Failed, caused by: boom"
+        );
+
+        let json = serde_json::to_value(Argument::Error(error.clone())).unwrap();
+        assert_eq!(json["Error"]["kind"], "runtime");
+        assert_eq!(json["Error"]["cause"]["data"]["code"]["Int"], 2);
+        assert!(json["Error"].get("span").is_none());
+        let argument: Argument = serde_json::from_value(json).unwrap();
+        assert_eq!(argument, Argument::Error(error));
+    }
+
+    #[test]
+    fn bytes_date_times_and_decimals() {
+        let bytes = Argument::Bytes(vec![0xde, 0xad, 0xbe, 0xef]);
+        let date = DateTime::parse_from_rfc3339("2024-05-01T12:00:00+02:00").unwrap();
+        let price: Decimal = "19.99".parse().unwrap();
+
+        assert_eq!(bytes.to_string(), "deadbeef");
+        assert_eq!(
+            Argument::DateTime(date).to_string(),
+            "2024-05-01T12:00:00+02:00"
+        );
+        assert_eq!(Argument::Decimal(price).to_string(), "19.99");
+
+        let json = serde_json::to_value(vec![
+            bytes.clone(),
+            Argument::DateTime(date),
+            Argument::Decimal(price),
+        ])
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                { "Bytes": "3q2+7w==" },
+                { "DateTime": "2024-05-01T12:00:00+02:00" },
+                { "Decimal": "19.99" }
+            ])
+        );
+        let arguments: Vec<Argument> = serde_json::from_value(json).unwrap();
+        assert_eq!(arguments[0], bytes);
+        let json = r#"[{ "Bytes": "3q2+7w==" }, { "Bytes": [222, 173, 190, 239] }]"#;
+        let arguments: Vec<Argument> = serde_json::from_str(json).unwrap();
+        assert_eq!(arguments, vec![bytes.clone(), bytes.clone()]);
+        assert!(serde_json::from_str::<Argument>(r#"{ "Bytes": [256] }"#).is_err());
+
+        // same instant in another offset, same value at another scale
+        let utc = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap();
+        assert_eq!(Argument::DateTime(date), Argument::DateTime(utc));
+        let later = DateTime::parse_from_rfc3339("2024-05-01T10:00:01Z").unwrap();
+        assert!(Argument::DateTime(date) < Argument::DateTime(later));
+        assert_eq!(
+            Argument::Decimal("1.0".parse().unwrap()),
+            Argument::Decimal("1.00".parse().unwrap())
+        );
+        assert!(Argument::Decimal(price) > Argument::Decimal(Decimal::from(19)));
+        assert!(bytes < Argument::Bytes(vec![0xff]));
+        assert!(bytes > Argument::Decimal(price));
+
+        assert_eq!(
+            bytes.clone().extract::<Vec<u8>>(),
+            Ok(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(
+            from_argument::<Vec<u8>>(bytes),
+            Ok(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(
+            Argument::String("2024-05-01T10:00:00Z".into()).extract::<DateTime<Utc>>(),
+            Ok(utc.to_utc())
+        );
+        assert_eq!(Argument::Long(3).extract::<Decimal>(), Ok(Decimal::from(3)));
+        assert_eq!(
+            Argument::Double(0.1)
+                .extract::<Decimal>()
+                .unwrap_err()
+                .to_string(),
+            "expected Decimal, Int, Long or String, found Double"
+        );
+    }
+
+    #[test]
+    fn rigz_file_is_lazy_and_streams_chunks() {
+        use std::io::{Read, Write};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rigz_file.txt");
+
+        let mut file = RigzFile::new(&path).with_mode(FileMode::Write);
+        // clones keep the mode, only the first to open the file truncates it
+        std::fs::write(&path, "stale contents").unwrap();
+        let mut clone = file.clone();
+        assert_eq!(clone, file);
+        assert_eq!(clone.mode(), FileMode::Write);
+        assert!(!file.is_open());
+        file.write_all(b"hello ").unwrap();
+        assert!(file.is_open());
+        clone.write_all(b"world").unwrap();
+        drop((file, clone));
+
+        let mut file = RigzFile::new(&path);
+        assert_eq!(file.metadata().unwrap().size, 11);
+        assert_eq!(
+            file.hash().unwrap(),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
+        let chunks: Vec<Vec<u8>> = file.chunks(4).unwrap().map(|c| c.unwrap()).collect();
+        assert_eq!(
+            chunks,
+            vec![b"hell".to_vec(), b"o wo".to_vec(), b"rld".to_vec()]
+        );
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "hello world");
+        let error = file.write(b"!").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+
+        let json = serde_json::to_value(Argument::File(file.clone())).unwrap();
+        assert_eq!(json["File"]["mode"], "read");
+        assert!(json["File"].get("metadata").is_none());
+        let argument: Argument = serde_json::from_value(json).unwrap();
+        assert_eq!(argument, Argument::File(file));
+
+        // missing files only fail when used
+        std::fs::remove_file(&path).unwrap();
+        let missing = RigzFile::new(&path);
+        let mut clone = missing.clone();
+        assert!(clone.handle().is_err());
+        assert!(missing.chunks(4).is_err());
+    }
+
+    #[test]
+    fn numbers_compare_and_hash_across_variants() {
+        use std::collections::HashSet;
+
+        let one = [
+            Argument::Int(1),
+            Argument::Long(1),
+            Argument::Float(1.0),
+            Argument::Double(1.0),
+            Argument::Decimal("1.00".parse().unwrap()),
+        ];
+        for a in &one {
+            for b in &one {
+                assert_eq!(a, b);
+            }
+        }
+        assert_eq!(one.iter().collect::<HashSet<_>>().len(), 1);
+        assert_eq!(
+            Argument::Double(0.5),
+            Argument::Decimal("0.5".parse().unwrap())
+        );
+        assert_ne!(
+            Argument::Double(0.1),
+            Argument::Decimal("0.1".parse().unwrap())
+        );
+        assert_ne!(Argument::Long(i64::MAX), Argument::Double(i64::MAX as f64));
+        assert_eq!(Argument::Double(f64::NAN), Argument::Double(f64::NAN));
+        assert_ne!(Argument::Bool(true), Argument::Int(1));
+
+        let mut arguments = [
+            Argument::String("a".into()),
+            Argument::Double(f64::NAN),
+            Argument::Decimal("2.5".parse().unwrap()),
+            Argument::Int(3),
+            Argument::None,
+            Argument::Double(-0.5),
+            Argument::Bool(false),
+        ];
+        arguments.sort();
+        assert_eq!(
+            arguments.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+            vec!["none", "false", "-0.5", "2.5", "3", "NaN", "a"]
+        );
+
+        let key = |k: &str, v: Argument| Argument::Object(HashMap::from([(k.to_string(), v)]));
+        let set = HashSet::from([key("a", Argument::Int(1)), key("a", Argument::Long(1))]);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn number_equality_is_transitive() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |a: &Argument| {
+            let mut hasher = DefaultHasher::new();
+            a.hash(&mut hasher);
+            hasher.finish()
+        };
+        let decimal = |s: &str| Argument::Decimal(s.parse().unwrap());
+        assert_ne!(Argument::Double(1e-30), decimal("0"));
+        assert!(Argument::Double(1e-30) > decimal("0"));
+        assert!(Argument::Double(1e-30) < decimal("0.0000000000000000000000000001"));
+        assert!(Argument::Double(-1e-30) < decimal("-0"));
+        assert!(Argument::Double(0.1) > decimal("0.1"));
+        assert!(Argument::Double(0.1) > decimal("0.1000000000000000055511151231"));
+        assert!(Argument::Double(0.1) < decimal("0.1000000000000000055511151232"));
+        assert_eq!(Argument::Double(-0.0), decimal("0"));
+        assert_eq!(
+            Argument::Double(2.0f64.powi(-28)),
+            decimal("0.0000000037252902984619140625")
+        );
+        assert!(Argument::Double(2.0f64.powi(96)) > decimal("79228162514264337593543950335"));
+        assert!(Argument::Double(f64::INFINITY) > decimal("79228162514264337593543950335"));
+
+        let numbers = [
+            Argument::Int(0),
+            Argument::Int(-3),
+            Argument::Long(1 << 53),
+            Argument::Long((1 << 53) + 1),
+            Argument::Double(0.0),
+            Argument::Double(1e-30),
+            Argument::Double(-1e-30),
+            Argument::Double(0.1),
+            Argument::Double(-3.0),
+            Argument::Double(9_007_199_254_740_992.0),
+            Argument::Double(f64::MIN_POSITIVE),
+            decimal("0"),
+            decimal("-0"),
+            decimal("0.1"),
+            decimal("-3.000"),
+            decimal("9007199254740992"),
+            decimal("9007199254740993"),
+            decimal("0.0000000000000000000000000001"),
+        ];
+        for a in &numbers {
+            for b in &numbers {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse(), "{a} {b}");
+                if a == b {
+                    assert_eq!(hash(a), hash(b), "{a} {b}");
+                }
+                for c in &numbers {
+                    if a <= b && b <= c {
+                        assert!(a <= c, "{a} <= {b} <= {c}");
+                    }
+                    if a == b && b == c {
+                        assert_eq!(a, c, "{a} == {b} == {c}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn checked_arithmetic_widens_and_fails_instead_of_wrapping() {
+        let decimal = |d: &str| Argument::Decimal(d.parse().unwrap());
+
+        assert_eq!(
+            Argument::Int(1).checked_add(&Argument::Int(2)),
+            Ok(Argument::Int(3))
+        );
+        assert_eq!(
+            Argument::Int(1).checked_add(&Argument::Long(2)),
+            Ok(Argument::Long(3))
+        );
+        assert_eq!(
+            Argument::Int(1).checked_add(&Argument::Float(0.5)),
+            Ok(Argument::Float(1.5))
+        );
+        // 2^24 + 1 isn't a float, adding 0.5 to it can't round
+        assert_eq!(
+            Argument::Int(16_777_217).checked_add(&Argument::Float(0.5)),
+            Ok(Argument::Double(16_777_217.5))
+        );
+        assert_eq!(
+            Argument::Float(1.0).checked_mul(&Argument::Int(i32::MAX)),
+            Ok(Argument::Double(2_147_483_647.0))
+        );
+        assert_eq!(
+            Argument::Long(1).checked_mul(&Argument::Double(0.5)),
+            Ok(Argument::Double(0.5))
+        );
+        assert_eq!(
+            Argument::Int(7).checked_div(&Argument::Int(2)),
+            Ok(Argument::Int(3))
+        );
+        assert_eq!(
+            decimal("0.1").checked_add(&decimal("0.2")),
+            Ok(decimal("0.3"))
+        );
+        assert_eq!(
+            decimal("19.99").checked_mul(&Argument::Int(3)),
+            Ok(decimal("59.97"))
+        );
+
+        assert_eq!(
+            Argument::Int(i32::MAX).checked_add(&Argument::Int(1)),
+            Err(ArithmeticError::Overflow(Operation::Add))
+        );
+        assert_eq!(
+            Argument::Double(f64::MAX).checked_mul(&Argument::Int(2)),
+            Err(ArithmeticError::Overflow(Operation::Mul))
+        );
+        assert_eq!(
+            Argument::Double(1.0).checked_rem(&Argument::Long(0)),
+            Err(ArithmeticError::DivisionByZero)
+        );
+        let error = decimal("1")
+            .checked_sub(&Argument::Double(0.5))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "cannot subtract Decimal and Double");
+        let error = RigzError::from(
+            Argument::String("1".into())
+                .checked_add(&Argument::Int(1))
+                .unwrap_err(),
+        );
+        assert_eq!(error.kind, ErrorKind::InvalidArgument);
+        assert_eq!(error.message, "cannot add String and Int");
+    }
+
+    #[test]
+    fn permissions_have_to_be_declared_and_granted() {
+        let declared: Permissions = from_argument(Argument::Object(HashMap::from([
+            (
+                "fs_write".to_string(),
+                Argument::List(vec![Argument::String("out".into())]),
+            ),
+            ("process".to_string(), Argument::Bool(true)),
+            (
+                "env".to_string(),
+                Argument::List(vec![Argument::String("*".into())]),
+            ),
+        ])))
+        .unwrap();
+        let granted = Permissions {
+            fs_write: vec![PathBuf::from("./out/../out/reports")],
+            env: vec!["HOME".to_string()],
+            ..Default::default()
+        };
+        let permissions = ModulePermissions::new("deploy", declared, granted);
+
+        assert_eq!(
+            permissions.check(Capability::FsWrite(Path::new("out/reports/a.csv"))),
+            Ok(())
+        );
+        assert_eq!(permissions.check(Capability::Env("HOME")), Ok(()));
+        let denied = permissions
+            .check(Capability::FsWrite(Path::new("out/other.csv")))
+            .unwrap_err();
+        assert_eq!(denied.reason, Denial::NotGranted);
+        let denied = permissions
+            .check(Capability::Net("example.com"))
+            .unwrap_err();
+        assert_eq!(
+            RigzError::from(denied).to_string(),
+            "deploy: permission denied, deploy doesn't declare `net example.com` in its module.rigz"
+        );
+        assert_eq!(
+            permissions.ungranted(),
+            vec!["fs_write out", "process", "env *"]
+        );
+
+        let unknown = from_argument::<Permissions>(Argument::Object(HashMap::from([(
+            "network".to_string(),
+            Argument::Bool(true),
+        )])));
+        assert!(unknown.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn permissions_follow_symlinks_out_of_granted_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let (granted, secret) = (dir.path().join("granted"), dir.path().join("secret"));
+        std::fs::create_dir_all(granted.join("nested")).unwrap();
+        std::fs::write(&secret, "password").unwrap();
+        std::os::unix::fs::symlink(&secret, granted.join("link")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), granted.join("parent")).unwrap();
+        let read = |path: &Path| Permissions {
+            fs_read: vec![path.to_path_buf()],
+            ..Default::default()
+        };
+        let permissions = ModulePermissions::new("reader", read(dir.path()), read(&granted));
+
+        let allowed = [granted.join("nested/a.txt"), granted.join("new/a.txt")];
+        for path in allowed {
+            assert_eq!(permissions.check(Capability::FsRead(&path)), Ok(()));
+        }
+        let denied = [
+            granted.join("link"),
+            granted.join("parent/secret"),
+            granted.join("parent/granted/../secret"),
+            granted.join("parent/new.txt"),
+            granted.join("nested/../../secret"),
+        ];
+        for path in denied {
+            let denied = permissions.check(Capability::FsRead(&path)).unwrap_err();
+            assert_eq!(denied.reason, Denial::NotGranted, "{}", path.display());
+        }
+    }
+
+    struct Greeter {
+        greeting: String,
+    }
+
+    #[rigz_module(name = "greeter")]
+    impl Greeter {
+        /// Greets `name`, with `!` unless given other punctuation
+        #[rigz_function]
+        fn greet(&self, name: String, punctuation: Option<String>) -> String {
+            let punctuation = punctuation.unwrap_or_else(|| "!".to_string());
+            format!("{}, {}{}", self.greeting, name, punctuation)
+        }
+
+        #[rigz_function(name = "retry-count")]
+        fn retry_count(#[definition] retry: Retry) -> i32 {
+            retry.attempts
+        }
+
+        #[rigz_function]
+        fn sum(#[variadic] values: Vec<i64>) -> i64 {
+            values.iter().sum()
+        }
+
+        #[rigz_function]
+        fn shout(
+            #[prior_result] prior: Option<String>,
+        ) -> std::result::Result<String, &'static str> {
+            match prior {
+                Some(s) => Ok(s.to_uppercase()),
+                None => Err("nothing to shout"),
+            }
+        }
+
+        #[rigz_function]
+        fn current_file(context: &CallContext) -> Option<String> {
+            context.file.map(str::to_string)
+        }
+
+        fn shutdown(&self) -> RuntimeStatus<()> {
+            RuntimeStatus::Err(RigzError::runtime("already shut down"))
+        }
+    }
+
+    #[test]
+    fn rigz_module_dispatches_to_functions() {
+        let greeter = Greeter {
+            greeting: "Hello".to_string(),
+        };
+        let scope = Scope::default();
+        let context = CallContext::detached(&scope).with_file("main.rigz");
+        let call = |name: &str, arguments: Vec<Argument>, definition, prior_result| {
+            greeter.function_call(name, arguments, definition, prior_result, &context)
+        };
+
+        assert_eq!(greeter.name(), "greeter");
+        assert_eq!(
+            call(
+                "greet",
+                vec![Argument::String("rigz".into())],
+                Definition::None,
+                Argument::None
+            ),
+            RuntimeStatus::Ok(Argument::String("Hello, rigz!".into()))
+        );
+        let retry = Retry {
+            attempts: 3,
+            delay: None,
+            mode: Mode::Fixed,
+        };
+        let Argument::Object(retry) = retry.into_argument() else {
+            panic!("Retry is an Object");
+        };
+        assert_eq!(
+            call(
+                "retry-count",
+                vec![],
+                Definition::One(retry),
+                Argument::None
+            ),
+            RuntimeStatus::Ok(Argument::Int(3))
+        );
+        let values = vec![Argument::Int(1), Argument::Long(2), Argument::Int(3)];
+        assert_eq!(
+            call("sum", values, Definition::None, Argument::None),
+            RuntimeStatus::Ok(Argument::Long(6))
+        );
+        assert_eq!(
+            call(
+                "shout",
+                vec![],
+                Definition::None,
+                Argument::String("hi".into())
+            ),
+            RuntimeStatus::Ok(Argument::String("HI".into()))
+        );
+        assert_eq!(
+            call("current-file", vec![], Definition::None, Argument::None),
+            RuntimeStatus::Ok(Argument::String("main.rigz".into()))
+        );
+        assert_eq!(
+            call("missing", vec![], Definition::None, Argument::None),
+            RuntimeStatus::NotFound
+        );
+
+        let RuntimeStatus::Err(error) = call(
+            "greet",
+            vec![Argument::Int(1)],
+            Definition::None,
+            Argument::None,
+        ) else {
+            panic!("greet takes a String");
+        };
+        assert_eq!(error.kind, ErrorKind::InvalidArgument);
+        assert_eq!(error.function.as_deref(), Some("greet"));
+        assert!(error.message.ends_with("at `name`"), "{}", error.message);
+        let RuntimeStatus::Err(error) = call("shout", vec![], Definition::None, Argument::None)
+        else {
+            panic!("shout needs a prior result");
+        };
+        assert_eq!(error.message, "nothing to shout");
+        let RuntimeStatus::Err(error) = call(
+            "current_file",
+            vec![Argument::Int(1)],
+            Definition::None,
+            Argument::None,
+        ) else {
+            panic!("current_file takes no arguments");
+        };
+        assert_eq!(error.kind, ErrorKind::InvalidArgument);
+        assert_eq!(error.message, "expected at most 0 arguments, found 1");
+        let RuntimeStatus::Err(error) = call(
+            "greet",
+            vec![
+                Argument::String("rigz".into()),
+                Argument::String("?".into()),
+                Argument::String("!".into()),
+            ],
+            Definition::None,
+            Argument::None,
+        ) else {
+            panic!("greet takes two arguments");
+        };
+        assert_eq!(error.message, "expected at most 2 arguments, found 3");
+        assert_eq!(error.function.as_deref(), Some("greet"));
+        assert_eq!(
+            greeter.initialize(InitializationArgs::default()),
+            RuntimeStatus::Ok(())
+        );
+        assert!(matches!(greeter.shutdown(), RuntimeStatus::Err(_)));
+
+        let functions = greeter.functions();
+        let signatures: Vec<String> = functions.iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            signatures,
+            vec![
+                "greet(name: string, punctuation?: string) -> string",
+                "retry-count() do ... end -> int",
+                "sum(...values: long) -> long",
+                "shout() -> string",
+                "current_file() -> string?",
+            ]
+        );
+        assert_eq!(
+            functions[0].docs.as_deref(),
+            Some("Greets `name`, with `!` unless given other punctuation")
+        );
+        assert!(functions[2].variadic && functions[3].prior_result);
+        // every variadic argument is checked against the element type
+        let arguments = [Argument::Long(1), Argument::Long(2), Argument::String("3".into())];
+        let error = functions[2].validate(&arguments, &Definition::None).unwrap();
+        assert_eq!(
+            error.data["errors"],
+            Argument::List(vec![Argument::String(
+                "argument `values` expected long, found String".into()
+            )])
+        );
+        assert!(functions[2].validate(&[], &Definition::None).is_none());
+    }
+
+    #[test]
+    fn paths_get_set_and_remove_nested_values() {
+        let item = |name: &str| {
+            Argument::Object(HashMap::from([(
+                "name".to_string(),
+                Argument::String(name.into()),
+            )]))
+        };
+        let mut argument = Argument::Definition(Definition::One(HashMap::from([
+            (
+                "items".to_string(),
+                Argument::List(vec![item("a"), item("b")]),
+            ),
+            ("a.b".to_string(), Argument::Int(1)),
+        ])));
+
+        assert_eq!(
+            argument.get_path("items[1].name"),
+            Ok(Some(&Argument::String("b".into())))
+        );
+        assert_eq!(
+            argument.get_path("items[-2].name"),
+            Ok(Some(&Argument::String("a".into())))
+        );
+        assert_eq!(argument.get_path("[\"a.b\"]"), Ok(Some(&Argument::Int(1))));
+        assert_eq!(argument.get_path("items[2].name"), Ok(None));
+        assert_eq!(argument.get_path("items.name"), Ok(None));
+        assert_eq!(
+            argument.get_path("items[*].name"),
+            Err(PathError::Wildcard("items[*].name".into()))
+        );
+        assert_eq!(
+            argument.select_path("items.*.name"),
+            Ok(vec![
+                &Argument::String("a".into()),
+                &Argument::String("b".into())
+            ])
+        );
+
+        argument.set_path("items[2]", item("c")).unwrap();
+        argument
+            .set_path("items[*].tags[0]", Argument::Bool(true))
+            .unwrap();
+        argument
+            .set_path("owner.team", Argument::String("ops".into()))
+            .unwrap();
+        assert_eq!(
+            argument.get_path("items[2].tags[0]"),
+            Ok(Some(&Argument::Bool(true)))
+        );
+        assert_eq!(
+            argument.get_path("owner.team"),
+            Ok(Some(&Argument::String("ops".into())))
+        );
+        assert!(matches!(
+            argument.set_path("items[9]", Argument::None),
+            Err(PathError::OutOfBounds {
+                index: 9,
+                len: 3,
+                ..
+            })
+        ));
+        assert_eq!(
+            argument.set_path("owner.team.lead", Argument::None),
+            Err(PathError::NotAContainer {
+                at: "owner.team".into(),
+                found: "String"
+            })
+        );
+
+        assert_eq!(
+            argument.remove_path("items[*].tags"),
+            Ok(vec![
+                Argument::List(vec![Argument::Bool(true)]),
+                Argument::List(vec![Argument::Bool(true)]),
+                Argument::List(vec![Argument::Bool(true)])
+            ])
+        );
+        assert_eq!(argument.remove_path("items[0]"), Ok(vec![item("a")]));
+        assert_eq!(argument.remove_path("missing.key"), Ok(vec![]));
+        assert_eq!(argument.select_path("items[*]").map(|i| i.len()), Ok(2));
+        assert_eq!(argument.remove_path(""), Err(PathError::RemoveRoot));
+
+        for invalid in ["a..b", "a[1", "a[b]", ".a", "a]"] {
+            assert!(
+                matches!(argument.get_path(invalid), Err(PathError::Syntax { .. })),
+                "{}",
+                invalid
+            );
+        }
+        let path: ArgumentPath = "a[\"b.c\"][0].*".parse().unwrap();
+        assert_eq!(path.to_string(), "a[\"b.c\"][0][*]");
+    }
+}
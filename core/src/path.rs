@@ -0,0 +1,368 @@
+use crate::{Argument, Definition};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Step of an `ArgumentPath`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    /// `.key`, or `["key"]` for keys with `.`, `[` or `]` in them
+    Key(String),
+    /// `[2]`, negative indexes count from the end of the list
+    Index(i64),
+    /// `.*` or `[*]`, every value of an object or element of a list
+    Wildcard,
+}
+
+/// Path into nested objects and lists, like `a.b[2].c`. `Object`s and `Definition::One` are indexed by key, `List`s
+/// and `Definition::Many` by position. The empty path is the value itself.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ArgumentPath(pub Vec<PathSegment>);
+
+/// Failed `Argument::*_path` call, paths are written the way they were given.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathError {
+    Syntax {
+        path: String,
+        message: String,
+    },
+    /// `get_path` returns a single value, `select_path` returns every match
+    Wildcard(String),
+    /// `set_path` through a value that isn't an object or list, `at` is the path to it
+    NotAContainer {
+        at: String,
+        found: &'static str,
+    },
+    /// `set_path` past the end of a list, the list's length appends
+    OutOfBounds {
+        at: String,
+        index: i64,
+        len: usize,
+    },
+    /// `remove_path` with the empty path
+    RemoveRoot,
+}
+
+impl Display for PathError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathError::Syntax { path, message } => {
+                write!(f, "invalid path `{}`, {}", path, message)
+            }
+            PathError::Wildcard(path) => write!(
+                f,
+                "`{}` can match several values, use select_path instead",
+                path
+            ),
+            PathError::NotAContainer { at, found } => {
+                write!(f, "`{}` is a {}, not an object or list", at, found)
+            }
+            PathError::OutOfBounds { at, index, len } => write!(
+                f,
+                "index {} is out of bounds for `{}`, it has {} elements",
+                index, at, len
+            ),
+            PathError::RemoveRoot => write!(f, "the empty path can't be removed"),
+        }
+    }
+}
+
+impl Error for PathError {}
+
+impl FromStr for ArgumentPath {
+    type Err = PathError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let syntax = |message: &str| PathError::Syntax {
+            path: path.to_string(),
+            message: message.to_string(),
+        };
+        let mut segments = Vec::new();
+        let mut rest = path;
+        while !rest.is_empty() {
+            if let Some(bracket) = rest.strip_prefix('[') {
+                let (segment, after) = bracketed(bracket).ok_or_else(|| syntax("unclosed `[`"))?;
+                segments.push(segment.map_err(|e| syntax(&e))?);
+                rest = after;
+                continue;
+            }
+            if !segments.is_empty() {
+                rest = rest
+                    .strip_prefix('.')
+                    .ok_or_else(|| syntax("expected `.` or `[` after a segment"))?;
+            }
+            let end = rest.find(['.', '[', ']']).unwrap_or(rest.len());
+            let key = &rest[..end];
+            match key {
+                "" => return Err(syntax("keys can't be empty")),
+                "*" => segments.push(PathSegment::Wildcard),
+                key => segments.push(PathSegment::Key(key.to_string())),
+            }
+            rest = &rest[end..];
+        }
+        Ok(ArgumentPath(segments))
+    }
+}
+
+/// Inside `[...]` up to its `]` and the rest of the path, `None` if it isn't closed
+fn bracketed(s: &str) -> Option<(Result<PathSegment, String>, &str)> {
+    if let Some(quote) = s.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let quoted = &s[1..];
+        let end = quoted.find(quote)?;
+        let rest = quoted[end + 1..].strip_prefix(']')?;
+        return Some((Ok(PathSegment::Key(quoted[..end].to_string())), rest));
+    }
+    let end = s.find(']')?;
+    let segment = match s[..end].trim() {
+        "*" => Ok(PathSegment::Wildcard),
+        index => index.parse().map(PathSegment::Index).map_err(|_| {
+            format!(
+                "`{}` isn't an index, quote keys like [\"{}\"]",
+                index, index
+            )
+        }),
+    };
+    Some((segment, &s[end + 1..]))
+}
+
+impl Display for ArgumentPath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Key(key) if key.contains(['.', '[', ']']) || key == "*" => {
+                    write!(f, "[\"{}\"]", key)?
+                }
+                PathSegment::Key(key) if i == 0 => write!(f, "{}", key)?,
+                PathSegment::Key(key) => write!(f, ".{}", key)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+                PathSegment::Wildcard => write!(f, "[*]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ArgumentPath {
+    pub fn has_wildcard(&self) -> bool {
+        self.0.contains(&PathSegment::Wildcard)
+    }
+
+    /// The first `len` segments, for errors
+    fn prefix(&self, len: usize) -> String {
+        ArgumentPath(self.0[..len].to_vec()).to_string()
+    }
+}
+
+fn object(argument: &Argument) -> Option<&HashMap<String, Argument>> {
+    match argument {
+        Argument::Object(o) | Argument::Definition(Definition::One(o)) => Some(o),
+        _ => None,
+    }
+}
+
+fn list(argument: &Argument) -> Option<&Vec<Argument>> {
+    match argument {
+        Argument::List(l) | Argument::Definition(Definition::Many(l)) => Some(l),
+        _ => None,
+    }
+}
+
+/// `index` from the start of a list of `len`, negative indexes count from the end
+fn position(index: i64, len: usize) -> Option<usize> {
+    let position = if index < 0 { len as i64 + index } else { index };
+    usize::try_from(position).ok()
+}
+
+/// Values of an object sorted by key, so wildcards match in the same order every time
+fn sorted_values(o: &HashMap<String, Argument>) -> Vec<&Argument> {
+    let mut entries: Vec<_> = o.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries.into_iter().map(|(_, v)| v).collect()
+}
+
+fn select<'a>(argument: &'a Argument, segments: &[PathSegment], found: &mut Vec<&'a Argument>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        found.push(argument);
+        return;
+    };
+    match segment {
+        PathSegment::Key(key) => {
+            if let Some(value) = object(argument).and_then(|o| o.get(key)) {
+                select(value, rest, found)
+            }
+        }
+        PathSegment::Index(index) => {
+            let value =
+                list(argument).and_then(|l| position(*index, l.len()).and_then(|i| l.get(i)));
+            if let Some(value) = value {
+                select(value, rest, found)
+            }
+        }
+        PathSegment::Wildcard => {
+            let values = match (object(argument), list(argument)) {
+                (Some(o), _) => sorted_values(o),
+                (_, Some(l)) => l.iter().collect(),
+                _ => Vec::new(),
+            };
+            for value in values {
+                select(value, rest, found)
+            }
+        }
+    }
+}
+
+/// Sets `value` at `segments` below `argument`, which is at `path[..depth]`
+fn set(
+    argument: &mut Argument,
+    path: &ArgumentPath,
+    depth: usize,
+    value: &Argument,
+) -> Result<(), PathError> {
+    let Some(segment) = path.0.get(depth) else {
+        *argument = value.clone();
+        return Ok(());
+    };
+    if let Argument::None = argument {
+        *argument = match segment {
+            PathSegment::Key(_) => Argument::Object(HashMap::new()),
+            PathSegment::Index(_) => Argument::List(Vec::new()),
+            PathSegment::Wildcard => return Ok(()),
+        };
+    }
+    let found = argument.variant_name();
+    let not_a_container = || PathError::NotAContainer {
+        at: path.prefix(depth),
+        found,
+    };
+    match (segment, argument) {
+        (PathSegment::Key(key), Argument::Object(o) | Argument::Definition(Definition::One(o))) => {
+            set(
+                o.entry(key.clone()).or_insert(Argument::None),
+                path,
+                depth + 1,
+                value,
+            )
+        }
+        (
+            PathSegment::Index(index),
+            Argument::List(l) | Argument::Definition(Definition::Many(l)),
+        ) => {
+            let len = l.len();
+            match position(*index, len) {
+                Some(i) if i < len => set(&mut l[i], path, depth + 1, value),
+                Some(i) if i == len => {
+                    l.push(Argument::None);
+                    set(&mut l[i], path, depth + 1, value)
+                }
+                _ => Err(PathError::OutOfBounds {
+                    at: path.prefix(depth),
+                    index: *index,
+                    len,
+                }),
+            }
+        }
+        (PathSegment::Wildcard, Argument::Object(o) | Argument::Definition(Definition::One(o))) => {
+            o.values_mut()
+                .try_for_each(|child| set(child, path, depth + 1, value))
+        }
+        (PathSegment::Wildcard, Argument::List(l) | Argument::Definition(Definition::Many(l))) => l
+            .iter_mut()
+            .try_for_each(|child| set(child, path, depth + 1, value)),
+        _ => Err(not_a_container()),
+    }
+}
+
+/// Removes the values at `segments` below `argument`, `segments` isn't empty
+fn remove(argument: &mut Argument, segments: &[PathSegment], removed: &mut Vec<Argument>) {
+    let (segment, rest) = segments
+        .split_first()
+        .expect("remove_path checks for the root");
+    match (segment, argument) {
+        (PathSegment::Key(key), Argument::Object(o) | Argument::Definition(Definition::One(o))) => {
+            if rest.is_empty() {
+                removed.extend(o.remove(key));
+            } else if let Some(child) = o.get_mut(key) {
+                remove(child, rest, removed)
+            }
+        }
+        (
+            PathSegment::Index(index),
+            Argument::List(l) | Argument::Definition(Definition::Many(l)),
+        ) => {
+            let Some(i) = position(*index, l.len()).filter(|i| *i < l.len()) else {
+                return;
+            };
+            if rest.is_empty() {
+                removed.push(l.remove(i));
+            } else {
+                remove(&mut l[i], rest, removed)
+            }
+        }
+        (PathSegment::Wildcard, Argument::Object(o) | Argument::Definition(Definition::One(o))) => {
+            if rest.is_empty() {
+                let mut entries: Vec<_> = o.drain().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                removed.extend(entries.into_iter().map(|(_, v)| v));
+            } else {
+                let mut keys: Vec<_> = o.keys().cloned().collect();
+                keys.sort();
+                for key in keys {
+                    if let Some(child) = o.get_mut(&key) {
+                        remove(child, rest, removed)
+                    }
+                }
+            }
+        }
+        (PathSegment::Wildcard, Argument::List(l) | Argument::Definition(Definition::Many(l))) => {
+            if rest.is_empty() {
+                removed.append(l);
+            } else {
+                for child in l {
+                    remove(child, rest, removed)
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Argument {
+    /// Value at `path`, like `a.b[2].c`, `None` if there's nothing there. Fails if the path is invalid or has a
+    /// wildcard.
+    pub fn get_path(&self, path: &str) -> Result<Option<&Argument>, PathError> {
+        let parsed: ArgumentPath = path.parse()?;
+        if parsed.has_wildcard() {
+            return Err(PathError::Wildcard(path.to_string()));
+        }
+        let mut found = Vec::with_capacity(1);
+        select(self, &parsed.0, &mut found);
+        Ok(found.pop())
+    }
+
+    /// Every value `path` matches, `items[*].name` is the name of each item. Object values are matched in key order.
+    pub fn select_path(&self, path: &str) -> Result<Vec<&Argument>, PathError> {
+        let parsed: ArgumentPath = path.parse()?;
+        let mut found = Vec::new();
+        select(self, &parsed.0, &mut found);
+        Ok(found)
+    }
+
+    /// Sets the value at `path`, wildcards set every match. Missing keys are created, as objects or lists when the
+    /// path continues past them, and a list's length appends to it.
+    pub fn set_path(&mut self, path: &str, value: Argument) -> Result<(), PathError> {
+        let parsed: ArgumentPath = path.parse()?;
+        set(self, &parsed, 0, &value)
+    }
+
+    /// Removes the values at `path` and returns them, several when it has wildcards and none when nothing is there.
+    pub fn remove_path(&mut self, path: &str) -> Result<Vec<Argument>, PathError> {
+        let parsed: ArgumentPath = path.parse()?;
+        if parsed.0.is_empty() {
+            return Err(PathError::RemoveRoot);
+        }
+        let mut removed = Vec::new();
+        remove(self, &parsed.0, &mut removed);
+        Ok(removed)
+    }
+}
@@ -0,0 +1,191 @@
+use crate::{ErrorKind, RigzError};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, Path, PathBuf};
+
+/// Capabilities a module declares in `module.rigz`, or a user grants it in `Options.modules[].config.permissions`.
+/// Nothing is allowed by default.
+///
+/// Relative paths are relative to the directory rigz runs in, a directory includes everything below it. Paths are
+/// compared after following symlinks. `env` and `net` take `*` for any variable or host.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Permissions {
+    pub fs_read: Vec<PathBuf>,
+    pub fs_write: Vec<PathBuf>,
+    /// Run other programs, or exit
+    pub process: bool,
+    pub env: Vec<String>,
+    pub net: Vec<String>,
+}
+
+impl Permissions {
+    pub fn allows(&self, capability: &Capability) -> bool {
+        match capability {
+            Capability::FsRead(path) => contains_path(&self.fs_read, path),
+            Capability::FsWrite(path) => contains_path(&self.fs_write, path),
+            Capability::Process => self.process,
+            Capability::Env(name) => contains_name(&self.env, name),
+            Capability::Net(host) => contains_name(&self.net, host),
+        }
+    }
+}
+
+fn contains_name(names: &[String], name: &str) -> bool {
+    names.iter().any(|n| n == "*" || n == name)
+}
+
+fn contains_path(paths: &[PathBuf], path: &Path) -> bool {
+    let path = resolve(path);
+    paths.iter().any(|p| path.starts_with(resolve(p)))
+}
+
+/// Absolute, with symlinks and `..` in the part of the path that exists resolved by the filesystem, so a link in a
+/// granted directory can't point outside it. The rest may not exist yet and is resolved without touching the
+/// filesystem.
+fn resolve(path: &Path) -> PathBuf {
+    let path = match std::env::current_dir() {
+        Ok(dir) if path.is_relative() => dir.join(path),
+        _ => path.to_path_buf(),
+    };
+    for existing in path.ancestors() {
+        if let Ok(canonical) = existing.canonicalize() {
+            let rest = path.strip_prefix(existing).expect("ancestors are prefixes");
+            return normalize(&canonical.join(rest));
+        }
+    }
+    normalize(&path)
+}
+
+/// `.` and `..` resolved without touching the filesystem
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            c => normalized.push(c),
+        }
+    }
+    normalized
+}
+
+/// Something a module wants to do, checked against its `Permissions`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capability<'a> {
+    FsRead(&'a Path),
+    FsWrite(&'a Path),
+    Process,
+    Env(&'a str),
+    Net(&'a str),
+}
+
+impl Display for Capability<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Capability::FsRead(path) => write!(f, "fs_read {}", path.display()),
+            Capability::FsWrite(path) => write!(f, "fs_write {}", path.display()),
+            Capability::Process => write!(f, "process"),
+            Capability::Env(name) => write!(f, "env {}", name),
+            Capability::Net(host) => write!(f, "net {}", host),
+        }
+    }
+}
+
+/// What a module declared and what it was granted, a capability has to be in both.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModulePermissions {
+    pub module: String,
+    pub declared: Permissions,
+    pub granted: Permissions,
+}
+
+impl ModulePermissions {
+    pub fn new(module: impl Into<String>, declared: Permissions, granted: Permissions) -> Self {
+        ModulePermissions {
+            module: module.into(),
+            declared,
+            granted,
+        }
+    }
+
+    pub fn check(&self, capability: Capability) -> Result<(), PermissionDenied> {
+        let reason = if !self.declared.allows(&capability) {
+            Denial::NotDeclared
+        } else if !self.granted.allows(&capability) {
+            Denial::NotGranted
+        } else {
+            return Ok(());
+        };
+        Err(PermissionDenied {
+            module: self.module.clone(),
+            capability: capability.to_string(),
+            reason,
+        })
+    }
+
+    /// Declared capabilities that weren't granted, formatted like `fs_write ./out`.
+    pub fn ungranted(&self) -> Vec<String> {
+        let (declared, granted) = (&self.declared, &self.granted);
+        let mut capabilities: Vec<Capability> = Vec::new();
+        capabilities.extend(declared.fs_read.iter().map(|p| Capability::FsRead(p)));
+        capabilities.extend(declared.fs_write.iter().map(|p| Capability::FsWrite(p)));
+        if declared.process {
+            capabilities.push(Capability::Process);
+        }
+        capabilities.extend(declared.env.iter().map(|n| Capability::Env(n)));
+        capabilities.extend(declared.net.iter().map(|h| Capability::Net(h)));
+        capabilities
+            .into_iter()
+            .filter(|c| match c {
+                Capability::Env("*") => !granted.env.iter().any(|n| n == "*"),
+                Capability::Net("*") => !granted.net.iter().any(|h| h == "*"),
+                c => !granted.allows(c),
+            })
+            .map(|c| c.to_string())
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Denial {
+    /// Missing from the module's `module.rigz`, the module has to declare it
+    NotDeclared,
+    /// Declared but not in `Options.modules[].config.permissions`
+    NotGranted,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermissionDenied {
+    pub module: String,
+    pub capability: String,
+    pub reason: Denial,
+}
+
+impl Display for PermissionDenied {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            Denial::NotDeclared => write!(
+                f,
+                "permission denied, {} doesn't declare `{}` in its module.rigz",
+                self.module, self.capability
+            ),
+            Denial::NotGranted => write!(
+                f,
+                "permission denied, {} needs `{}`, grant it in its `config.permissions`",
+                self.module, self.capability
+            ),
+        }
+    }
+}
+
+impl Error for PermissionDenied {}
+
+impl From<PermissionDenied> for RigzError {
+    fn from(value: PermissionDenied) -> Self {
+        RigzError::new(ErrorKind::PermissionDenied, value.to_string()).with_module(value.module)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{Argument, ArgumentType, Definition};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Shape of a `Definition::One`, the keys a function reads from its `do ... end` block.
+///
+/// Deserializes from `{ "fields": { "channel": { "type": "string", "required": true } } }`, keys not in `fields` are
+/// reported as unknown unless `allow_unknown` is set.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct DefinitionSchema {
+    #[serde(default)]
+    pub fields: HashMap<String, FieldSchema>,
+    #[serde(default)]
+    pub allow_unknown: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FieldSchema {
+    #[serde(default, rename = "type")]
+    pub argument_type: ArgumentType,
+    #[serde(default)]
+    pub required: bool,
+    /// Keys of a nested `Object`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<DefinitionSchema>,
+}
+
+impl DefinitionSchema {
+    pub fn new() -> Self {
+        DefinitionSchema::default()
+    }
+
+    pub fn field(self, name: impl Into<String>, argument_type: ArgumentType) -> Self {
+        self.insert(name, argument_type, true, None)
+    }
+
+    pub fn optional_field(self, name: impl Into<String>, argument_type: ArgumentType) -> Self {
+        self.insert(name, argument_type, false, None)
+    }
+
+    pub fn object_field(
+        self,
+        name: impl Into<String>,
+        schema: DefinitionSchema,
+        required: bool,
+    ) -> Self {
+        self.insert(name, ArgumentType::Object, required, Some(schema))
+    }
+
+    pub fn allow_unknown(mut self) -> Self {
+        self.allow_unknown = true;
+        self
+    }
+
+    fn insert(
+        mut self,
+        name: impl Into<String>,
+        argument_type: ArgumentType,
+        required: bool,
+        schema: Option<DefinitionSchema>,
+    ) -> Self {
+        self.fields.insert(
+            name.into(),
+            FieldSchema {
+                argument_type,
+                required,
+                schema,
+            },
+        );
+        self
+    }
+
+    /// Every problem with `definition`, empty if it matches.
+    pub fn check(&self, definition: &Definition) -> Vec<String> {
+        let mut problems = Vec::new();
+        match definition {
+            Definition::One(object) => self.check_object(object, "", &mut problems),
+            Definition::None => {
+                for key in self.required_keys() {
+                    problems.push(format!("missing key `{}`", key));
+                }
+            }
+            Definition::Many(_) => {
+                problems.push("expected a definition with keys, found a list".to_string())
+            }
+        }
+        problems
+    }
+
+    fn required_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .fields
+            .iter()
+            .filter(|(_, f)| f.required)
+            .map(|(k, _)| k.as_str())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn check_object(
+        &self,
+        object: &HashMap<String, Argument>,
+        prefix: &str,
+        problems: &mut Vec<String>,
+    ) {
+        for key in self.required_keys() {
+            if !object.contains_key(key) {
+                problems.push(format!("missing key `{}{}`", prefix, key));
+            }
+        }
+
+        let mut keys: Vec<&String> = object.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &object[key];
+            let field = match self.fields.get(key) {
+                Some(f) => f,
+                None if self.allow_unknown => continue,
+                None => {
+                    let mut allowed: Vec<&str> = self.fields.keys().map(|k| k.as_str()).collect();
+                    allowed.sort();
+                    problems.push(format!(
+                        "unknown key `{}{}`, expected one of `{}`",
+                        prefix,
+                        key,
+                        allowed.join("`, `")
+                    ));
+                    continue;
+                }
+            };
+            if !field.argument_type.matches(value) {
+                problems.push(format!(
+                    "`{}{}` expected {}, found {}",
+                    prefix,
+                    key,
+                    field.argument_type,
+                    value.variant_name()
+                ));
+                continue;
+            }
+            let nested = match value {
+                Argument::Object(o) | Argument::Definition(Definition::One(o)) => o,
+                _ => continue,
+            };
+            if let Some(schema) = &field.schema {
+                schema.check_object(nested, &format!("{}{}.", prefix, key), problems);
+            }
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use crate::{Argument, SerdeError};
+use serde::ser::{
+    Impossible, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant,
+};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Converts any `Serialize` type to an `Argument`, the inverse of `from_argument`.
+///
+/// Structs and maps become an `Object`, sequences and tuples a `List`, unit variants a `String` of the variant name,
+/// and other variants an `Object` with the variant name as its only key, the same shape as
+/// `#[derive(IntoArgument)]`. `Argument` itself keeps its derived, externally tagged representation.
+pub fn to_argument<T: Serialize + ?Sized>(value: &T) -> Result<Argument, SerdeError> {
+    value.serialize(ArgumentSerializer)
+}
+
+struct ArgumentSerializer;
+
+impl Serializer for ArgumentSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = VariantSerializer<ListSerializer>;
+    type SerializeMap = ObjectSerializer;
+    type SerializeStruct = ObjectSerializer;
+    type SerializeStructVariant = VariantSerializer<ObjectSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<Argument, SerdeError> {
+        Ok(Argument::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Argument, SerdeError> {
+        Ok(Argument::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Argument, SerdeError> {
+        Ok(Argument::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Argument, SerdeError> {
+        Ok(Argument::Int(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Argument, SerdeError> {
+        Ok(Argument::Long(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Argument, SerdeError> {
+        Ok(Argument::Int(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Argument, SerdeError> {
+        Ok(Argument::Int(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Argument, SerdeError> {
+        Ok(Argument::Long(v.into()))
+    }
+
+    /// Values above `i64::MAX` become a `Double`, like `IntoArgument`
+    fn serialize_u64(self, v: u64) -> Result<Argument, SerdeError> {
+        match i64::try_from(v) {
+            Ok(l) => Ok(Argument::Long(l)),
+            Err(_) => Ok(Argument::Double(v as f64)),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Argument, SerdeError> {
+        Ok(Argument::Float(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Argument, SerdeError> {
+        Ok(Argument::Double(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Argument, SerdeError> {
+        Ok(Argument::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Argument, SerdeError> {
+        Ok(Argument::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Argument, SerdeError> {
+        Ok(Argument::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Argument, SerdeError> {
+        Ok(Argument::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Argument, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Argument, SerdeError> {
+        Ok(Argument::None)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Argument, SerdeError> {
+        Ok(Argument::None)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Argument, SerdeError> {
+        Ok(Argument::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Argument, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Argument, SerdeError> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer, SerdeError> {
+        Ok(ListSerializer(Vec::with_capacity(len.unwrap_or_default())))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ListSerializer, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<ListSerializer>, SerdeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<ObjectSerializer, SerdeError> {
+        Ok(ObjectSerializer {
+            object: HashMap::with_capacity(len.unwrap_or_default()),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<ObjectSerializer, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<ObjectSerializer>, SerdeError> {
+        Ok(VariantSerializer {
+            variant,
+            inner: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+fn tagged(variant: &str, value: Argument) -> Argument {
+    let mut object = HashMap::with_capacity(1);
+    object.insert(variant.to_string(), value);
+    Argument::Object(object)
+}
+
+struct ListSerializer(Vec<Argument>);
+
+impl SerializeSeq for ListSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.0.push(value.serialize(ArgumentSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        Ok(Argument::List(self.0))
+    }
+}
+
+impl SerializeTuple for ListSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for ListSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct ObjectSerializer {
+    object: HashMap<String, Argument>,
+    key: Option<String>,
+}
+
+impl SerializeMap for ObjectSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = match self.key.take() {
+            None => {
+                return Err(SerdeError::new(
+                    "serialize_value called before serialize_key",
+                ))
+            }
+            Some(key) => key,
+        };
+        self.object
+            .insert(key, value.serialize(ArgumentSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        Ok(Argument::Object(self.object))
+    }
+}
+
+impl SerializeStruct for ObjectSerializer {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.object
+            .insert(key.to_string(), value.serialize(ArgumentSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        SerializeMap::end(self)
+    }
+}
+
+struct VariantSerializer<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl SerializeTupleVariant for VariantSerializer<ListSerializer> {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        Ok(tagged(self.variant, SerializeSeq::end(self.inner)?))
+    }
+}
+
+impl SerializeStructVariant for VariantSerializer<ObjectSerializer> {
+    type Ok = Argument;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Argument, SerdeError> {
+        Ok(tagged(self.variant, SerializeMap::end(self.inner)?))
+    }
+}
+
+/// `Object` keys are strings, numbers, bools, and chars are converted like JSON keys.
+struct KeySerializer;
+
+impl KeySerializer {
+    fn unsupported(kind: &str) -> SerdeError {
+        SerdeError::new(format!("Object keys must be strings, found {}", kind))
+    }
+}
+
+impl Serializer for KeySerializer {
+    type Ok = String;
+    type Error = SerdeError;
+    type SerializeSeq = Impossible<String, SerdeError>;
+    type SerializeTuple = Impossible<String, SerdeError>;
+    type SerializeTupleStruct = Impossible<String, SerdeError>;
+    type SerializeTupleVariant = Impossible<String, SerdeError>;
+    type SerializeMap = Impossible<String, SerdeError>;
+    type SerializeStruct = Impossible<String, SerdeError>;
+    type SerializeStructVariant = Impossible<String, SerdeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported("f32"))
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported("f64"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported("bytes"))
+    }
+
+    fn serialize_none(self) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported("none"))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported("unit"))
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, SerdeError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, SerdeError> {
+        Err(KeySerializer::unsupported("sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, SerdeError> {
+        Err(KeySerializer::unsupported("tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, SerdeError> {
+        Err(KeySerializer::unsupported("map"))
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, SerdeError> {
+        Err(KeySerializer::unsupported(name))
+    }
+}
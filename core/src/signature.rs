@@ -0,0 +1,327 @@
+use crate::{Argument, Definition, DefinitionSchema, ErrorKind, RigzError};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Kind of `Argument` a parameter accepts or a function returns, `Any` when the module doesn't say.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgumentType {
+    #[default]
+    Any,
+    None,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bool,
+    String,
+    Bytes,
+    DateTime,
+    Decimal,
+    File,
+    Object,
+    List,
+    FunctionCall,
+    Definition,
+    Error,
+}
+
+impl ArgumentType {
+    /// Whether `argument` can be passed where this type is expected, wider numbers accept narrower ones. `Decimal`
+    /// accepts integers but not floats, which may already have lost precision.
+    pub fn matches(&self, argument: &Argument) -> bool {
+        matches!(
+            (self, argument),
+            (ArgumentType::Any, _)
+                | (ArgumentType::None, Argument::None)
+                | (ArgumentType::Int, Argument::Int(_))
+                | (ArgumentType::Long, Argument::Int(_) | Argument::Long(_))
+                | (ArgumentType::Float, Argument::Int(_) | Argument::Float(_))
+                | (
+                    ArgumentType::Double,
+                    Argument::Int(_) | Argument::Long(_) | Argument::Float(_) | Argument::Double(_),
+                )
+                | (ArgumentType::Bool, Argument::Bool(_))
+                | (ArgumentType::String, Argument::String(_))
+                | (ArgumentType::Bytes, Argument::Bytes(_))
+                | (ArgumentType::DateTime, Argument::DateTime(_))
+                | (
+                    ArgumentType::Decimal,
+                    Argument::Int(_) | Argument::Long(_) | Argument::Decimal(_)
+                )
+                | (ArgumentType::File, Argument::File(_))
+                | (
+                    ArgumentType::Object,
+                    Argument::Object(_) | Argument::Definition(Definition::One(_))
+                )
+                | (ArgumentType::List, Argument::List(_))
+                | (ArgumentType::FunctionCall, Argument::FunctionCall(_))
+                | (ArgumentType::Definition, Argument::Definition(_))
+                | (ArgumentType::Error, Argument::Error(_))
+        )
+    }
+}
+
+impl Display for ArgumentType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ArgumentType::Any => "any",
+            ArgumentType::None => "none",
+            ArgumentType::Int => "int",
+            ArgumentType::Long => "long",
+            ArgumentType::Float => "float",
+            ArgumentType::Double => "double",
+            ArgumentType::Bool => "bool",
+            ArgumentType::String => "string",
+            ArgumentType::Bytes => "bytes",
+            ArgumentType::DateTime => "date_time",
+            ArgumentType::Decimal => "decimal",
+            ArgumentType::File => "file",
+            ArgumentType::Object => "object",
+            ArgumentType::List => "list",
+            ArgumentType::FunctionCall => "function_call",
+            ArgumentType::Definition => "definition",
+            ArgumentType::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ArgumentType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let argument_type = match s {
+            "any" => ArgumentType::Any,
+            "none" => ArgumentType::None,
+            "int" => ArgumentType::Int,
+            "long" => ArgumentType::Long,
+            "float" => ArgumentType::Float,
+            "double" => ArgumentType::Double,
+            "bool" => ArgumentType::Bool,
+            "string" => ArgumentType::String,
+            "bytes" => ArgumentType::Bytes,
+            "date_time" => ArgumentType::DateTime,
+            "decimal" => ArgumentType::Decimal,
+            "file" => ArgumentType::File,
+            "object" => ArgumentType::Object,
+            "list" => ArgumentType::List,
+            "function_call" => ArgumentType::FunctionCall,
+            "definition" => ArgumentType::Definition,
+            "error" => ArgumentType::Error,
+            _ => return Err(format!("Invalid Argument Type: `{}`", s)),
+        };
+        Ok(argument_type)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Parameter {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub argument_type: ArgumentType,
+    #[serde(default)]
+    pub optional: bool,
+    /// Takes the remaining arguments, each of `argument_type`. Only the last parameter can be variadic.
+    #[serde(default)]
+    pub variadic: bool,
+}
+
+/// What a module function accepts and returns, used for completion, `rigz check`, and docs.
+///
+/// `definition` is set when the function reads the `do ... end` block, `prior_result` when it reads the value of the
+/// previous call. `variadic` functions accept any number of arguments after `parameters`, `schema` is the shape of
+/// the definition.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct FunctionSignature {
+    pub name: String,
+    #[serde(default)]
+    pub parameters: Vec<Parameter>,
+    #[serde(default)]
+    pub definition: bool,
+    #[serde(default)]
+    pub prior_result: bool,
+    #[serde(default)]
+    pub variadic: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<DefinitionSchema>,
+    #[serde(default)]
+    pub returns: ArgumentType,
+    /// `returns` or `None`
+    #[serde(default)]
+    pub optional_return: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub docs: Option<String>,
+}
+
+impl FunctionSignature {
+    pub fn new(name: impl Into<String>) -> Self {
+        FunctionSignature {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn parameter(mut self, name: impl Into<String>, argument_type: ArgumentType) -> Self {
+        self.parameters.push(Parameter {
+            name: name.into(),
+            argument_type,
+            optional: false,
+            variadic: false,
+        });
+        self
+    }
+
+    pub fn optional_parameter(
+        mut self,
+        name: impl Into<String>,
+        argument_type: ArgumentType,
+    ) -> Self {
+        self.parameters.push(Parameter {
+            name: name.into(),
+            argument_type,
+            optional: true,
+            variadic: false,
+        });
+        self
+    }
+
+    /// Zero or more remaining arguments of `argument_type`, implies `variadic`
+    pub fn variadic_parameter(
+        mut self,
+        name: impl Into<String>,
+        argument_type: ArgumentType,
+    ) -> Self {
+        self.parameters.push(Parameter {
+            name: name.into(),
+            argument_type,
+            optional: true,
+            variadic: true,
+        });
+        self.variadic = true;
+        self
+    }
+
+    pub fn with_definition(mut self) -> Self {
+        self.definition = true;
+        self
+    }
+
+    pub fn with_prior_result(mut self) -> Self {
+        self.prior_result = true;
+        self
+    }
+
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+
+    /// Sets the shape of the definition, implies `with_definition`
+    pub fn with_schema(mut self, schema: DefinitionSchema) -> Self {
+        self.definition = true;
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn returns(mut self, argument_type: ArgumentType) -> Self {
+        self.returns = argument_type;
+        self
+    }
+
+    pub fn returns_optional(mut self, argument_type: ArgumentType) -> Self {
+        self.returns = argument_type;
+        self.optional_return = true;
+        self
+    }
+
+    pub fn docs(mut self, docs: impl Into<String>) -> Self {
+        self.docs = Some(docs.into());
+        self
+    }
+
+    /// Checks the number and types of `arguments`, and `definition` against `schema`, `None` if the call matches.
+    /// All problems are reported in one `InvalidArgument` error, listed in its `errors` data.
+    pub fn validate(&self, arguments: &[Argument], definition: &Definition) -> Option<RigzError> {
+        let mut problems = Vec::new();
+        let required = self.parameters.iter().filter(|p| !p.optional).count();
+        let max = self.parameters.len();
+        let rest = self.parameters.last().filter(|p| p.variadic);
+        if arguments.len() < required || (!self.variadic && arguments.len() > max) {
+            let expected = if self.variadic {
+                format!("at least {}", required)
+            } else if required == max {
+                max.to_string()
+            } else {
+                format!("{} to {}", required, max)
+            };
+            let plural = if expected == "1" { "" } else { "s" };
+            problems.push(format!(
+                "expected {} argument{}, found {}",
+                expected,
+                plural,
+                arguments.len()
+            ));
+        }
+        for (index, argument) in arguments.iter().enumerate() {
+            let Some(parameter) = self.parameters.get(index).or(rest) else {
+                break;
+            };
+            let skipped = parameter.optional && *argument == Argument::None;
+            if !skipped && !parameter.argument_type.matches(argument) {
+                problems.push(format!(
+                    "argument `{}` expected {}, found {}",
+                    parameter.name,
+                    parameter.argument_type,
+                    argument.variant_name()
+                ));
+            }
+        }
+        if let Some(schema) = &self.schema {
+            problems.extend(schema.check(definition));
+        }
+
+        if problems.is_empty() {
+            return None;
+        }
+        let message = format!(
+            "invalid arguments for `{}`: {}",
+            self.name,
+            problems.join("; ")
+        );
+        let problems = problems.into_iter().map(Argument::String).collect();
+        let error = RigzError::new(ErrorKind::InvalidArgument, message)
+            .with_function(&self.name)
+            .with_data("errors", Argument::List(problems));
+        Some(error)
+    }
+}
+
+/// `name(a: string, b?: int, ...c: long) -> bool`, `-> string?` when it can also return `None`
+impl Display for FunctionSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (index, parameter) in self.parameters.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            let (rest, optional) = match (parameter.variadic, parameter.optional) {
+                (true, _) => ("...", ""),
+                (false, true) => ("", "?"),
+                (false, false) => ("", ""),
+            };
+            write!(
+                f,
+                "{}{}{}: {}",
+                rest, parameter.name, optional, parameter.argument_type
+            )?;
+        }
+        write!(f, ")")?;
+        if self.definition {
+            write!(f, " do ... end")?;
+        }
+        let optional = if self.optional_return { "?" } else { "" };
+        write!(f, " -> {}{}", self.returns, optional)
+    }
+}
@@ -0,0 +1,319 @@
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, Generics, Ident, ItemImpl,
+    LitStr,
+};
+
+mod module;
+
+/// Builds `Self` from an `Argument`, see the crate README for the expected shape.
+#[proc_macro_derive(FromArgument)]
+pub fn derive_from_argument(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let generics = add_bound(
+        input.generics.clone(),
+        parse_quote!(::rigz_core::FromArgument),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(s) => from_fields(name, &quote!(#name), &s.fields, quote!(argument)),
+        Data::Enum(e) => {
+            let unit: Vec<&Ident> = e
+                .variants
+                .iter()
+                .filter(|v| matches!(v.fields, Fields::Unit))
+                .map(|v| &v.ident)
+                .collect();
+            let unit_names: Vec<String> = unit.iter().map(|v| v.to_string()).collect();
+            let tagged: Vec<TokenStream2> = e
+                .variants
+                .iter()
+                .filter(|v| !matches!(v.fields, Fields::Unit))
+                .map(|v| {
+                    let ident = &v.ident;
+                    let tag = ident.to_string();
+                    let from = from_fields(name, &quote!(#name::#ident), &v.fields, quote!(value));
+                    quote! {
+                        #tag => (|| -> ::std::result::Result<Self, ::rigz_core::ConversionError> {
+                            #from
+                        })()
+                        .map_err(|e| e.at(#tag)),
+                    }
+                })
+                .collect();
+            let expected = format!(
+                "one of {} for {}",
+                e.variants
+                    .iter()
+                    .map(|v| format!("`{}`", v.ident))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                name
+            );
+            quote! {
+                match argument {
+                    ::rigz_core::Argument::String(s) => match s.as_str() {
+                        #(#unit_names => Ok(#name::#unit),)*
+                        _ => Err(::rigz_core::ConversionError {
+                            expected: #expected.to_string(),
+                            found: format!("String({:?})", s),
+                            path: Vec::new(),
+                        }),
+                    },
+                    ::rigz_core::Argument::Object(o) if o.len() == 1 => {
+                        let (key, value) = o.into_iter().next().expect("length checked above");
+                        match key.as_str() {
+                            #(#tagged)*
+                            _ => Err(::rigz_core::ConversionError {
+                                expected: #expected.to_string(),
+                                found: format!("Object with key {:?}", key),
+                                path: Vec::new(),
+                            }),
+                        }
+                    }
+                    a => Err(::rigz_core::ConversionError::new(#expected, &a)),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Error::new(
+                Span::call_site(),
+                "FromArgument can't be derived for unions",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    quote! {
+        impl #impl_generics ::rigz_core::FromArgument for #name #ty_generics #where_clause {
+            fn from_argument(
+                argument: ::rigz_core::Argument,
+            ) -> ::std::result::Result<Self, ::rigz_core::ConversionError> {
+                #body
+            }
+        }
+    }
+    .into()
+}
+
+/// Converts `Self` to an `Argument`, the inverse of `FromArgument`.
+#[proc_macro_derive(IntoArgument)]
+pub fn derive_into_argument(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let generics = add_bound(
+        input.generics.clone(),
+        parse_quote!(::rigz_core::IntoArgument),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(s) => {
+            let (pattern, into) = into_fields(&s.fields);
+            quote! {
+                let #name #pattern = self;
+                #into
+            }
+        }
+        Data::Enum(e) => {
+            let arms = e.variants.iter().map(|v| {
+                let ident = &v.ident;
+                let tag = ident.to_string();
+                if let Fields::Unit = v.fields {
+                    return quote! {
+                        #name::#ident => ::rigz_core::Argument::String(#tag.to_string()),
+                    };
+                }
+                let (pattern, into) = into_fields(&v.fields);
+                quote! {
+                    #name::#ident #pattern => {
+                        let mut object = ::std::collections::HashMap::with_capacity(1);
+                        object.insert(#tag.to_string(), { #into });
+                        ::rigz_core::Argument::Object(object)
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Error::new(
+                Span::call_site(),
+                "IntoArgument can't be derived for unions",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    quote! {
+        impl #impl_generics ::rigz_core::IntoArgument for #name #ty_generics #where_clause {
+            fn into_argument(self) -> ::rigz_core::Argument {
+                #body
+            }
+        }
+    }
+    .into()
+}
+
+/// Implements `Module` for the type of an `impl` block, exporting its `#[rigz_function]` methods. See the crate
+/// README for how parameters and return values are converted.
+#[proc_macro_attribute]
+pub fn rigz_module(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse::<LitStr>()?.value());
+            Ok(())
+        } else {
+            Err(meta.error("expected `name = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let item = parse_macro_input!(item as ItemImpl);
+    module::expand(name, item)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Exports a method of a `#[rigz_module]` impl, optionally as `#[rigz_function(name = "...")]`. It's an error
+/// anywhere else.
+#[proc_macro_attribute]
+pub fn rigz_function(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut error = Error::new(
+        Span::call_site(),
+        "#[rigz_function] has to be on a method in a #[rigz_module] impl",
+    )
+    .into_compile_error();
+    error.extend(TokenStream2::from(item));
+    error.into()
+}
+
+fn add_bound(mut generics: Generics, bound: syn::TypeParamBound) -> Generics {
+    for param in generics.type_params_mut() {
+        param.bounds.push(bound.clone());
+    }
+    generics
+}
+
+/// Expression converting `source` into `constructor`, evaluates to `Result<Self, ConversionError>`.
+fn from_fields(
+    name: &Ident,
+    constructor: &TokenStream2,
+    fields: &Fields,
+    source: TokenStream2,
+) -> TokenStream2 {
+    match fields {
+        Fields::Named(named) => {
+            let expected = format!("Object for {}", name);
+            let values = named.named.iter().map(|f| {
+                let ident = f.ident.as_ref().expect("named field");
+                let key = ident.to_string();
+                quote! {
+                    #ident: ::rigz_core::FromArgument::from_argument(
+                        object.remove(#key).unwrap_or(::rigz_core::Argument::None),
+                    )
+                    .map_err(|e| e.at(#key))?,
+                }
+            });
+            quote! {{
+                let mut object = match #source {
+                    ::rigz_core::Argument::Object(o) => o,
+                    ::rigz_core::Argument::Definition(::rigz_core::Definition::One(o)) => o,
+                    a => return Err(::rigz_core::ConversionError::new(#expected, &a)),
+                };
+                Ok(#constructor { #(#values)* })
+            }}
+        }
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => quote! {
+            Ok(#constructor(::rigz_core::FromArgument::from_argument(#source)?))
+        },
+        Fields::Unnamed(unnamed) => {
+            let len = unnamed.unnamed.len();
+            let expected = format!("List of {} elements for {}", len, name);
+            let values = (0..len).map(|index| {
+                quote! {
+                    ::rigz_core::FromArgument::from_argument(
+                        values.next().expect("length checked above"),
+                    )
+                    .map_err(|e| e.at(#index))?,
+                }
+            });
+            quote! {{
+                let list = match #source {
+                    ::rigz_core::Argument::List(l) if l.len() == #len => l,
+                    ::rigz_core::Argument::List(l) => {
+                        return Err(::rigz_core::ConversionError {
+                            expected: #expected.to_string(),
+                            found: format!("List of {} elements", l.len()),
+                            path: Vec::new(),
+                        })
+                    }
+                    a => return Err(::rigz_core::ConversionError::new(#expected, &a)),
+                };
+                let mut values = list.into_iter();
+                Ok(#constructor(#(#values)*))
+            }}
+        }
+        Fields::Unit => {
+            let expected = format!("None for {}", name);
+            quote! {
+                match #source {
+                    ::rigz_core::Argument::None => Ok(#constructor),
+                    a => Err(::rigz_core::ConversionError::new(#expected, &a)),
+                }
+            }
+        }
+    }
+}
+
+/// Destructuring pattern for `fields` and the expression converting the bindings to an `Argument`.
+fn into_fields(fields: &Fields) -> (TokenStream2, TokenStream2) {
+    match fields {
+        Fields::Named(named) => {
+            let idents: Vec<&Ident> = named
+                .named
+                .iter()
+                .map(|f| f.ident.as_ref().expect("named field"))
+                .collect();
+            let keys: Vec<String> = idents.iter().map(|i| i.to_string()).collect();
+            (
+                quote!({ #(#idents),* }),
+                quote! {{
+                    let mut object = ::std::collections::HashMap::new();
+                    #(object.insert(
+                        #keys.to_string(),
+                        ::rigz_core::IntoArgument::into_argument(#idents),
+                    );)*
+                    ::rigz_core::Argument::Object(object)
+                }},
+            )
+        }
+        Fields::Unnamed(unnamed) if unnamed.unnamed.len() == 1 => (
+            quote!((value)),
+            quote!(::rigz_core::IntoArgument::into_argument(value)),
+        ),
+        Fields::Unnamed(unnamed) => {
+            let bindings: Vec<Ident> = (0..unnamed.unnamed.len())
+                .map(|i| format_ident!("field_{}", i))
+                .collect();
+            (
+                quote!((#(#bindings),*)),
+                quote! {
+                    ::rigz_core::Argument::List(vec![
+                        #(::rigz_core::IntoArgument::into_argument(#bindings)),*
+                    ])
+                },
+            )
+        }
+        Fields::Unit => (quote!(), quote!(::rigz_core::Argument::None)),
+    }
+}
@@ -0,0 +1,590 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    Attribute, Error, Expr, FnArg, GenericArgument, Ident, ImplItem, ImplItemFn, ItemImpl, Lit,
+    LitStr, Meta, Pat, Path, PathArguments, PathSegment, ReturnType, Type,
+};
+
+/// `Module` methods forwarded to a method of the same name in the impl, when it has one and it isn't exported.
+const HOOKS: &[&str] = &[
+    "root",
+    "initialize",
+    "on_file_start",
+    "on_file_end",
+    "shutdown",
+    "reload",
+];
+
+/// Where a parameter's value comes from
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Argument,
+    Variadic,
+    Definition,
+    PriorResult,
+    Context,
+}
+
+struct Parameter {
+    ident: Ident,
+    ty: Type,
+    source: Source,
+}
+
+struct Function {
+    ident: Ident,
+    name: String,
+    receiver: bool,
+    parameters: Vec<Parameter>,
+    output: ReturnType,
+    docs: Option<String>,
+}
+
+/// The impl with parameter attributes removed, followed by its `Module` impl.
+pub fn expand(name: Option<String>, mut item: ItemImpl) -> Result<TokenStream, Error> {
+    let name = match name {
+        Some(name) => name,
+        None => snake_case(&type_name(&item.self_ty)?),
+    };
+
+    let mut functions = Vec::new();
+    let mut paths: Vec<Path> = Vec::new();
+    let mut hooks = Vec::new();
+    for impl_item in &mut item.items {
+        let ImplItem::Fn(method) = impl_item else {
+            continue;
+        };
+        match take_function_attribute(method)? {
+            Some((exported, path)) => {
+                functions.push(function(method, exported)?);
+                paths.push(path);
+            }
+            None if HOOKS.contains(&method.sig.ident.to_string().as_str()) => {
+                hooks.push(method.sig.ident.clone())
+            }
+            None => {}
+        }
+    }
+
+    let arms = functions.iter().map(arm);
+    let signatures = functions.iter().map(signature);
+    let hook = |hook: &str| hooks.iter().find(|h| *h == hook);
+    let root = match hook("root") {
+        Some(root) => quote!(Self::#root(self)),
+        None => quote!(::std::path::PathBuf::new()),
+    };
+    let initialize = match hook("initialize") {
+        Some(initialize) => quote!(Self::#initialize(self, args)),
+        None => quote!(::rigz_core::RuntimeStatus::Ok(())),
+    };
+    let forwarded = hooks.iter().map(|hook| match hook.to_string().as_str() {
+        "on_file_start" => quote! {
+            fn on_file_start(&self, file: &str) -> ::rigz_core::RuntimeStatus<()> {
+                Self::on_file_start(self, file)
+            }
+        },
+        "on_file_end" => quote! {
+            fn on_file_end(
+                &self,
+                file: &str,
+                result: &::rigz_core::Argument,
+            ) -> ::rigz_core::RuntimeStatus<()> {
+                Self::on_file_end(self, file, result)
+            }
+        },
+        "shutdown" => quote! {
+            fn shutdown(&self) -> ::rigz_core::RuntimeStatus<()> {
+                Self::shutdown(self)
+            }
+        },
+        "reload" => quote! {
+            fn reload(&self, args: ::rigz_core::InitializationArgs) -> ::rigz_core::RuntimeStatus<()> {
+                Self::reload(self, args)
+            }
+        },
+        _ => quote!(),
+    });
+
+    let self_ty = &item.self_ty;
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        // `#[rigz_function]` was removed from the methods above, this keeps importing it from being unused
+        #(#[allow(unused_imports)] use #paths as _;)*
+
+        impl #impl_generics ::rigz_core::Module for #self_ty #where_clause {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn root(&self) -> ::std::path::PathBuf {
+                #root
+            }
+
+            #[allow(unused_variables)]
+            fn function_call(
+                &self,
+                name: &str,
+                arguments: ::std::vec::Vec<::rigz_core::Argument>,
+                definition: ::rigz_core::Definition,
+                prior_result: ::rigz_core::Argument,
+                context: &::rigz_core::CallContext,
+            ) -> ::rigz_core::RuntimeStatus<::rigz_core::Argument> {
+                match name {
+                    #(#arms)*
+                    _ => ::rigz_core::RuntimeStatus::NotFound,
+                }
+            }
+
+            fn initialize(
+                &self,
+                args: ::rigz_core::InitializationArgs,
+            ) -> ::rigz_core::RuntimeStatus<()> {
+                #initialize
+            }
+
+            fn functions(&self) -> ::std::vec::Vec<::rigz_core::FunctionSignature> {
+                ::std::vec![#(#signatures),*]
+            }
+
+            #(#forwarded)*
+        }
+    })
+}
+
+fn type_name(ty: &Type) -> Result<String, Error> {
+    match ty {
+        Type::Path(path) => match path.path.segments.last() {
+            Some(segment) => Ok(segment.ident.to_string()),
+            None => Err(Error::new_spanned(ty, "expected a type name")),
+        },
+        _ => Err(Error::new_spanned(
+            ty,
+            "#[rigz_module] needs a `name` for this type",
+        )),
+    }
+}
+
+/// `JsonModule` is `json_module`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+fn is_attribute(attribute: &Attribute, name: &str) -> bool {
+    attribute
+        .path()
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == name)
+}
+
+/// Removes `#[rigz_function]` from `method`, returning the name it's exported as if it had one and the attribute's
+/// path.
+fn take_function_attribute(method: &mut ImplItemFn) -> Result<Option<(String, Path)>, Error> {
+    let Some(index) = method
+        .attrs
+        .iter()
+        .position(|a| is_attribute(a, "rigz_function"))
+    else {
+        return Ok(None);
+    };
+    let attribute = method.attrs.remove(index);
+    let mut name = method.sig.ident.to_string();
+    if let Meta::List(_) = attribute.meta {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else {
+                Err(meta.error("expected `name = \"...\"`"))
+            }
+        })?;
+    }
+    Ok(Some((name, attribute.path().clone())))
+}
+
+fn function(method: &mut ImplItemFn, name: String) -> Result<Function, Error> {
+    let mut receiver = false;
+    let mut parameters: Vec<Parameter> = Vec::new();
+    for input in &mut method.sig.inputs {
+        match input {
+            FnArg::Receiver(r) => {
+                if r.reference.is_none() || r.mutability.is_some() {
+                    return Err(Error::new_spanned(r, "rigz functions take `&self`"));
+                }
+                receiver = true;
+            }
+            FnArg::Typed(typed) => {
+                let Pat::Ident(pat) = &*typed.pat else {
+                    return Err(Error::new_spanned(
+                        &typed.pat,
+                        "rigz function parameters have to be identifiers",
+                    ));
+                };
+                let source = take_source(&mut typed.attrs, &typed.ty)?;
+                if parameters.iter().any(|p| p.source == Source::Variadic) {
+                    return Err(Error::new_spanned(
+                        typed,
+                        "#[variadic] has to be the last parameter",
+                    ));
+                }
+                if source != Source::Argument && parameters.iter().any(|p| p.source == source) {
+                    return Err(Error::new_spanned(
+                        typed,
+                        "only one parameter can take this value",
+                    ));
+                }
+                parameters.push(Parameter {
+                    ident: pat.ident.clone(),
+                    ty: (*typed.ty).clone(),
+                    source,
+                });
+            }
+        }
+    }
+    Ok(Function {
+        ident: method.sig.ident.clone(),
+        name,
+        receiver,
+        parameters,
+        output: method.sig.output.clone(),
+        docs: docs(&method.attrs),
+    })
+}
+
+/// Removes `#[definition]`, `#[prior_result]` or `#[variadic]` from a parameter, `&CallContext` is the context and
+/// anything else is the next argument.
+fn take_source(attrs: &mut Vec<Attribute>, ty: &Type) -> Result<Source, Error> {
+    let mut source = None;
+    let mut error = None;
+    attrs.retain(|attribute| {
+        let marked = [
+            ("definition", Source::Definition),
+            ("prior_result", Source::PriorResult),
+            ("variadic", Source::Variadic),
+        ]
+        .into_iter()
+        .find(|(name, _)| attribute.path().is_ident(name));
+        match (marked, source) {
+            (None, _) => true,
+            (Some((_, marked)), None) => {
+                source = Some(marked);
+                false
+            }
+            (Some(_), Some(_)) => {
+                error = Some(Error::new_spanned(
+                    attribute,
+                    "a parameter can only have one of #[definition], #[prior_result] or #[variadic]",
+                ));
+                false
+            }
+        }
+    });
+    if let Some(error) = error {
+        return Err(error);
+    }
+    match (source, ty) {
+        (None, Type::Reference(reference))
+            if last_segment(&reference.elem).is_some_and(|s| s.ident == "CallContext") =>
+        {
+            Ok(Source::Context)
+        }
+        (_, Type::Reference(_)) => Err(Error::new_spanned(
+            ty,
+            "only `&CallContext` can be borrowed, take an owned value like `String`",
+        )),
+        (Some(source), _) => Ok(source),
+        (None, _) => Ok(Source::Argument),
+    }
+}
+
+/// `///` comments, trimmed and joined by newlines
+fn docs(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter_map(|attribute| match &attribute.meta {
+            Meta::NameValue(doc) if doc.path.is_ident("doc") => match &doc.value {
+                Expr::Lit(lit) => match &lit.lit {
+                    Lit::Str(s) => Some(s.value().trim().to_string()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let docs = lines.join("\n").trim().to_string();
+    if docs.is_empty() {
+        None
+    } else {
+        Some(docs)
+    }
+}
+
+/// Match arm checking there aren't more arguments than parameters, converting them, calling the function and
+/// converting its result
+fn arm(function: &Function) -> TokenStream {
+    let name = &function.name;
+    let kebab = name.replace('_', "-");
+    let pattern = if kebab == *name {
+        quote!(#name)
+    } else {
+        quote!(#name | #kebab)
+    };
+
+    let values: Vec<Ident> = (0..function.parameters.len())
+        .map(|index| format_ident!("value_{}", index))
+        .collect();
+    let bindings = function
+        .parameters
+        .iter()
+        .zip(&values)
+        .map(|(parameter, value)| {
+            let argument = match parameter.source {
+                Source::Context => return quote!(let #value = context;),
+                Source::Argument => {
+                    quote!(arguments.next().unwrap_or(::rigz_core::Argument::None))
+                }
+                Source::Variadic => {
+                    quote!(::rigz_core::Argument::List(arguments.by_ref().collect()))
+                }
+                Source::Definition => quote! {
+                    match definition {
+                        ::rigz_core::Definition::None => ::rigz_core::Argument::None,
+                        d => ::rigz_core::Argument::Definition(d),
+                    }
+                },
+                Source::PriorResult => quote!(prior_result),
+            };
+            let ty = &parameter.ty;
+            let key = parameter.ident.to_string();
+            quote! {
+                let #value: #ty = match ::rigz_core::FromArgument::from_argument(#argument) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        return ::rigz_core::RuntimeStatus::Err(
+                            ::rigz_core::RigzError::from(e.at(#key)).with_function(#name),
+                        )
+                    }
+                };
+            }
+        });
+    let takes_arguments = function
+        .parameters
+        .iter()
+        .any(|p| matches!(p.source, Source::Argument | Source::Variadic));
+    let arguments = if takes_arguments {
+        quote!(let mut arguments = arguments.into_iter();)
+    } else {
+        quote!()
+    };
+    let max = function
+        .parameters
+        .iter()
+        .filter(|p| p.source == Source::Argument)
+        .count();
+    let variadic = function
+        .parameters
+        .iter()
+        .any(|p| p.source == Source::Variadic);
+    let arity = if variadic {
+        quote!()
+    } else {
+        let plural = if max == 1 { "" } else { "s" };
+        quote! {
+            if arguments.len() > #max {
+                return ::rigz_core::RuntimeStatus::Err(
+                    ::rigz_core::RigzError::new(
+                        ::rigz_core::ErrorKind::InvalidArgument,
+                        format!("expected at most {} argument{}, found {}", #max, #plural, arguments.len()),
+                    )
+                    .with_function(#name),
+                );
+            }
+        }
+    };
+
+    let ident = &function.ident;
+    let call = if function.receiver {
+        quote!(self.#ident(#(#values),*))
+    } else {
+        quote!(Self::#ident(#(#values),*))
+    };
+    let result = match &function.output {
+        ReturnType::Default => quote! {
+            #call;
+            ::rigz_core::RuntimeStatus::Ok(::rigz_core::Argument::None)
+        },
+        ReturnType::Type(_, ty) => match last_segment(ty).map(|s| s.ident.to_string()) {
+            Some(s) if s == "Result" => quote! {
+                match #call {
+                    Ok(v) => ::rigz_core::RuntimeStatus::Ok(::rigz_core::IntoArgument::into_argument(v)),
+                    Err(e) => ::rigz_core::RuntimeStatus::Err(
+                        ::std::convert::Into::<::rigz_core::RigzError>::into(e).with_function(#name),
+                    ),
+                }
+            },
+            Some(s) if s == "RuntimeStatus" => quote! {
+                match #call {
+                    ::rigz_core::RuntimeStatus::Ok(v) => {
+                        ::rigz_core::RuntimeStatus::Ok(::rigz_core::IntoArgument::into_argument(v))
+                    }
+                    ::rigz_core::RuntimeStatus::NotFound => ::rigz_core::RuntimeStatus::NotFound,
+                    ::rigz_core::RuntimeStatus::Err(e) => {
+                        ::rigz_core::RuntimeStatus::Err(e.with_function(#name))
+                    }
+                }
+            },
+            _ => {
+                quote!(::rigz_core::RuntimeStatus::Ok(::rigz_core::IntoArgument::into_argument(#call)))
+            }
+        },
+    };
+
+    quote! {
+        #pattern => {
+            #arity
+            #arguments
+            #(#bindings)*
+            #result
+        }
+    }
+}
+
+/// `FunctionSignature` built from the parameter and return types, `Any` for types the macro doesn't know. It has the
+/// exported name, the kebab-case form `function_call` also accepts isn't listed.
+fn signature(function: &Function) -> TokenStream {
+    let name = &function.name;
+    let parameters = function.parameters.iter().map(|parameter| {
+        let key = parameter.ident.to_string();
+        match parameter.source {
+            Source::Argument => match option_inner(&parameter.ty) {
+                Some(inner) => {
+                    let argument_type = argument_type(inner);
+                    quote!(.optional_parameter(#key, #argument_type))
+                }
+                None => {
+                    let argument_type = argument_type(&parameter.ty);
+                    quote!(.parameter(#key, #argument_type))
+                }
+            },
+            Source::Variadic => {
+                let element = match last_segment(&parameter.ty) {
+                    Some(segment) if segment.ident == "Vec" => first_generic(segment),
+                    _ => None,
+                };
+                let argument_type =
+                    element.map_or_else(|| quote!(::rigz_core::ArgumentType::Any), argument_type);
+                quote!(.variadic_parameter(#key, #argument_type))
+            }
+            Source::Definition => quote!(.with_definition()),
+            Source::PriorResult => quote!(.with_prior_result()),
+            Source::Context => quote!(),
+        }
+    });
+    let returns = match &function.output {
+        ReturnType::Default => {
+            let argument_type = argument_type(&syn::parse_quote!(()));
+            quote!(.returns(#argument_type))
+        }
+        ReturnType::Type(_, ty) => {
+            let ty = match last_segment(ty) {
+                Some(s) if s.ident == "Result" || s.ident == "RuntimeStatus" => {
+                    first_generic(s).unwrap_or(ty)
+                }
+                _ => ty,
+            };
+            match option_inner(ty) {
+                Some(inner) => {
+                    let argument_type = argument_type(inner);
+                    quote!(.returns_optional(#argument_type))
+                }
+                None => {
+                    let argument_type = argument_type(ty);
+                    quote!(.returns(#argument_type))
+                }
+            }
+        }
+    };
+    let docs = function.docs.as_ref().map(|docs| quote!(.docs(#docs)));
+    quote! {
+        ::rigz_core::FunctionSignature::new(#name)
+            #(#parameters)*
+            #returns
+            #docs
+    }
+}
+
+fn last_segment(ty: &Type) -> Option<&PathSegment> {
+    match ty {
+        Type::Path(path) => path.path.segments.last(),
+        _ => None,
+    }
+}
+
+fn first_generic(segment: &PathSegment) -> Option<&Type> {
+    match &segment.arguments {
+        PathArguments::AngleBracketed(generics) => {
+            generics.args.iter().find_map(|argument| match argument {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    match last_segment(ty) {
+        Some(segment) if segment.ident == "Option" => first_generic(segment),
+        _ => None,
+    }
+}
+
+fn argument_type(ty: &Type) -> TokenStream {
+    let variant = match ty {
+        Type::Tuple(tuple) if tuple.elems.is_empty() => "None",
+        Type::Reference(reference) => return argument_type(&reference.elem),
+        _ => match last_segment(ty) {
+            Some(segment) => match segment.ident.to_string().as_str() {
+                "i8" | "i16" | "i32" | "u8" | "u16" => "Int",
+                "i64" | "u32" | "u64" | "isize" | "usize" => "Long",
+                "f32" => "Float",
+                "f64" => "Double",
+                "bool" => "Bool",
+                "String" | "str" => "String",
+                "Decimal" => "Decimal",
+                "DateTime" => "DateTime",
+                "RigzFile" => "File",
+                "HashMap" | "BTreeMap" => "Object",
+                "Vec"
+                    if first_generic(segment)
+                        .and_then(last_segment)
+                        .is_some_and(|s| s.ident == "u8") =>
+                {
+                    "Any"
+                }
+                "Vec" => "List",
+                "FunctionCall" => "FunctionCall",
+                "Definition" => "Definition",
+                "RigzError" => "Error",
+                _ => "Any",
+            },
+            None => "Any",
+        },
+    };
+    let variant = Ident::new(variant, Span::call_site());
+    quote!(::rigz_core::ArgumentType::#variant)
+}
@@ -0,0 +1,22 @@
+use rigz_core::{export_module, rigz_function, rigz_module};
+
+struct Math;
+
+#[rigz_module(name = "math")]
+impl Math {
+    /// Adds `a` and `b`
+    #[rigz_function]
+    fn add(a: i64, b: i64) -> i64 {
+        a + b
+    }
+
+    #[rigz_function(name = "div")]
+    fn divide(a: f64, b: f64) -> Result<f64, &'static str> {
+        if b == 0.0 {
+            return Err("division by zero");
+        }
+        Ok(a / b)
+    }
+}
+
+export_module!(Math);
@@ -0,0 +1,55 @@
+/*
+ * C ABI for rigz modules built as shared libraries, loaded by modules with `type = "cdylib"` in their module.rigz.
+ *
+ * Values cross the boundary as NUL terminated UTF-8 JSON, in the serde representation of the rigz_core types:
+ * an `Argument` is an object with the variant as its key, like {"String": "hi"} or {"List": [{"Int": 1}]}, and
+ * `None` is the string "None".
+ *
+ * Strings returned by the library are freed by the runtime with `rigz_free`, the runtime's strings are only valid
+ * during the call. Calls can come from several threads at once.
+ */
+#ifndef RIGZ_MODULE_H
+#define RIGZ_MODULE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The runtime only loads libraries returning the version it was built with */
+#define RIGZ_ABI_VERSION 1
+
+/* Required, returns RIGZ_ABI_VERSION */
+uint32_t rigz_abi_version(void);
+
+/*
+ * Required, calls a function.
+ *
+ * request: {"name": "add", "arguments": [{"Int": 1}], "definition": "None", "prior_result": "None", "file": "main.rigz"}
+ * returns: {"Ok": {"Int": 2}}, "NotFound" if the module doesn't have the function, or
+ *          {"Err": {"kind": "runtime", "message": "..."}}
+ */
+char *rigz_function_call(const char *request);
+
+/*
+ * Optional, runs a lifecycle hook.
+ *
+ * request: {"hook": "initialize", "args": {...}}, {"hook": "on_file_start", "file": "main.rigz"},
+ *          {"hook": "on_file_end", "file": "main.rigz", "result": "None"}, {"hook": "shutdown"} or
+ *          {"hook": "reload", "args": {...}}
+ * returns: {"Ok": null}, "NotFound" or {"Err": {...}}
+ */
+char *rigz_hook(const char *request);
+
+/* Optional, a JSON list of the functions the module defines, see `FunctionSignature` */
+char *rigz_functions(void);
+
+/* Required, frees a string returned by the functions above */
+void rigz_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
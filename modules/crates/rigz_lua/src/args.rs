@@ -1,226 +1,272 @@
-use mlua::{Error, FromLua, IntoLua, Lua, Value};
-use rigz_core::{Argument, RigzFile};
+use crate::lua_error;
+use mlua::{
+    Error, FromLua, IntoLua, Lua, MetaMethod, UserData, UserDataFields, UserDataMethods, Value,
+};
+use rigz_core::{
+    Argument, ConversionError, DateTime, Decimal, Definition, FixedOffset, FromArgument,
+    FunctionCall, IntoArgument, Operation, RigzFile,
+};
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
+/// An `Argument` crossing the Lua boundary, anything `IntoArgument` goes in with `Arg::new`. Lua integers come back
+/// as `Long`, numbers as `Double` and tables as `Object`.
 #[derive(Clone, Debug, PartialEq)]
-pub(crate) enum Arg {
-    None,
-    Int(i32),
-    Long(i64),
-    Float(f32),
-    Double(f64),
-    Bool(bool),
-    String(String),
-    Object(HashMap<String, Arg>),
-    List(Vec<Arg>),
-    FunctionCall(FunctionCall),
-    Definition(Definition),
-    Error(String),
-    File(RigzFile),
+pub(crate) struct Arg(pub Argument);
+
+impl Arg {
+    pub(crate) fn new(value: impl IntoArgument) -> Self {
+        Arg(value.into_argument())
+    }
+}
+
+impl FromArgument for Arg {
+    fn from_argument(argument: Argument) -> Result<Self, ConversionError> {
+        Ok(Arg(argument))
+    }
 }
 
-impl FromLua<'_> for Arg {
-    fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
+impl IntoArgument for Arg {
+    fn into_argument(self) -> Argument {
+        self.0
+    }
+}
+
+impl<'lua> FromLua<'lua> for Arg {
+    fn from_lua(value: Value<'lua>, lua: &'lua Lua) -> mlua::Result<Self> {
         let arg = match value {
-            Value::Nil => Arg::None,
-            Value::Boolean(b) => Arg::Bool(b),
-            Value::Error(e) => Arg::Error(e.to_string()),
-            Value::Integer(i) => Arg::Long(i),
-            Value::Number(n) => Arg::Double(n),
-            Value::String(s) => Arg::String(s.to_str()?.to_string()),
+            Value::Nil => Argument::None,
+            Value::Boolean(b) => Argument::Bool(b),
+            Value::Error(e) => Argument::Error(lua_error(&e)),
+            Value::Integer(i) => Argument::Long(i),
+            Value::Number(n) => Argument::Double(n),
+            Value::String(s) => match s.to_str() {
+                Ok(s) => Argument::String(s.to_string()),
+                Err(_) => Argument::Bytes(s.as_bytes().to_vec()),
+            },
+            Value::UserData(u) => {
+                if let Ok(d) = u.borrow::<LuaDecimal>() {
+                    Argument::Decimal(d.0)
+                } else if let Ok(d) = u.borrow::<LuaDateTime>() {
+                    Argument::DateTime(d.0)
+                } else if let Ok(b) = u.borrow::<LuaBytes>() {
+                    Argument::Bytes(b.0.clone())
+                } else if let Ok(f) = u.borrow::<LuaFile>() {
+                    Argument::File(f.0.clone())
+                } else {
+                    return Err(Error::RuntimeError("Unsupported userdata".into()));
+                }
+            }
             Value::Table(t) => {
                 // TODO: check vec vs map
                 let mut results = HashMap::new();
                 for each in t.pairs() {
                     let (k, v): (String, Value) = each?;
-                    results.insert(k, Self::from_lua(v, lua)?);
+                    results.insert(k, lua.unpack::<Arg>(v)?.0);
                 }
-                Arg::Object(results)
+                Argument::Object(results)
             }
             // TODO - Value::LightUserData(_) => {}
-            // TODO - Value::UserData(_) => {}
             _ => return Err(Error::RuntimeError("Unsupported".into())),
         };
-        Ok(arg)
+        Ok(Arg(arg))
     }
 }
 
-impl IntoLua<'_> for Arg {
-    fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
-        let value = lua.scope(|_| {
-            Ok({
-                match self {
-                    Arg::None => Value::Nil,
-                    Arg::Int(i) => Value::Integer(i.into()),
-                    Arg::Long(l) => Value::Integer(l),
-                    Arg::Float(f) => Value::Number(f.into()),
-                    Arg::Double(d) => Value::Number(d),
-                    Arg::Bool(b) => Value::Boolean(b),
-                    Arg::String(s) => s.into_lua(lua)?,
-                    Arg::Object(o) => o.into_lua(lua)?,
-                    Arg::List(l) => l.into_lua(lua)?,
-                    Arg::FunctionCall(fc) => fc.into_lua(lua)?,
-                    Arg::Definition(c) => c.into_lua(lua)?,
-                    Arg::Error(e) => Value::Error(Error::RuntimeError(e)),
-                    _ => {
-                        let arg: Argument = self.into();
-                        Value::Error(Error::RuntimeError(format!(
-                            "{} is not implemented yet",
-                            arg
-                        )))
-                    }
-                }
-            })
-        })?;
+impl<'lua> IntoLua<'lua> for Arg {
+    fn into_lua(self, lua: &'lua Lua) -> mlua::Result<Value<'lua>> {
+        let value = match self.0 {
+            Argument::None => Value::Nil,
+            Argument::Int(i) => Value::Integer(i.into()),
+            Argument::Long(l) => Value::Integer(l),
+            Argument::Float(f) => Value::Number(f.into()),
+            Argument::Double(d) => Value::Number(d),
+            Argument::Bool(b) => Value::Boolean(b),
+            Argument::String(s) => s.into_lua(lua)?,
+            Argument::Bytes(b) => Value::UserData(lua.create_userdata(LuaBytes(b))?),
+            Argument::DateTime(d) => Value::UserData(lua.create_userdata(LuaDateTime(d))?),
+            Argument::Decimal(d) => Value::UserData(lua.create_userdata(LuaDecimal(d))?),
+            Argument::File(f) => Value::UserData(lua.create_userdata(LuaFile(f))?),
+            Argument::Object(o) => {
+                Value::Table(lua.create_table_from(o.into_iter().map(|(k, v)| (k, Arg(v))))?)
+            }
+            Argument::List(l) => Value::Table(lua.create_sequence_from(l.into_iter().map(Arg))?),
+            Argument::Definition(Definition::None) => Value::Nil,
+            Argument::Definition(Definition::One(o)) => Arg(Argument::Object(o)).into_lua(lua)?,
+            Argument::Definition(Definition::Many(l)) => Arg(Argument::List(l)).into_lua(lua)?,
+            Argument::FunctionCall(fc) => function_call(fc, lua)?,
+            Argument::Error(e) => Value::Error(Error::RuntimeError(e.to_string())),
+        };
         Ok(value)
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub(crate) enum Definition {
-    None,
-    Some(HashMap<String, Arg>),
-    Many(Vec<Arg>),
+/// `{ name, args, context }`, evaluated with `invoke(block.name, table.unpack(block.args))`
+fn function_call<'lua>(call: FunctionCall, lua: &'lua Lua) -> mlua::Result<Value<'lua>> {
+    let table = lua.create_table()?;
+    table.set("name", call.name)?;
+    table.set("args", Arg::new(call.args))?;
+    table.set("context", Arg::new(call.definition))?;
+    Ok(Value::Table(table))
 }
 
-impl From<rigz_core::Definition> for Definition {
-    fn from(value: rigz_core::Definition) -> Self {
-        match value {
-            rigz_core::Definition::None => Definition::None,
-            rigz_core::Definition::One(o) => Definition::Some(to_context(o)),
-            rigz_core::Definition::Many(l) => Definition::Many(to_args(l)),
-        }
-    }
-}
+/// `Decimal` in Lua. Arithmetic with integers, numeric strings and other decimals stays exact, floats aren't
+/// converted implicitly.
+pub(crate) struct LuaDecimal(pub Decimal);
 
-impl From<Definition> for rigz_core::Definition {
-    fn from(val: Definition) -> Self {
-        match val {
-            Definition::None => rigz_core::Definition::None,
-            Definition::Some(o) => rigz_core::Definition::One(to_object(o)),
-            Definition::Many(l) => rigz_core::Definition::Many(to_arguments(l)),
-        }
-    }
-}
+/// `DateTime` in Lua, compares with other date times and `tostring` gives RFC 3339
+pub(crate) struct LuaDateTime(pub DateTime<FixedOffset>);
 
-impl IntoLua<'_> for Definition {
-    fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
-        let value = lua.scope(|_| {
-            Ok(match self {
-                Definition::None => Value::Nil,
-                Definition::Some(o) => o.into_lua(lua)?,
-                Definition::Many(m) => m.into_lua(lua)?,
-            })
-        })?;
-        Ok(value)
-    }
-}
+/// `Bytes` in Lua, so bytes come back as `Bytes` even when they're valid UTF-8. `#b` and `b[i]` read them,
+/// `tostring(b)` is a Lua string of the same bytes.
+pub(crate) struct LuaBytes(pub Vec<u8>);
 
-#[derive(Clone, Debug, PartialEq)]
-pub(crate) struct FunctionCall {
-    pub name: String,
-    pub args: Vec<Arg>,
-    pub context: Definition,
-}
+/// `File` in Lua, `f.path` and `f.mode` read it and `tostring(f)` is its path. Lua only passes it along, reading and
+/// writing stay with the modules that were granted the file.
+pub(crate) struct LuaFile(pub RigzFile);
 
-impl From<rigz_core::FunctionCall> for FunctionCall {
-    fn from(value: rigz_core::FunctionCall) -> Self {
-        FunctionCall {
-            name: value.name,
-            args: to_args(value.args),
-            context: value.definition.into(),
+impl UserData for LuaDecimal {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        for (method, operation) in [
+            (MetaMethod::Add, Operation::Add),
+            (MetaMethod::Sub, Operation::Sub),
+            (MetaMethod::Mul, Operation::Mul),
+            (MetaMethod::Div, Operation::Div),
+            (MetaMethod::Mod, Operation::Rem),
+        ] {
+            methods.add_meta_function(method, move |lua, (a, b): (Value, Value)| {
+                let result = operand(&a)?
+                    .checked(operation, &operand(&b)?)
+                    .map_err(|e| Error::RuntimeError(e.to_string()))?;
+                Arg(result).into_lua(lua)
+            });
         }
+        methods.add_meta_method(MetaMethod::Unm, |_, d, ()| Ok(LuaDecimal(-d.0)));
+        methods.add_meta_method(MetaMethod::ToString, |_, d, ()| Ok(d.0.to_string()));
+        add_comparisons(methods);
     }
 }
 
-impl From<FunctionCall> for rigz_core::FunctionCall {
-    fn from(val: FunctionCall) -> Self {
-        rigz_core::FunctionCall {
-            name: val.name,
-            args: to_arguments(val.args),
-            definition: val.context.into(),
-        }
+impl UserData for LuaDateTime {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, d, ()| Ok(d.0.to_rfc3339()));
+        add_comparisons(methods);
     }
 }
 
-impl IntoLua<'_> for FunctionCall {
-    fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
-        let result = lua.scope(|_| {
-            let lua_args = lua.create_table()?;
-            Ok(Value::Table(lua_args))
-        })?;
-        Ok(result)
+impl UserData for LuaFile {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("path", |_, f| Ok(f.0.path().display().to_string()));
+        fields.add_field_method_get("mode", |_, f| Ok(f.0.mode().to_string()));
     }
-}
 
-impl From<Argument> for Arg {
-    fn from(value: Argument) -> Self {
-        match value {
-            Argument::None => Arg::None,
-            Argument::Int(i) => Arg::Int(i),
-            Argument::Long(l) => Arg::Long(l),
-            Argument::Float(f) => Arg::Float(f),
-            Argument::Double(d) => Arg::Double(d),
-            Argument::Bool(b) => Arg::Bool(b),
-            Argument::String(s) => Arg::String(s),
-            Argument::Object(o) => Arg::Object(to_context(o)),
-            Argument::List(l) => Arg::List(to_args(l)),
-            Argument::FunctionCall(f) => Arg::FunctionCall(f.into()),
-            Argument::Definition(d) => Arg::Definition(d.into()),
-            Argument::Error(e) => Arg::Error(e),
-            Argument::File(f) => Arg::File(f),
-        }
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::ToString, |_, f, ()| {
+            Ok(f.0.path().display().to_string())
+        });
     }
 }
 
-impl From<Arg> for Argument {
-    fn from(val: Arg) -> Self {
-        match val {
-            Arg::None => Argument::None,
-            Arg::Int(i) => Argument::Int(i),
-            Arg::Long(l) => Argument::Long(l),
-            Arg::Float(f) => Argument::Float(f),
-            Arg::Double(d) => Argument::Double(d),
-            Arg::Bool(b) => Argument::Bool(b),
-            Arg::String(s) => Argument::String(s),
-            Arg::Object(o) => Argument::Object(to_object(o)),
-            Arg::List(l) => Argument::List(to_arguments(l)),
-            Arg::FunctionCall(f) => Argument::FunctionCall(f.into()),
-            Arg::Definition(d) => Argument::Definition(d.into()),
-            Arg::Error(e) => Argument::Error(e),
-            Arg::File(f) => Argument::File(f),
-        }
+impl UserData for LuaBytes {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Len, |_, b, ()| Ok(b.0.len()));
+        methods.add_meta_method(MetaMethod::Index, |_, b, i: i64| {
+            Ok(usize::try_from(i - 1)
+                .ok()
+                .and_then(|i| b.0.get(i).copied()))
+        });
+        methods.add_meta_method(MetaMethod::ToString, |lua, b, ()| lua.create_string(&b.0));
+        methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (Value, Value)| {
+            Ok(operand(&a)? == operand(&b)?)
+        });
+        methods.add_meta_function(MetaMethod::Concat, |lua, (a, b): (Value, Value)| {
+            let mut bytes = raw_bytes(lua, a)?;
+            bytes.extend(raw_bytes(lua, b)?);
+            Ok(LuaBytes(bytes))
+        });
     }
 }
 
-pub(crate) fn to_arguments(args: Vec<Arg>) -> Vec<Argument> {
-    let mut v = Vec::with_capacity(args.len());
-    for arg in args {
-        v.push(arg.into());
-    }
-    v
+/// `==`, `<` and `<=` between numbers or between date times, Lua only calls `==` when both sides are userdata
+fn add_comparisons<'lua, T: UserData, M: UserDataMethods<'lua, T>>(methods: &mut M) {
+    methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (Value, Value)| {
+        Ok(compare(&a, &b)? == Ordering::Equal)
+    });
+    methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (Value, Value)| {
+        Ok(compare(&a, &b)? == Ordering::Less)
+    });
+    methods.add_meta_function(MetaMethod::Le, |_, (a, b): (Value, Value)| {
+        Ok(compare(&a, &b)? != Ordering::Greater)
+    });
+    methods.add_meta_function(MetaMethod::Concat, |lua, (a, b): (Value, Value)| {
+        let mut bytes = raw_bytes(lua, a)?;
+        bytes.extend(raw_bytes(lua, b)?);
+        lua.create_string(bytes)
+    });
 }
 
-pub(crate) fn to_args(args: Vec<Argument>) -> Vec<Arg> {
-    let mut v = Vec::with_capacity(args.len());
-    for arg in args {
-        v.push(arg.into());
+fn compare(a: &Value, b: &Value) -> mlua::Result<Ordering> {
+    let (a, b) = (operand(a)?, operand(b)?);
+    let number = |a: &Argument| {
+        matches!(
+            a,
+            Argument::Long(_) | Argument::Double(_) | Argument::Decimal(_)
+        )
+    };
+    match (&a, &b) {
+        (Argument::DateTime(_), Argument::DateTime(_)) => Ok(a.cmp(&b)),
+        (a, b) if number(a) && number(b) => Ok(a.cmp(b)),
+        _ => Err(Error::RuntimeError(format!(
+            "attempt to compare {} with {}",
+            a.variant_name(),
+            b.variant_name()
+        ))),
     }
-    v
 }
 
-pub(crate) fn to_object(args: HashMap<String, Arg>) -> HashMap<String, Argument> {
-    let mut v = HashMap::with_capacity(args.len());
-    for (k, arg) in args {
-        v.insert(k, arg.into());
-    }
-    v
+/// Operand of a metamethod, strings are read as decimals
+fn operand(value: &Value) -> mlua::Result<Argument> {
+    let argument = match value {
+        Value::Integer(i) => Argument::Long(*i),
+        Value::Number(n) => Argument::Double(*n),
+        Value::String(s) => match s.to_str().ok().and_then(|s| s.trim().parse().ok()) {
+            Some(d) => Argument::Decimal(d),
+            None => Argument::Bytes(s.as_bytes().to_vec()),
+        },
+        Value::UserData(u) => {
+            if let Ok(d) = u.borrow::<LuaDecimal>() {
+                Argument::Decimal(d.0)
+            } else if let Ok(d) = u.borrow::<LuaDateTime>() {
+                Argument::DateTime(d.0)
+            } else if let Ok(b) = u.borrow::<LuaBytes>() {
+                Argument::Bytes(b.0.clone())
+            } else {
+                return Err(Error::RuntimeError("Unsupported userdata".into()));
+            }
+        }
+        v => {
+            return Err(Error::RuntimeError(format!(
+                "Unsupported operand {}",
+                v.type_name()
+            )))
+        }
+    };
+    Ok(argument)
 }
 
-pub(crate) fn to_context(args: HashMap<String, Argument>) -> HashMap<String, Arg> {
-    let mut v = HashMap::with_capacity(args.len());
-    for (k, arg) in args {
-        v.insert(k, arg.into());
+/// Bytes of a `..` operand, the same as `tostring`
+fn raw_bytes(lua: &Lua, value: Value) -> mlua::Result<Vec<u8>> {
+    if let Value::UserData(_) = &value {
+        return match operand(&value)? {
+            Argument::Bytes(b) => Ok(b),
+            a => Ok(a.to_string().into_bytes()),
+        };
+    }
+    match lua.coerce_string(value.clone())? {
+        Some(s) => Ok(s.as_bytes().to_vec()),
+        None => Err(Error::RuntimeError(format!(
+            "attempt to concatenate a {} value",
+            value.type_name()
+        ))),
     }
-    v
 }
@@ -1,16 +1,21 @@
 mod args;
+mod pool;
+mod sandbox;
 
-use std::cmp::max;
-use crate::args::{to_args, Arg, Definition};
+use crate::args::Arg;
+pub use crate::pool::LuaPool;
 use anyhow::anyhow;
 use log::{debug, info, warn};
-use mlua::{Function, Lua, Value, Variadic};
-use rigz_core::{Argument, InitializationArgs, Module, RuntimeStatus};
+use mlua::{Function, IntoLuaMulti, Lua, LuaSerdeExt, Table, Value, Variadic};
+use rigz_core::{
+    Argument, ArgumentType, CallContext, DefinitionSchema, ErrorKind, FunctionSignature,
+    InitializationArgs, Module, ModulePermissions, PermissionDenied, RigzError, RuntimeStatus,
+};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Copy, Clone, Debug, Default, Deserialize)]
 pub enum FunctionFormat {
@@ -38,20 +43,25 @@ pub struct LuaModule {
     pub(crate) lua: Lua,
     pub(crate) source_files: Vec<PathBuf>,
     pub(crate) input_files: HashMap<String, Vec<File>>,
+    /// Enforced by the sandbox `initialize` installs
+    pub(crate) permissions: ModulePermissions,
 }
 
-fn inspect(lua: &Lua, value: Value) -> mlua::Result<String> {
+fn inspect(_: &Lua, value: Value) -> mlua::Result<String> {
+    inspect_value(value)
+}
+
+fn inspect_value(value: Value) -> mlua::Result<String> {
     let result = match value {
         Value::Table(t) => {
             let mut result = String::new();
             let len = t.clone().pairs::<Value, Value>().count();
             let pairs = t.pairs::<Value, Value>();
-            let mut index = 1;
             let mut is_array = false;
-            for pair in pairs {
+            for (index, pair) in (1..).zip(pairs) {
                 let (key, value) = pair?;
                 result.push(' ');
-                let key_str = inspect(lua, key)?;
+                let key_str = inspect_value(key)?;
 
                 if index == 1 {
                     if key_str.as_str() == "1" {
@@ -65,11 +75,10 @@ fn inspect(lua: &Lua, value: Value) -> mlua::Result<String> {
                     result.push_str(key_str.as_str());
                     result.push_str(" = ");
                 }
-                result.push_str(inspect(lua, value)?.as_str());
+                result.push_str(inspect_value(value)?.as_str());
                 if index < len {
                     result.push(',');
                 }
-                index += 1;
             }
 
             if is_array {
@@ -84,31 +93,34 @@ fn inspect(lua: &Lua, value: Value) -> mlua::Result<String> {
     Ok(result)
 }
 
+/// `config.function_format`, the default if it's missing or invalid
+pub(crate) fn function_format(config: Option<serde_value::Value>) -> FunctionFormat {
+    match config {
+        None => FunctionFormat::default(),
+        Some(f) => {
+            if let serde_value::Value::Map(mut map) = f {
+                match map.remove(&serde_value::Value::String("function_format".into())) {
+                    None => FunctionFormat::default(),
+                    Some(f) => f.deserialize_into().unwrap_or(FunctionFormat::default()),
+                }
+            } else {
+                FunctionFormat::default()
+            }
+        }
+    }
+}
+
 impl LuaModule {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(
         name: String,
         module_root: PathBuf,
         source_files: Vec<PathBuf>,
         input_files: HashMap<String, Vec<File>>,
         config: Option<serde_value::Value>,
+        permissions: ModulePermissions,
     ) -> Box<dyn Module> {
-        let function_format = match config {
-            None => FunctionFormat::default(),
-            Some(f) => {
-                if let serde_value::Value::Map(mut map) = f {
-                    let f = map.remove(&serde_value::Value::String("function_format".into()));
-                    if f.is_none() {
-                        FunctionFormat::default()
-                    } else {
-                        f.unwrap()
-                            .deserialize_into()
-                            .unwrap_or(FunctionFormat::default())
-                    }
-                } else {
-                    FunctionFormat::default()
-                }
-            }
-        };
+        let function_format = function_format(config);
         Box::new(LuaModule {
             name,
             function_format,
@@ -116,22 +128,26 @@ impl LuaModule {
             input_files,
             lua: Lua::new(),
             source_files,
+            permissions,
         })
     }
 
+    /// Besides its arguments, the function's table has the `file` and `line` being run, `invoke(name, ...)` to call
+    /// any rigz function and `get_variable(name)`/`set_variable(name, value)` for the file's scope.
     pub(crate) fn invoke_function(
         &self,
         name: &str,
-        args: Vec<Arg>,
-        context: Definition,
-        previous_value: Arg,
-    ) -> RuntimeStatus<Arg> {
+        args: Vec<Argument>,
+        context: rigz_core::Definition,
+        previous_value: Argument,
+        call_context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
         let lua = &self.lua;
         let table = lua.globals();
         
         lua
-            .scope(|_| {
-                let function: Function = match table.get::<_, Function>(name) {
+            .scope(|scope| {
+                let function: Function = match find_function(&table, name) {
                     Ok(f) => f,
                     Err(e) => {
                         warn!("Function Not Found: {} - {}", name, e);
@@ -143,15 +159,62 @@ impl LuaModule {
                     FunctionFormat::StructFunction => {
                         let table = lua.create_table()?;
                         table.set("name", name)?;
-                        table.set("args", args)?;
-                        table.set("previous_value", previous_value)?;
-                        table.set("context", context)?;
-                        RuntimeStatus::Ok(function.call(table)?)
+                        table.set("args", Arg::new(args))?;
+                        table.set("previous_value", Arg(previous_value))?;
+                        table.set("context", Arg::new(context))?;
+                        table.set("file", call_context.file)?;
+                        table.set("line", call_context.span.as_ref().map(|s| s.line))?;
+                        table.set(
+                            "invoke",
+                            scope.create_function(|_, (name, args): (String, Variadic<Arg>)| {
+                                let args = args.into_iter().map(|a| a.0).collect();
+                                invoke_symbol(call_context, &name, args)
+                            })?,
+                        )?;
+                        table.set(
+                            "get_variable",
+                            scope.create_function(|_, name: String| {
+                                Ok(call_context.scope.get(&name).map(Arg))
+                            })?,
+                        )?;
+                        table.set(
+                            "set_variable",
+                            scope.create_function(|_, (name, value): (String, Arg)| {
+                                call_context.scope.set(name, value.0);
+                                Ok(())
+                            })?,
+                        )?;
+                        RuntimeStatus::Ok(function.call::<_, Arg>(table)?.0)
                     }
                 };
                 Ok(status)
             })
-            .unwrap_or(RuntimeStatus::Err("Lua Execution Failed".to_string()))
+            .unwrap_or_else(|e| {
+                RuntimeStatus::Err(
+                    RigzError::runtime("Lua Execution Failed").with_cause(lua_error(&e)),
+                )
+            })
+    }
+
+    /// Calls the global Lua function `hook` if the module's sources define one.
+    pub(crate) fn call_hook<'lua>(
+        &'lua self,
+        hook: &str,
+        args: impl IntoLuaMulti<'lua>,
+    ) -> RuntimeStatus<()> {
+        let function = match self.lua.globals().get::<_, Value>(hook) {
+            Ok(Value::Function(f)) => f,
+            _ => return RuntimeStatus::Ok(()),
+        };
+        match function.call::<_, ()>(args) {
+            Ok(()) => RuntimeStatus::Ok(()),
+            Err(e) => RuntimeStatus::Err(
+                RigzError::runtime(format!("{} failed", hook))
+                    .with_module(&self.name)
+                    .with_function(hook)
+                    .with_cause(lua_error(&e)),
+            ),
+        }
     }
 
     fn load_source_files(&self) -> anyhow::Result<()> {
@@ -172,7 +235,7 @@ impl LuaModule {
             let contents = load_file(file)?;
             match self.lua.scope(|_| {
                 let global = self.lua.globals();
-                let chunk = self.lua.load(contents);
+                let chunk = self.lua.load(contents).set_name(chunk_name(file));
                 chunk.exec()?;
                 global.set("__module_name", self.name.as_str())?;
                 Ok(())
@@ -193,6 +256,169 @@ impl LuaModule {
     }
 }
 
+/// `invoke` for Lua, errors and functions no module defines are raised as Lua errors holding the `RigzError`, which
+/// `lua_error` gets back if Lua doesn't catch it
+fn invoke_symbol(context: &CallContext, name: &str, args: Vec<Argument>) -> mlua::Result<Arg> {
+    match context.invoke_symbol(name, args, rigz_core::Definition::None, &Argument::None) {
+        RuntimeStatus::Ok(a) => Ok(Arg(a)),
+        RuntimeStatus::NotFound => Err(mlua::Error::external(
+            RigzError::new(
+                ErrorKind::NotFound,
+                format!("Failed to find function - {}", name),
+            )
+            .with_function(name),
+        )),
+        RuntimeStatus::Err(e) => Err(mlua::Error::external(e)),
+    }
+}
+
+/// rigz identifiers can contain `-` (`max-retries`), which isn't valid in a Lua name. When no global matches the
+/// original name it's looked up with `-` replaced by `_`; Unicode names are used as is, `_G["größe"]`.
+fn find_function<'lua>(globals: &Table<'lua>, name: &str) -> mlua::Result<Function<'lua>> {
+    match globals.get::<_, Function>(name) {
+        Ok(f) => Ok(f),
+        Err(e) => {
+            if name.contains('-') {
+                globals.get::<_, Function>(name.replace('-', "_"))
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Keeps Lua's message, errors raised in a Rust callback also keep the traceback in `data`.
+pub(crate) fn lua_error(error: &mlua::Error) -> RigzError {
+    match error {
+        mlua::Error::CallbackError { traceback, cause } => {
+            lua_error(cause).with_data("traceback", Argument::String(traceback.clone()))
+        }
+        mlua::Error::RuntimeError(message) => RigzError::runtime(message.clone()),
+        mlua::Error::SyntaxError { message, .. } => {
+            RigzError::new(ErrorKind::Parse, message.clone())
+        }
+        mlua::Error::ExternalError(e) => {
+            if let Some(error) = e.downcast_ref::<RigzError>() {
+                return error.clone();
+            }
+            match e.downcast_ref::<PermissionDenied>() {
+                Some(denied) => denied.clone().into(),
+                None => RigzError::runtime(e.to_string()),
+            }
+        }
+        mlua::Error::FromLuaConversionError { .. } | mlua::Error::ToLuaConversionError { .. } => {
+            RigzError::new(ErrorKind::InvalidArgument, error.to_string())
+        }
+        e => RigzError::runtime(e.to_string()),
+    }
+}
+
+/// `@path`, Lua's convention for chunks loaded from a file, `functions` matches it against `FunctionInfo::source`.
+fn chunk_name(path: &Path) -> String {
+    format!("@{}", path.to_string_lossy())
+}
+
+/// `--` comment lines directly above `line`, 1-based, with the leading dashes removed.
+fn doc_comments(source: &str, line: usize) -> Vec<String> {
+    let lines: Vec<&str> = source.lines().take(line.saturating_sub(1)).collect();
+    let mut comments: Vec<String> = lines
+        .into_iter()
+        .rev()
+        .map(str::trim)
+        .take_while(|l| l.starts_with("--"))
+        .map(|l| l.trim_start_matches('-').trim().to_string())
+        .collect();
+    comments.reverse();
+    comments
+}
+
+fn lua_type(name: &str) -> ArgumentType {
+    if name.ends_with("[]") {
+        return ArgumentType::List;
+    }
+    match name {
+        "number" => ArgumentType::Double,
+        "integer" => ArgumentType::Long,
+        "boolean" => ArgumentType::Bool,
+        "table" => ArgumentType::Object,
+        "nil" => ArgumentType::None,
+        n => n.parse().unwrap_or_default(),
+    }
+}
+
+/// Builds a signature from LuaLS style annotations, `@param name type` for each element of `args.args` (`@param ...`
+/// for any number of them), `@field name type` for each key of `args.context`, and `@return type` (`type?` if it can
+/// return `nil`). `@definition`/`@prior_result` mark functions that read `context` or `previous_value`. Without any
+/// `@param` the arguments aren't checked. Any other line is part of the docs.
+fn lua_signature(name: String, comments: Vec<String>) -> FunctionSignature {
+    let mut signature = FunctionSignature::new(name);
+    let mut schema = None;
+    let mut has_params = false;
+    let mut docs = Vec::new();
+    for comment in comments {
+        let mut parts = comment.split_whitespace();
+        match parts.next() {
+            Some("@param") => {
+                has_params = true;
+                let (name, kind, optional) = annotated(&mut parts);
+                signature = if name == "..." {
+                    signature.variadic()
+                } else if optional {
+                    signature.optional_parameter(name, kind)
+                } else {
+                    signature.parameter(name, kind)
+                };
+            }
+            Some("@field") => {
+                let (name, kind, optional) = annotated(&mut parts);
+                let fields = schema.take().unwrap_or_else(DefinitionSchema::new);
+                schema = Some(if optional {
+                    fields.optional_field(name, kind)
+                } else {
+                    fields.field(name, kind)
+                });
+            }
+            Some("@return") => {
+                let kind = parts.next().unwrap_or("any");
+                let argument_type = lua_type(kind.trim_end_matches('?'));
+                signature = if kind.ends_with('?') {
+                    signature.returns_optional(argument_type)
+                } else {
+                    signature.returns(argument_type)
+                };
+            }
+            Some("@definition") => signature = signature.with_definition(),
+            Some("@prior_result") => signature = signature.with_prior_result(),
+            Some(tag) if tag.starts_with('@') => {}
+            _ => docs.push(comment),
+        }
+    }
+    if !has_params {
+        signature = signature.variadic();
+    }
+    if let Some(schema) = schema {
+        signature = signature.with_schema(schema);
+    }
+    let docs = docs.join("\n");
+    if docs.trim().is_empty() {
+        signature
+    } else {
+        signature.docs(docs.trim())
+    }
+}
+
+/// `name type` of a `@param` or `@field`, optional if either ends with `?`
+fn annotated<'a>(parts: &mut impl Iterator<Item = &'a str>) -> (&'a str, ArgumentType, bool) {
+    let name = parts.next().unwrap_or("arg");
+    let kind = parts.next().unwrap_or("any");
+    let optional = name.ends_with('?') || kind.ends_with('?');
+    (
+        name.trim_end_matches('?'),
+        lua_type(kind.trim_end_matches('?')),
+        optional,
+    )
+}
+
 fn load_file(path_buf: &PathBuf) -> anyhow::Result<String> {
     let mut contents = String::new();
     let mut file = File::open(path_buf)?;
@@ -200,6 +426,9 @@ fn load_file(path_buf: &PathBuf) -> anyhow::Result<String> {
     Ok(contents)
 }
 
+/// Lua functions called by the runtime instead of from rigz
+const HOOKS: [&str; 4] = ["on_file_start", "on_file_end", "shutdown", "reload"];
+
 impl Module for LuaModule {
     fn name(&self) -> &str {
         self.name.as_str()
@@ -215,23 +444,40 @@ impl Module for LuaModule {
         arguments: Vec<Argument>,
         definition: rigz_core::Definition,
         prior_result: Argument,
+        context: &CallContext,
     ) -> RuntimeStatus<Argument> {
-        match self.invoke_function(
-            name,
-            to_args(arguments),
-            definition.into(),
-            prior_result.into(),
-        ) {
-            RuntimeStatus::Ok(a) => RuntimeStatus::Ok(a.into()),
+        match self.invoke_function(name, arguments, definition, prior_result, context) {
+            RuntimeStatus::Ok(Argument::Error(e)) => RuntimeStatus::Ok(Argument::Error(
+                e.with_module(&self.name).with_function(name),
+            )),
+            RuntimeStatus::Ok(a) => RuntimeStatus::Ok(a),
             RuntimeStatus::NotFound => RuntimeStatus::NotFound,
-            RuntimeStatus::Err(e) => RuntimeStatus::Err(e),
+            RuntimeStatus::Err(e) => {
+                RuntimeStatus::Err(e.with_module(&self.name).with_function(name))
+            }
         }
     }
 
-    fn initialize(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+    /// Installs the sandbox before the source files run, so code at their top level is checked too.
+    fn initialize(&self, _args: InitializationArgs) -> RuntimeStatus<()> {
+        if let Err(e) = sandbox::install(&self.lua, &self.permissions) {
+            return RuntimeStatus::Err(
+                RigzError::new(ErrorKind::Initialization, "Failed to install sandbox")
+                    .with_module(&self.name)
+                    .with_cause(lua_error(&e)),
+            );
+        }
         match self.load_source_files() {
             Ok(_) => {}
-            Err(e) => return RuntimeStatus::Err(format!("Failed to load source files - {}", e)),
+            Err(e) => {
+                return RuntimeStatus::Err(
+                    RigzError::new(
+                        ErrorKind::Initialization,
+                        format!("Failed to load source files - {}", e),
+                    )
+                    .with_module(&self.name),
+                )
+            }
         };
 
 
@@ -246,14 +492,88 @@ impl Module for LuaModule {
             Ok(())
         }) {
             Ok(_) => RuntimeStatus::Ok(()),
-            Err(e) => RuntimeStatus::Err(format!("Initialization Failed: {} - {}", self.name, e)),
+            Err(e) => RuntimeStatus::Err(
+                RigzError::new(ErrorKind::Initialization, "Initialization Failed")
+                    .with_module(&self.name)
+                    .with_cause(lua_error(&e)),
+            ),
         }
     }
+
+    fn on_file_start(&self, file: &str) -> RuntimeStatus<()> {
+        self.call_hook("on_file_start", file)
+    }
+
+    fn on_file_end(&self, file: &str, result: &Argument) -> RuntimeStatus<()> {
+        self.call_hook("on_file_end", (file, Arg(result.clone())))
+    }
+
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        self.call_hook("shutdown", ())
+    }
+
+    /// Runs the source files again, then the Lua `reload` function if there is one with `args` as a table. Globals
+    /// the sources don't set keep their values.
+    fn reload(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        if let Err(e) = self.load_source_files() {
+            return RuntimeStatus::Err(
+                RigzError::new(
+                    ErrorKind::Initialization,
+                    format!("Failed to reload source files - {}", e),
+                )
+                .with_module(&self.name),
+            );
+        }
+        match self.lua.to_value(&args) {
+            Ok(args) => self.call_hook("reload", args),
+            Err(e) => RuntimeStatus::Err(
+                RigzError::runtime("reload failed")
+                    .with_module(&self.name)
+                    .with_function("reload")
+                    .with_cause(lua_error(&e)),
+            ),
+        }
+    }
+
+    /// Global Lua functions defined by the module's source files, documented with the annotations in `lua_signature`.
+    /// Lifecycle hooks like `on_file_start` and the sandbox's `loadfile` and `dofile` aren't included.
+    fn functions(&self) -> Vec<FunctionSignature> {
+        let sources: HashMap<String, String> = self
+            .source_files
+            .iter()
+            .filter_map(|f| load_file(f).ok().map(|c| (chunk_name(f), c)))
+            .collect();
+        let mut functions: Vec<FunctionSignature> = self
+            .lua
+            .globals()
+            .pairs::<String, Value>()
+            .filter_map(|pair| match pair {
+                Ok((name, Value::Function(function))) => Some((name, function.info())),
+                _ => None,
+            })
+            .filter(|(name, info)| {
+                info.what == "Lua"
+                    && !HOOKS.contains(&name.as_str())
+                    && info.source.as_deref() != Some(sandbox::CHUNK_NAME)
+            })
+            .map(|(name, info)| {
+                let source = info.source.and_then(|s| sources.get(&s));
+                let comments = match (source, info.line_defined) {
+                    (Some(source), Some(line)) => doc_comments(source, line),
+                    _ => Vec::new(),
+                };
+                lua_signature(name, comments)
+            })
+            .collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rigz_core::Scope;
 
     #[test]
     fn it_works() {
@@ -263,6 +583,7 @@ mod tests {
             vec![],
             Default::default(),
             None,
+            Default::default(),
         );
 
         let result = module.function_call(
@@ -270,7 +591,615 @@ mod tests {
             vec![Argument::String("Hello World".into())],
             rigz_core::Definition::None,
             Argument::None,
+            &CallContext::detached(&Scope::default()),
         );
         assert_eq!(result, RuntimeStatus::Ok(Argument::None));
     }
+
+    #[test]
+    fn kebab_case_uses_snake_case_function() {
+        let module = LuaModule {
+            name: "kebab".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load("function max_retries(args) return 3 end")
+            .exec()
+            .expect("Failed to load function");
+
+        let result = module.function_call(
+            "max-retries",
+            vec![],
+            rigz_core::Definition::None,
+            Argument::None,
+            &CallContext::detached(&Scope::default()),
+        );
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Long(3)));
+    }
+
+    #[test]
+    fn lua_errors_name_module_and_function() {
+        let module = LuaModule {
+            name: "failing".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load("function fail(args) error('boom') end")
+            .exec()
+            .expect("Failed to load function");
+
+        let scope = Scope::default();
+        let context = CallContext::detached(&scope);
+        let error = match module.function_call(
+            "fail",
+            vec![],
+            rigz_core::Definition::None,
+            Argument::None,
+            &context,
+        ) {
+            RuntimeStatus::Err(e) => e,
+            s => panic!("expected an error, got {:?}", s),
+        };
+        assert_eq!(error.module.as_deref(), Some("failing"));
+        assert_eq!(error.function.as_deref(), Some("fail"));
+        let cause = error.cause.expect("missing cause");
+        assert_eq!(cause.kind, rigz_core::ErrorKind::Runtime);
+        assert!(cause.message.contains("boom"), "{}", cause.message);
+    }
+
+    #[test]
+    fn bytes_date_times_and_decimals_cross_into_lua() {
+        let module = LuaModule {
+            name: "values".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load(
+                r#"
+                function append(input) return input.args[1] .. "\255" end
+                function first(input) return input.args[1] end
+                function add(input) return input.args[1] + 1 end
+                function total(input) return (input.args[1] - "0.99") * 3 / 2 end
+                function label(input) return "$" .. input.args[1] end
+                function over(input) return input.args[1] > 10 and input.args[1] <= "19.99" end
+                function size(input) return #input.args[1] * 1000 + input.args[1][2] end
+                "#,
+            )
+            .exec()
+            .expect("Failed to load functions");
+        let scope = Scope::default();
+        let context = CallContext::detached(&scope);
+        let call = |name: &str, argument: Argument| {
+            module.function_call(
+                name,
+                vec![argument],
+                rigz_core::Definition::None,
+                Argument::None,
+                &context,
+            )
+        };
+
+        let result = call("append", Argument::Bytes(vec![0]));
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Bytes(vec![0, 255])));
+        // bytes stay bytes even when they're valid UTF-8
+        let result = call("first", Argument::Bytes(b"hi".to_vec()));
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Bytes(b"hi".to_vec())));
+        let result = call("size", Argument::Bytes(b"hi".to_vec()));
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Long(2105)));
+
+        let date = rigz_core::DateTime::parse_from_rfc3339("2024-05-01T12:00:00+02:00").unwrap();
+        let result = call("first", Argument::DateTime(date));
+        assert_eq!(result, RuntimeStatus::Ok(Argument::DateTime(date)));
+
+        // decimal arithmetic stays exact
+        let price = || Argument::Decimal("19.99".parse().unwrap());
+        let result = call("add", price());
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::Decimal("20.99".parse().unwrap()))
+        );
+        let result = call("total", price());
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::Decimal("28.5".parse().unwrap()))
+        );
+        let result = call("label", price());
+        assert_eq!(result, RuntimeStatus::Ok(Argument::String("$19.99".into())));
+        let result = call("over", price());
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Bool(true)));
+    }
+
+    #[test]
+    fn files_cross_into_lua() {
+        let module = LuaModule {
+            name: "files".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load(
+                r#"
+                function first(input) return input.args[1] end
+                function describe(input) return input.args[1].mode .. " " .. tostring(input.args[1]) end
+                "#,
+            )
+            .exec()
+            .expect("Failed to load functions");
+        let scope = Scope::default();
+        let context = CallContext::detached(&scope);
+        let call = |name: &str, argument: Argument| {
+            module.function_call(
+                name,
+                vec![argument],
+                rigz_core::Definition::None,
+                Argument::None,
+                &context,
+            )
+        };
+        let file = || rigz_core::RigzFile::new("report.csv").with_mode(rigz_core::FileMode::Append);
+
+        let result = call("first", Argument::File(file()));
+        assert_eq!(result, RuntimeStatus::Ok(Argument::File(file())));
+        let result = call("describe", Argument::File(file()));
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::String("append report.csv".into()))
+        );
+    }
+
+    struct Upper;
+
+    impl rigz_core::Invoker for Upper {
+        fn invoke(
+            &self,
+            name: &str,
+            arguments: Vec<Argument>,
+            _definition: rigz_core::Definition,
+            _prior_result: &Argument,
+            _context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            match name {
+                "upper" => {
+                    RuntimeStatus::Ok(Argument::String(arguments[0].to_string().to_uppercase()))
+                }
+                "fail" => RuntimeStatus::Err(
+                    RigzError::new(ErrorKind::InvalidArgument, "bad input")
+                        .with_module("upper")
+                        .with_function("fail"),
+                ),
+                _ => RuntimeStatus::NotFound,
+            }
+        }
+    }
+
+    #[test]
+    fn functions_call_back_through_the_context() {
+        let module = LuaModule {
+            name: "composite".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load(
+                r#"
+                function shout(input)
+                    input.set_variable("said", input.args[1])
+                    return input.invoke("upper", input.args[1]) .. " at " .. input.file .. ":" .. input.line
+                end
+                function said(input) return input.get_variable("said") end
+                function run(input) local block = input.args[1] return input.invoke(block.name, table.unpack(block.args)) end
+                function missing(input) return input.invoke("nope") end
+                function broken(input) return input.invoke("fail") end
+                "#,
+            )
+            .exec()
+            .expect("Failed to load functions");
+        let scope = Scope::default();
+        let context = CallContext::new(&Upper, &scope, InitializationArgs::default())
+            .with_file("main.rigz")
+            .with_span(rigz_core::SourceSpan {
+                file: "main.rigz".to_string(),
+                start: 10,
+                end: 15,
+                line: 3,
+                column: 1,
+            });
+        let call = |name: &str, arguments: Vec<Argument>| {
+            module.function_call(
+                name,
+                arguments,
+                rigz_core::Definition::None,
+                Argument::None,
+                &context,
+            )
+        };
+
+        let result = call("shout", vec![Argument::String("hi".into())]);
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::String("HI at main.rigz:3".into()))
+        );
+        assert_eq!(
+            call("said", vec![]),
+            RuntimeStatus::Ok(Argument::String("hi".into()))
+        );
+
+        let block = Argument::FunctionCall(rigz_core::FunctionCall {
+            name: "upper".into(),
+            args: vec![Argument::String("lazy".into())],
+            definition: rigz_core::Definition::None,
+        });
+        assert_eq!(
+            call("run", vec![block]),
+            RuntimeStatus::Ok(Argument::String("LAZY".into()))
+        );
+
+        let error = match call("missing", vec![]) {
+            RuntimeStatus::Err(e) => e,
+            s => panic!("expected an error, got {:?}", s),
+        };
+        let cause = error.cause.expect("missing cause");
+        assert_eq!(cause.kind, ErrorKind::NotFound);
+        assert_eq!(cause.message, "Failed to find function - nope");
+
+        // errors from invoked functions come back as they were
+        let error = match call("broken", vec![]) {
+            RuntimeStatus::Err(e) => e,
+            s => panic!("expected an error, got {:?}", s),
+        };
+        let cause = *error.cause.expect("missing cause");
+        assert_eq!(cause.kind, ErrorKind::InvalidArgument);
+        assert_eq!(cause.message, "bad input");
+        assert_eq!(cause.module.as_deref(), Some("upper"));
+        assert_eq!(cause.function.as_deref(), Some("fail"));
+        assert!(cause.data.contains_key("traceback"));
+    }
+
+    #[test]
+    fn hooks_call_lua_functions() {
+        let module = LuaModule {
+            name: "buffered".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        module
+            .lua
+            .load(
+                r#"
+                ended = {}
+                function on_file_end(file, result) ended[#ended + 1] = file .. "=" .. tostring(result) end
+                function shutdown() error("flush failed") end
+                function reload(args) reloaded = args.require_aliases end
+                function ended_files(args) return table.concat(ended, ",") end
+                function was_reloaded(args) return reloaded end
+                "#,
+            )
+            .exec()
+            .expect("Failed to load functions");
+
+        assert_eq!(module.on_file_start("a.rigz"), RuntimeStatus::Ok(()));
+        let result = module.on_file_end("a.rigz", &Argument::Long(3));
+        assert_eq!(result, RuntimeStatus::Ok(()));
+        let result = module.function_call(
+            "ended_files",
+            vec![],
+            rigz_core::Definition::None,
+            Argument::None,
+            &CallContext::detached(&Scope::default()),
+        );
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::String("a.rigz=3".into()))
+        );
+
+        let error = match module.shutdown() {
+            RuntimeStatus::Err(e) => e,
+            s => panic!("expected an error, got {:?}", s),
+        };
+        assert_eq!(error.to_string(), "buffered.shutdown: shutdown failed");
+        let args = InitializationArgs {
+            require_aliases: true,
+            ..Default::default()
+        };
+        assert_eq!(module.reload(args), RuntimeStatus::Ok(()));
+        let result = module.function_call(
+            "was_reloaded",
+            vec![],
+            rigz_core::Definition::None,
+            Argument::None,
+            &CallContext::detached(&Scope::default()),
+        );
+        assert_eq!(result, RuntimeStatus::Ok(Argument::Bool(true)));
+        let names: Vec<String> = module.functions().into_iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["ended_files", "was_reloaded"]);
+    }
+
+    #[test]
+    fn functions_read_annotations() {
+        let file = std::env::temp_dir().join("rigz_lua_functions_read_annotations.lua");
+        std::fs::write(
+            &file,
+            r#"
+-- not attached, separated by a blank line
+
+--- Sends a message to a channel
+---@param channel string
+---@param retries? integer
+---@definition
+---@return boolean
+function notify(args)
+    return true
+end
+
+function bare(args) end
+
+---@return string?
+function maybe(args) end
+
+local function hidden(args) end
+"#,
+        )
+        .expect("Failed to write source file");
+        let module = LuaModule {
+            name: "notify".to_string(),
+            function_format: FunctionFormat::default(),
+            module_root: Default::default(),
+            lua: Lua::new(),
+            source_files: vec![file],
+            input_files: Default::default(),
+            permissions: Default::default(),
+        };
+        let args = InitializationArgs {
+            all_errors_fatal: true,
+            ignore_symbol_not_found: false,
+            prefer_none_over_prior_result: false,
+            require_aliases: false,
+        };
+        assert_eq!(module.initialize(args), RuntimeStatus::Ok(()));
+
+        let expected = vec![
+            FunctionSignature::new("bare").variadic(),
+            FunctionSignature::new("maybe")
+                .variadic()
+                .returns_optional(ArgumentType::String),
+            FunctionSignature::new("notify")
+                .parameter("channel", ArgumentType::String)
+                .optional_parameter("retries", ArgumentType::Long)
+                .with_definition()
+                .returns(ArgumentType::Bool)
+                .docs("Sends a message to a channel"),
+        ];
+        assert_eq!(module.functions(), expected);
+        assert_eq!(
+            expected[2].to_string(),
+            "notify(channel: string, retries?: long) do ... end -> bool"
+        );
+        assert_eq!(expected[1].to_string(), "maybe() -> string?");
+    }
+
+    #[test]
+    fn sandbox_enforces_permissions() {
+        let dir = std::env::temp_dir().join("rigz_lua_sandbox_enforces_permissions");
+        std::fs::create_dir_all(&dir).expect("Failed to create directory");
+        let (allowed, secret) = (dir.join("allowed.txt"), dir.join("secret.txt"));
+        std::fs::write(&allowed, "allowed").expect("Failed to write file");
+        std::fs::write(&secret, "secret").expect("Failed to write file");
+        let source = dir.join("main.lua");
+        std::fs::write(
+            &source,
+            r#"
+            function read(input) local f = assert(io.open(input.args[1])) return f:read("a") end
+            function run(input) return os.execute("true") end
+            function home(input) return os.getenv("HOME") end
+            function network(input) return pcall(package.loadlib, "libc.so.6", "*") or pcall(require, "socket") end
+            "#,
+        )
+        .expect("Failed to write source file");
+        let declared = rigz_core::Permissions {
+            fs_read: vec![allowed.clone(), dir.join("..").join("escaped")],
+            env: vec!["HOME".to_string()],
+            ..Default::default()
+        };
+        let granted = rigz_core::Permissions {
+            fs_read: vec![dir.clone()],
+            ..Default::default()
+        };
+        let module = LuaModule::new(
+            "contained".to_string(),
+            Default::default(),
+            vec![source],
+            Default::default(),
+            None,
+            ModulePermissions::new("contained", declared, granted),
+        );
+        assert_eq!(
+            module.initialize(InitializationArgs::default()),
+            RuntimeStatus::Ok(())
+        );
+        let scope = Scope::default();
+        let context = CallContext::detached(&scope);
+        let call = |name: &str, arguments: Vec<Argument>| {
+            module.function_call(
+                name,
+                arguments,
+                rigz_core::Definition::None,
+                Argument::None,
+                &context,
+            )
+        };
+        let denied = |name: &str, arguments: Vec<Argument>| match call(name, arguments) {
+            RuntimeStatus::Err(e) => {
+                let cause = *e.cause.expect("missing cause");
+                assert_eq!(cause.kind, ErrorKind::PermissionDenied, "{}", cause.message);
+                cause.message
+            }
+            s => panic!("expected an error, got {:?}", s),
+        };
+
+        let path = |p: &PathBuf| Argument::String(p.to_string_lossy().to_string());
+        let result = call("read", vec![path(&allowed)]);
+        assert_eq!(
+            result,
+            RuntimeStatus::Ok(Argument::String("allowed".into()))
+        );
+        let message = denied("read", vec![path(&secret)]);
+        assert!(message.contains("doesn't declare `fs_read"), "{}", message);
+        // `..` can't escape the granted directory
+        let message = denied("read", vec![path(&dir.join("..").join("escaped"))]);
+        assert!(message.contains("needs `fs_read"), "{}", message);
+        let message = denied("run", vec![]);
+        assert_eq!(
+            message,
+            "permission denied, contained doesn't declare `process` in its module.rigz"
+        );
+        let message = denied("home", vec![]);
+        assert_eq!(
+            message,
+            "permission denied, contained needs `env HOME`, grant it in its `config.permissions`"
+        );
+        // no C modules, so no sockets
+        assert_eq!(
+            call("network", vec![]),
+            RuntimeStatus::Ok(Argument::Bool(false))
+        );
+    }
+
+    #[test]
+    fn pool_has_a_state_per_thread() {
+        let file = std::env::temp_dir().join("rigz_lua_pool_has_a_state_per_thread.lua");
+        std::fs::write(
+            &file,
+            "count = 0\nfunction increment(args) count = count + 1 return count end",
+        )
+        .expect("Failed to write source file");
+        let pool = LuaPool::new(
+            "counter".to_string(),
+            Default::default(),
+            vec![file],
+            None,
+            Default::default(),
+        );
+        let args = InitializationArgs {
+            all_errors_fatal: true,
+            ignore_symbol_not_found: false,
+            prefer_none_over_prior_result: false,
+            require_aliases: false,
+        };
+        assert_eq!(pool.initialize(args), RuntimeStatus::Ok(()));
+
+        let increment = || {
+            pool.function_call(
+                "increment",
+                vec![],
+                rigz_core::Definition::None,
+                Argument::None,
+                &CallContext::detached(&Scope::default()),
+            )
+        };
+        assert_eq!(increment(), RuntimeStatus::Ok(Argument::Long(1)));
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4).map(|_| scope.spawn(increment)).collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), RuntimeStatus::Ok(Argument::Long(1)));
+            }
+        });
+        assert_eq!(increment(), RuntimeStatus::Ok(Argument::Long(2)));
+    }
+
+    #[test]
+    fn pool_shuts_down_every_state_once() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let (source, log) = (dir.path().join("main.lua"), dir.path().join("shutdown.log"));
+        std::fs::write(
+            &source,
+            format!(
+                r#"
+                function ping(input) return true end
+                function shutdown() local f = assert(io.open({:?}, "a")) f:write("shutdown\n") f:close() end
+                "#,
+                log.to_string_lossy()
+            ),
+        )
+        .expect("Failed to write source file");
+        let write = rigz_core::Permissions {
+            fs_write: vec![dir.path().to_path_buf()],
+            ..Default::default()
+        };
+        let pool = LuaPool::new(
+            "logged".to_string(),
+            Default::default(),
+            vec![source],
+            None,
+            ModulePermissions::new("logged", write.clone(), write),
+        );
+        assert_eq!(
+            pool.initialize(InitializationArgs::default()),
+            RuntimeStatus::Ok(())
+        );
+        let ping = || {
+            pool.function_call(
+                "ping",
+                vec![],
+                rigz_core::Definition::None,
+                Argument::None,
+                &CallContext::detached(&Scope::default()),
+            )
+        };
+        let shutdowns = || {
+            std::fs::read_to_string(&log)
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..2).map(|_| scope.spawn(ping)).collect();
+            for handle in handles {
+                // joining waits for the thread's locals to be dropped
+                assert_eq!(
+                    handle.join().unwrap(),
+                    RuntimeStatus::Ok(Argument::Bool(true))
+                );
+            }
+        });
+        // each thread's state shut down when its thread exited
+        assert_eq!(shutdowns(), 2);
+        assert_eq!(pool.shutdown(), RuntimeStatus::Ok(()));
+        assert_eq!(shutdowns(), 3);
+        assert_eq!(pool.shutdown(), RuntimeStatus::Ok(()));
+        drop(pool);
+        assert_eq!(shutdowns(), 3);
+    }
 }
@@ -0,0 +1,228 @@
+use crate::{function_format, FunctionFormat, LuaModule};
+use log::warn;
+use mlua::{Lua, LuaSerdeExt};
+use rigz_core::{
+    Argument, CallContext, Definition, FunctionSignature, InitializationArgs, Module,
+    ModulePermissions, RuntimeStatus, SyncModule,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+static NEXT_POOL_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Lua states for this thread, by pool id
+    static STATES: RefCell<HashMap<usize, Rc<State>>> = RefCell::new(HashMap::new());
+}
+
+/// A thread's Lua state and the generation it was created in. A Lua state can't leave its thread, so its `shutdown`
+/// function runs when it's dropped, unless `LuaPool::shutdown` already ran it.
+struct State {
+    generation: usize,
+    module: LuaModule,
+    shut_down: Cell<bool>,
+}
+
+impl State {
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        if self.shut_down.replace(true) {
+            return RuntimeStatus::Ok(());
+        }
+        self.module.shutdown()
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        if let RuntimeStatus::Err(e) = self.shutdown() {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// `LuaModule` that is `Send + Sync`, every thread gets its own Lua state.
+///
+/// A thread's state is created, loaded and initialized the first time it calls into the module, with the arguments
+/// passed to `initialize`. States live until their thread exits, or until the pool is dropped on that thread; Lua
+/// globals set on one thread aren't visible on another.
+///
+/// `reload` replaces the states of every thread, each is recreated the next time its thread calls in. Every state
+/// runs the Lua `shutdown` function once: `shutdown` runs it for the calling thread's state, other states run it when
+/// they're dropped, with errors logged.
+pub struct LuaPool {
+    id: usize,
+    generation: AtomicUsize,
+    name: String,
+    function_format: FunctionFormat,
+    module_root: PathBuf,
+    source_files: Vec<PathBuf>,
+    permissions: ModulePermissions,
+    initialize_args: Mutex<Option<InitializationArgs>>,
+}
+
+impl LuaPool {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        name: String,
+        module_root: PathBuf,
+        source_files: Vec<PathBuf>,
+        config: Option<serde_value::Value>,
+        permissions: ModulePermissions,
+    ) -> Arc<dyn SyncModule> {
+        Arc::new(LuaPool {
+            id: NEXT_POOL_ID.fetch_add(1, Ordering::Relaxed),
+            generation: AtomicUsize::new(0),
+            name,
+            function_format: function_format(config),
+            module_root,
+            source_files,
+            permissions,
+            initialize_args: Mutex::new(None),
+        })
+    }
+
+    /// This thread's state, created on first use. The `Rc` is cloned out so a call can re-enter the pool.
+    fn state(&self) -> RuntimeStatus<Rc<State>> {
+        let generation = self.generation.load(Ordering::Acquire);
+        if let Some(state) = STATES.with(|s| s.borrow().get(&self.id).cloned()) {
+            if state.generation == generation {
+                return RuntimeStatus::Ok(state);
+            }
+        }
+
+        let module = LuaModule {
+            name: self.name.clone(),
+            function_format: self.function_format,
+            module_root: self.module_root.clone(),
+            lua: Lua::new(),
+            source_files: self.source_files.clone(),
+            input_files: Default::default(),
+            permissions: self.permissions.clone(),
+        };
+        let args = *self
+            .initialize_args
+            .lock()
+            .expect("initialize_args lock poisoned");
+        if let Some(args) = args {
+            if let RuntimeStatus::Err(e) = module.initialize(args) {
+                return RuntimeStatus::Err(e);
+            }
+        }
+        let state = Rc::new(State {
+            generation,
+            module,
+            shut_down: Cell::new(false),
+        });
+        // dropped outside the borrow, its `shutdown` can run Lua
+        let replaced = STATES.with(|s| s.borrow_mut().insert(self.id, state.clone()));
+        drop(replaced);
+        RuntimeStatus::Ok(state)
+    }
+
+    /// Removes this thread's state, dropping it outside the borrow
+    fn remove_state(&self) -> Option<Rc<State>> {
+        STATES
+            .try_with(|s| s.borrow_mut().remove(&self.id))
+            .ok()
+            .flatten()
+    }
+
+    /// Calls `hook` on this thread's state, creating it if needed
+    fn with_state(&self, hook: impl FnOnce(&LuaModule) -> RuntimeStatus<()>) -> RuntimeStatus<()> {
+        match self.state() {
+            RuntimeStatus::Ok(state) => hook(&state.module),
+            RuntimeStatus::NotFound => RuntimeStatus::NotFound,
+            RuntimeStatus::Err(e) => RuntimeStatus::Err(e),
+        }
+    }
+}
+
+impl Module for LuaPool {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn root(&self) -> PathBuf {
+        self.module_root.clone()
+    }
+
+    fn function_call(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: Argument,
+        context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
+        match self.state() {
+            RuntimeStatus::Ok(state) => {
+                state
+                    .module
+                    .function_call(name, arguments, definition, prior_result, context)
+            }
+            RuntimeStatus::NotFound => RuntimeStatus::NotFound,
+            RuntimeStatus::Err(e) => RuntimeStatus::Err(e),
+        }
+    }
+
+    /// Stores `args` for new states and initializes this thread's state, so errors surface on the calling thread.
+    fn initialize(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        *self
+            .initialize_args
+            .lock()
+            .expect("initialize_args lock poisoned") = Some(args);
+        drop(self.remove_state());
+        match self.state() {
+            RuntimeStatus::Ok(_) => RuntimeStatus::Ok(()),
+            RuntimeStatus::NotFound => RuntimeStatus::NotFound,
+            RuntimeStatus::Err(e) => RuntimeStatus::Err(e),
+        }
+    }
+
+    fn functions(&self) -> Vec<FunctionSignature> {
+        match self.state() {
+            RuntimeStatus::Ok(state) => state.module.functions(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn on_file_start(&self, file: &str) -> RuntimeStatus<()> {
+        self.with_state(|module| module.on_file_start(file))
+    }
+
+    fn on_file_end(&self, file: &str, result: &Argument) -> RuntimeStatus<()> {
+        self.with_state(|module| module.on_file_end(file, result))
+    }
+
+    /// This thread's state, without creating one. The states of other threads shut down when they're dropped.
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        match STATES.with(|s| s.borrow().get(&self.id).cloned()) {
+            Some(state) => state.shutdown(),
+            None => RuntimeStatus::Ok(()),
+        }
+    }
+
+    /// Replaces this thread's state now so errors surface here, other threads replace theirs on their next call. The
+    /// new state runs the Lua `reload` function with `args`.
+    fn reload(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        *self
+            .initialize_args
+            .lock()
+            .expect("initialize_args lock poisoned") = Some(args);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.with_state(|module| match module.lua.to_value(&args) {
+            Ok(args) => module.call_hook("reload", args),
+            Err(e) => RuntimeStatus::Err(crate::lua_error(&e)),
+        })
+    }
+}
+
+impl Drop for LuaPool {
+    fn drop(&mut self) {
+        drop(self.remove_state());
+    }
+}
@@ -0,0 +1,83 @@
+-- Wraps the standard library functions that reach outside the Lua state. `check` raises an error unless the
+-- module's permissions allow the capability, the originals are only reachable through these wrappers.
+local check, temp_dir = ...
+
+local open, lines, input, output, popen = io.open, io.lines, io.input, io.output, io.popen
+local execute, exit, getenv, remove, rename, tmpname = os.execute, os.exit, os.getenv, os.remove, os.rename, os.tmpname
+local raw_loadfile, raw_dofile = loadfile, dofile
+local lua_searcher = package.searchers[2]
+
+function io.open(path, mode)
+    local m = mode or "r"
+    if m:find("[wa+]") then check("fs_write", path) end
+    if m:find("[r+]") then check("fs_read", path) end
+    return open(path, mode)
+end
+
+function io.lines(path, ...)
+    if path ~= nil then check("fs_read", path) end
+    return lines(path, ...)
+end
+
+function io.input(file)
+    if type(file) == "string" then check("fs_read", file) end
+    return input(file)
+end
+
+function io.output(file)
+    if type(file) == "string" then check("fs_write", file) end
+    return output(file)
+end
+
+function io.popen(...)
+    check("process")
+    return popen(...)
+end
+
+function os.execute(...)
+    check("process")
+    return execute(...)
+end
+
+function os.exit(...)
+    check("process")
+    return exit(...)
+end
+
+function os.getenv(name)
+    check("env", name)
+    return getenv(name)
+end
+
+function os.remove(path)
+    check("fs_write", path)
+    return remove(path)
+end
+
+function os.rename(from, to)
+    check("fs_write", from)
+    check("fs_write", to)
+    return rename(from, to)
+end
+
+function os.tmpname()
+    check("fs_write", temp_dir)
+    return tmpname()
+end
+
+function loadfile(path, ...)
+    if path ~= nil then check("fs_read", path) end
+    return raw_loadfile(path, ...)
+end
+
+function dofile(path)
+    if path ~= nil then check("fs_read", path) end
+    return raw_dofile(path)
+end
+
+-- `require` of a Lua file reads it, C modules are disabled in mlua's safe mode
+package.searchers[2] = function(name)
+    local path = package.searchpath(name, package.path)
+    if path ~= nil then check("fs_read", path) end
+    return lua_searcher(name)
+end
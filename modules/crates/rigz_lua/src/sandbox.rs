@@ -0,0 +1,35 @@
+use mlua::{Lua, Value};
+use rigz_core::{Capability, ModulePermissions};
+use std::path::Path;
+
+/// Name of the sandbox chunk, its functions aren't the module's
+pub(crate) const CHUNK_NAME: &str = "=sandbox";
+
+/// Replaces the `io`, `os`, `loadfile`, `dofile` and `require` functions that reach the filesystem, other processes or
+/// the environment with ones that check `permissions` first. Denials are raised as Lua errors holding a
+/// `PermissionDenied`.
+pub(crate) fn install(lua: &Lua, permissions: &ModulePermissions) -> mlua::Result<()> {
+    let permissions = permissions.clone();
+    let check = lua.create_function(move |_, (capability, value): (String, Option<String>)| {
+        let value = value.unwrap_or_default();
+        let capability = match capability.as_str() {
+            "fs_read" => Capability::FsRead(Path::new(&value)),
+            "fs_write" => Capability::FsWrite(Path::new(&value)),
+            "process" => Capability::Process,
+            "env" => Capability::Env(&value),
+            "net" => Capability::Net(&value),
+            c => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "unknown capability {}",
+                    c
+                )))
+            }
+        };
+        permissions.check(capability).map_err(mlua::Error::external)
+    })?;
+    let temp_dir = std::env::temp_dir().to_string_lossy().to_string();
+    lua.load(include_str!("sandbox.lua"))
+        .set_name(CHUNK_NAME)
+        .call::<_, Value>((check, temp_dir))?;
+    Ok(())
+}
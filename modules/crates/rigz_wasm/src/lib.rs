@@ -0,0 +1,401 @@
+use anyhow::{anyhow, Result};
+use rigz_core::{
+    Argument, CallContext, CallRequest, Definition, FunctionSignature, HookRequest,
+    InitializationArgs, Module, RigzError, RuntimeStatus, ABI_VERSION,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use wasmi::core::TrapCode;
+use wasmi::{Config, Engine, Linker, Memory, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+/// Fuel and memory a wasm module can use, from `config.limits` in its module options.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmLimits {
+    /// Fuel for each call, most instructions use one
+    pub fuel: u64,
+    /// Bytes of linear memory for each source file
+    pub memory: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {
+            fuel: 100_000_000,
+            memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// `(ptr, len)` of a request in guest memory, returns a response packed as `ptr << 32 | len`
+type Request = TypedFunc<(i32, i32), i64>;
+
+struct State {
+    limits: StoreLimits,
+}
+
+/// An instantiated source file
+struct Guest {
+    file: PathBuf,
+    store: Store<State>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    free: Option<TypedFunc<(i32, i32), ()>>,
+    function_call: Request,
+    hook: Option<Request>,
+    functions: Option<TypedFunc<(), i64>>,
+}
+
+/// Runs `.wasm` source files with [wasmi](https://github.com/wasmi-labs/wasmi). Guests get no imports, so they can
+/// only compute with the arguments they're given, and every call is limited by `WasmLimits`.
+///
+/// Requests and responses are the JSON of the cdylib ABI, written to and read from the guest's memory. Calls to a
+/// source file are run one at a time.
+pub struct WasmModule {
+    name: String,
+    module_root: PathBuf,
+    limits: WasmLimits,
+    guests: Vec<Mutex<Guest>>,
+}
+
+impl WasmModule {
+    /// Compiles and instantiates every source file, checking its `rigz_abi_version`.
+    pub fn new(
+        name: String,
+        module_root: PathBuf,
+        source_files: Vec<PathBuf>,
+        limits: WasmLimits,
+    ) -> Result<WasmModule> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let guests = source_files
+            .iter()
+            .map(|file| Guest::load(&engine, file, limits).map(Mutex::new))
+            .collect::<Result<_>>()?;
+        Ok(WasmModule {
+            name,
+            module_root,
+            limits,
+            guests,
+        })
+    }
+
+    fn guests(&self) -> impl Iterator<Item = MutexGuard<'_, Guest>> {
+        self.guests
+            .iter()
+            .map(|g| g.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    fn error(&self, guest: &Guest, message: String) -> RigzError {
+        RigzError::runtime(format!("{}: {}", guest.file.display(), message)).with_module(&self.name)
+    }
+
+    /// Runs a hook in every source file that exports `rigz_hook`, stopping at the first error.
+    fn hook(&self, request: HookRequest) -> RuntimeStatus<()> {
+        for mut guest in self.guests() {
+            let Some(hook) = guest.hook else {
+                continue;
+            };
+            match guest.request::<()>(hook, &request, &self.limits) {
+                Ok(RuntimeStatus::Err(e)) => return RuntimeStatus::Err(e.with_module(&self.name)),
+                Ok(_) => {}
+                Err(message) => return RuntimeStatus::Err(self.error(&guest, message)),
+            }
+        }
+        RuntimeStatus::Ok(())
+    }
+}
+
+impl Guest {
+    fn load(engine: &Engine, file: &Path, limits: WasmLimits) -> Result<Guest> {
+        let wasm =
+            std::fs::read(file).map_err(|e| anyhow!("Failed to read {}: {}", file.display(), e))?;
+        let module = wasmi::Module::new(engine, &wasm)
+            .map_err(|e| anyhow!("Invalid wasm in {}: {}", file.display(), e))?;
+        let state = State {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory)
+                .trap_on_grow_failure(true)
+                .build(),
+        };
+        let mut store = Store::new(engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(limits.fuel).map_err(|e| anyhow!("{}", e))?;
+        let instance = Linker::new(engine)
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| anyhow!("Failed to instantiate {}: {}", file.display(), e))?;
+
+        let missing = |export: &str| {
+            anyhow!(
+                "{} doesn't export `{}`, required by rigz modules",
+                file.display(),
+                export
+            )
+        };
+        let abi_version = instance
+            .get_typed_func::<(), i32>(&store, "rigz_abi_version")
+            .map_err(|_| missing("rigz_abi_version"))?
+            .call(&mut store, ())
+            .map_err(|e| anyhow!("{}", e))?;
+        if abi_version as u32 != ABI_VERSION {
+            return Err(anyhow!(
+                "{} was built for module ABI version {}, this runtime supports version {}",
+                file.display(),
+                abi_version,
+                ABI_VERSION
+            ));
+        }
+        Ok(Guest {
+            file: file.to_path_buf(),
+            memory: instance
+                .get_memory(&store, "memory")
+                .ok_or_else(|| missing("memory"))?,
+            alloc: instance
+                .get_typed_func(&store, "rigz_alloc")
+                .map_err(|_| missing("rigz_alloc"))?,
+            free: instance.get_typed_func(&store, "rigz_free").ok(),
+            function_call: instance
+                .get_typed_func(&store, "rigz_function_call")
+                .map_err(|_| missing("rigz_function_call"))?,
+            hook: instance.get_typed_func(&store, "rigz_hook").ok(),
+            functions: instance.get_typed_func(&store, "rigz_functions").ok(),
+            store,
+        })
+    }
+
+    /// Copies `request` into the guest, calls `function` with it and reads the status it returns. `Err` is a
+    /// failure of the guest itself, like a trap or an invalid response.
+    fn request<T: DeserializeOwned>(
+        &mut self,
+        function: Request,
+        request: &impl Serialize,
+        limits: &WasmLimits,
+    ) -> std::result::Result<RuntimeStatus<T>, String> {
+        let request = serde_json::to_vec(request).map_err(|e| e.to_string())?;
+        let len = i32::try_from(request.len()).map_err(|_| "request is too large".to_string())?;
+        self.store
+            .set_fuel(limits.fuel)
+            .map_err(|e| e.to_string())?;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|e| trap(e, limits))?;
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, &request)
+            .map_err(|e| format!("rigz_alloc returned an invalid pointer: {}", e))?;
+        let response = function
+            .call(&mut self.store, (ptr, len))
+            .map_err(|e| trap(e, limits));
+        self.free(ptr, len);
+        self.response(response?)
+    }
+
+    /// Reads and frees a response packed as `ptr << 32 | len`, straight from the guest's memory so a bogus `len`
+    /// can't make the host allocate
+    fn response<T: DeserializeOwned>(&mut self, packed: i64) -> std::result::Result<T, String> {
+        let (ptr, len) = ((packed as u64 >> 32) as u32, packed as u32);
+        let response = (ptr as usize)
+            .checked_add(len as usize)
+            .and_then(|end| self.memory.data(&self.store).get(ptr as usize..end))
+            .ok_or_else(|| {
+                format!(
+                    "invalid response, {} bytes at {} are outside the guest's memory",
+                    len, ptr
+                )
+            })
+            .and_then(|response| {
+                serde_json::from_slice(response).map_err(|e| format!("invalid response: {}", e))
+            })?;
+        self.free(ptr as i32, len as i32);
+        Ok(response)
+    }
+
+    /// Guests without `rigz_free` keep their buffers
+    fn free(&mut self, ptr: i32, len: i32) {
+        if let Some(free) = self.free {
+            let _ = free.call(&mut self.store, (ptr, len));
+        }
+    }
+}
+
+/// Out of fuel and memory are named with their limit
+fn trap(error: wasmi::Error, limits: &WasmLimits) -> String {
+    match error.as_trap_code() {
+        Some(TrapCode::OutOfFuel) => {
+            format!("ran out of fuel, the limit is {} per call", limits.fuel)
+        }
+        Some(TrapCode::GrowthOperationLimited) => {
+            format!("exceeded its memory limit of {} bytes", limits.memory)
+        }
+        _ => error.to_string(),
+    }
+}
+
+impl Module for WasmModule {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn root(&self) -> PathBuf {
+        self.module_root.clone()
+    }
+
+    /// Source files are tried in order, the first one that doesn't return `NotFound` handles the call
+    fn function_call(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: Argument,
+        context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
+        let request = CallRequest {
+            name: name.to_string(),
+            arguments,
+            definition,
+            prior_result,
+            file: context.file.map(str::to_string),
+        };
+        for mut guest in self.guests() {
+            let function_call = guest.function_call;
+            match guest.request(function_call, &request, &self.limits) {
+                Ok(RuntimeStatus::NotFound) => continue,
+                Ok(status) => return status,
+                Err(message) => return RuntimeStatus::Err(self.error(&guest, message)),
+            }
+        }
+        RuntimeStatus::NotFound
+    }
+
+    fn initialize(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Initialize { args })
+    }
+
+    fn functions(&self) -> Vec<FunctionSignature> {
+        let mut functions = Vec::new();
+        for mut guest in self.guests() {
+            let Some(list) = guest.functions else {
+                continue;
+            };
+            guest.store.set_fuel(self.limits.fuel).ok();
+            let listed = list
+                .call(&mut guest.store, ())
+                .map_err(|e| trap(e, &self.limits))
+                .and_then(|packed| guest.response::<Vec<FunctionSignature>>(packed));
+            if let Ok(listed) = listed {
+                functions.extend(listed);
+            }
+        }
+        functions
+    }
+
+    fn on_file_start(&self, file: &str) -> RuntimeStatus<()> {
+        self.hook(HookRequest::OnFileStart {
+            file: file.to_string(),
+        })
+    }
+
+    fn on_file_end(&self, file: &str, result: &Argument) -> RuntimeStatus<()> {
+        self.hook(HookRequest::OnFileEnd {
+            file: file.to_string(),
+            result: result.clone(),
+        })
+    }
+
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Shutdown)
+    }
+
+    fn reload(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Reload { args })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rigz_core::{ErrorKind, Scope};
+
+    /// Answers `ping` with `pong`, loops forever on `spin`, grows its memory on `grow` and answers `huge` with a
+    /// response longer than its memory
+    const GUEST: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 0) "{\"Ok\":{\"String\":\"pong\"}}")
+          (data (i32.const 64) "\"NotFound\"")
+          (func (export "rigz_abi_version") (result i32) (i32.const 1))
+          (func (export "rigz_alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+          (func (export "rigz_function_call") (param $ptr i32) (param $len i32) (result i64)
+            (local $name i32)
+            ;; first 4 bytes of the name, after {"name":"
+            (local.set $name (i32.load (i32.add (local.get $ptr) (i32.const 9))))
+            (if (i32.eq (local.get $name) (i32.const 0x676e6970))
+              (then (return (i64.const 24))))
+            (if (i32.eq (local.get $name) (i32.const 0x6e697073))
+              (then (loop $forever (br $forever))))
+            (if (i32.eq (local.get $name) (i32.const 0x776f7267))
+              (then (drop (memory.grow (i32.const 16)))))
+            (if (i32.eq (local.get $name) (i32.const 0x65677568))
+              (then (return (i64.const 0xffffffff))))
+            (i64.or (i64.shl (i64.const 64) (i64.const 32)) (i64.const 10))))
+    "#;
+
+    fn call(module: &WasmModule, name: &str) -> RuntimeStatus<Argument> {
+        module.function_call(
+            name,
+            vec![],
+            Definition::None,
+            Argument::None,
+            &CallContext::detached(&Scope::default()),
+        )
+    }
+
+    #[test]
+    fn calls_are_limited() {
+        let file = std::env::temp_dir().join(format!("rigz_wasm_{}.wasm", std::process::id()));
+        std::fs::write(&file, wat::parse_str(GUEST).expect("Invalid wat")).unwrap();
+        let limits = WasmLimits {
+            fuel: 10_000,
+            memory: 2 * 64 * 1024,
+        };
+        let module = WasmModule::new("guest".into(), PathBuf::new(), vec![file.clone()], limits)
+            .expect("Failed to load guest");
+        std::fs::remove_file(file).ok();
+
+        assert_eq!(
+            call(&module, "ping"),
+            RuntimeStatus::Ok(Argument::String("pong".into()))
+        );
+        assert_eq!(call(&module, "missing"), RuntimeStatus::NotFound);
+
+        for (name, message) in [
+            ("spin", "ran out of fuel, the limit is 10000 per call"),
+            ("grow", "exceeded its memory limit of 131072 bytes"),
+            (
+                "huge",
+                "invalid response, 4294967295 bytes at 0 are outside the guest's memory",
+            ),
+        ] {
+            match call(&module, name) {
+                RuntimeStatus::Err(e) => {
+                    assert_eq!(e.kind, ErrorKind::Runtime);
+                    assert_eq!(e.module.as_deref(), Some("guest"));
+                    assert!(e.message.ends_with(message), "{}", e.message);
+                }
+                status => panic!("Unexpected status: {:?}", status),
+            }
+        }
+        // fuel is reset for each call
+        assert_eq!(
+            call(&module, "ping"),
+            RuntimeStatus::Ok(Argument::String("pong".into()))
+        );
+    }
+}
@@ -1,3 +1,4 @@
+--- Prints its arguments with `inspect`
 function puts(args)
     print(inspect(args))
 end
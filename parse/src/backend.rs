@@ -0,0 +1,52 @@
+use crate::{check_version, macros, parse_pairs, parse_version, ParseConfig, Rule, Tokenizer, AST};
+#[cfg(not(feature = "tree-sitter"))]
+use anyhow::anyhow;
+use anyhow::Result;
+use pest::Parser;
+use serde::{Deserialize, Serialize};
+
+/// Turns source text into an `AST`, every backend must produce the same elements for the same input.
+pub trait ParserBackend {
+    fn parse(&self, input: &str, config: &ParseConfig) -> Result<AST>;
+}
+
+/// Parser used by `parse`, set with `ParseConfig.backend` or `parse.backend` in the runtime options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// The full grammar in `grammar.pest`
+    #[default]
+    Pest,
+    /// tree-sitter-rigz, requires the `tree-sitter` feature. Only supports the syntax shared with
+    /// tree-sitter-rigz: no macros, heredocs, named args, `do ... end`, or `;` separators.
+    TreeSitter,
+}
+
+impl Backend {
+    pub fn parser(&self) -> Result<Box<dyn ParserBackend>> {
+        match self {
+            Backend::Pest => Ok(Box::new(PestBackend)),
+            #[cfg(feature = "tree-sitter")]
+            Backend::TreeSitter => Ok(Box::new(crate::treesitter::TreeSitterBackend)),
+            #[cfg(not(feature = "tree-sitter"))]
+            Backend::TreeSitter => Err(anyhow!(
+                "rigz_parse was built without the `tree-sitter` feature"
+            )),
+        }
+    }
+}
+
+pub struct PestBackend;
+
+impl ParserBackend for PestBackend {
+    fn parse(&self, input: &str, config: &ParseConfig) -> Result<AST> {
+        let tokens = Tokenizer::parse(Rule::program, input)?;
+        let version = parse_version(tokens.clone(), config)?;
+        check_version(tokens.clone(), version)?;
+        let mut elements = parse_pairs(tokens, config)?;
+        if !config.preserve_macros {
+            elements = macros::expand_macros(elements, input)?;
+        }
+        Ok(AST { elements, version })
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    check_version, parse, parse_pairs, ASTFunctionCall, Backend, Definition, Element, List, Object,
+    ParseConfig, Rule, Span, Tokenizer, Value, AST,
+};
+use anyhow::{anyhow, Result};
+use log::debug;
+use pest::Parser;
+use std::ops::Range;
+
+/// Replace `range`, byte offsets in the previous source, with `text`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Reparse {
+    pub ast: AST,
+    pub source: String,
+    /// Ranges in `source` of the top level calls that were parsed again, everything else was reused
+    pub changed: Vec<Range<usize>>,
+}
+
+/// Apply `edit` to `source` and parse only the top level calls around it, `previous` must be the AST of `source`.
+///
+/// The calls touching the edit and one neighbour on each side are reparsed, more calls after them are included
+/// until the last one parses the same as before, since arguments can run on to the next line. Edits before the first
+/// call (where the pragma lives), files defining macros or edits adding one, and backends other than pest are parsed
+/// in full.
+pub fn reparse(
+    previous: &AST,
+    source: &str,
+    edit: &TextEdit,
+    config: &ParseConfig,
+) -> Result<Reparse> {
+    let TextEdit { range, text } = edit;
+    if range.start > range.end
+        || range.end > source.len()
+        || !source.is_char_boundary(range.start)
+        || !source.is_char_boundary(range.end)
+    {
+        return Err(anyhow!(
+            "Invalid edit range {:?} for source of length {}",
+            range,
+            source.len()
+        ));
+    }
+
+    let mut updated = String::with_capacity(source.len() + text.len());
+    updated.push_str(&source[..range.start]);
+    updated.push_str(text);
+    updated.push_str(&source[range.end..]);
+
+    // an `Element::Macro`, from `preserve_macros`, has no call span
+    let spans = match top_level_spans(previous) {
+        Some(spans) if !spans.is_empty() => spans,
+        _ => return full_parse(updated, config),
+    };
+    if range.start < spans[0].start
+        || config.backend != Backend::Pest
+        || defines_macros(source, &spans)
+    {
+        return full_parse(updated, config);
+    }
+
+    let first = spans
+        .iter()
+        .position(|s| s.end >= range.start)
+        .unwrap_or(spans.len() - 1);
+    let last = spans
+        .iter()
+        .rposition(|s| s.start <= range.end)
+        .unwrap_or(first)
+        .max(first);
+    let low = first.saturating_sub(1);
+    let mut high = (last + 1).min(spans.len() - 1);
+    let window_start = spans[low].start.min(range.start);
+    let delta = text.len() as isize - (range.end - range.start) as isize;
+
+    // A call without parentheses takes every call after it as arguments, so the window grows until its last call
+    // parses the same as before. The call before the edit has to parse the same too, or the whole file is parsed.
+    let reparsed = loop {
+        let at_end = high == spans.len() - 1;
+        let window_end = if at_end {
+            source.len()
+        } else {
+            spans[high].end.max(range.end)
+        };
+        let new_end = (window_end as isize + delta) as usize;
+        let fragment = &updated[window_start..new_end];
+        let elements: Vec<Element> = match Tokenizer::parse(Rule::fragment, fragment) {
+            Ok(tokens) => {
+                check_version(tokens.clone(), previous.version)?;
+                parse_pairs(tokens, config)?
+            }
+            Err(e) => {
+                debug!("Fragment failed to parse, parsing full source - {}", e);
+                return full_parse(updated, config);
+            }
+        };
+        // macros expand across the whole file
+        if elements.iter().any(|e| matches!(e, Element::Macro(_))) {
+            debug!("Edit defines a macro, parsing full source");
+            return full_parse(updated, config);
+        }
+        let elements: Vec<Element> = elements
+            .into_iter()
+            .map(|e| shift(e, window_start as isize))
+            .collect();
+        if low < first && elements.first() != Some(&previous.elements[low]) {
+            debug!("Call before the edit changed, parsing full source");
+            return full_parse(updated, config);
+        }
+        if at_end || elements.last() == Some(&shift(previous.elements[high].clone(), delta)) {
+            break elements;
+        }
+        high += 1;
+    };
+
+    let changed = reparsed
+        .iter()
+        .filter_map(|element| match element {
+            Element::FunctionCall(fc) => Some(fc.span.start..fc.span.end),
+            _ => None,
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(previous.elements.len() + reparsed.len());
+    results.extend(previous.elements[..low].iter().cloned());
+    results.extend(reparsed);
+    results.extend(
+        previous.elements[high + 1..]
+            .iter()
+            .cloned()
+            .map(|e| shift(e, delta)),
+    );
+
+    Ok(Reparse {
+        ast: AST {
+            elements: results,
+            version: previous.version,
+        },
+        source: updated,
+        changed,
+    })
+}
+
+fn full_parse(source: String, config: &ParseConfig) -> Result<Reparse> {
+    let ast = parse(source.clone(), config)?;
+    let changed = match top_level_spans(&ast) {
+        Some(spans) => spans.iter().map(|s| s.start..s.end).collect(),
+        None => vec![Range {
+            start: 0,
+            end: source.len(),
+        }],
+    };
+    Ok(Reparse {
+        ast,
+        source,
+        changed,
+    })
+}
+
+/// Whether a macro is defined between the top level calls at `spans`, the only place one can be. Gaps that don't
+/// parse count as defining one.
+fn defines_macros(source: &str, spans: &[Span]) -> bool {
+    let ends = spans.iter().map(|s| s.end);
+    let starts = spans.iter().map(|s| s.start).skip(1);
+    let mut gaps = std::iter::once(0..spans[0].start)
+        .chain(ends.zip(starts).map(|(end, start)| end..start))
+        .chain(spans.last().map(|s| s.end..source.len()));
+    let defines = |gap: Range<usize>| match Tokenizer::parse(Rule::gap, &source[gap]) {
+        Ok(tokens) => tokens
+            .flatten()
+            .any(|pair| pair.as_rule() == Rule::macro_definition),
+        Err(_) => true,
+    };
+    gaps.any(defines)
+}
+
+/// `None` unless every element is a call with its own, increasing span (macro expansions share one).
+fn top_level_spans(ast: &AST) -> Option<Vec<Span>> {
+    let mut spans: Vec<Span> = Vec::with_capacity(ast.elements.len());
+    for element in &ast.elements {
+        match element {
+            Element::FunctionCall(fc) => {
+                if let Some(last) = spans.last() {
+                    if fc.span.start < last.end {
+                        return None;
+                    }
+                }
+                spans.push(fc.span)
+            }
+            _ => return None,
+        }
+    }
+    Some(spans)
+}
+
+fn shift(element: Element, offset: isize) -> Element {
+    match element {
+        Element::FunctionCall(fc) => Element::FunctionCall(shift_call(fc, offset)),
+        Element::Value(v) => Element::Value(shift_value(v, offset)),
+        Element::NamedArg(k, v) => Element::NamedArg(k, shift_value(v, offset)),
+        Element::Object(o) => Element::Object(shift_object(o, offset)),
+        Element::List(l) => Element::List(shift_list(l, offset)),
+        Element::Args(a) => Element::Args(a.into_iter().map(|e| shift(e, offset)).collect()),
+        e => e,
+    }
+}
+
+fn shift_value(value: Value, offset: isize) -> Value {
+    match value {
+        Value::FunctionCall(fc) => Value::FunctionCall(shift_call(fc, offset)),
+        Value::Object(o) => Value::Object(shift_object(o, offset)),
+        Value::List(l) => Value::List(shift_list(l, offset)),
+        v => v,
+    }
+}
+
+fn shift_object(object: Object, offset: isize) -> Object {
+    Object(
+        object
+            .0
+            .into_iter()
+            .map(|(k, v)| (k, shift(v, offset)))
+            .collect(),
+    )
+}
+
+fn shift_list(list: List, offset: isize) -> List {
+    List(list.0.into_iter().map(|e| shift(e, offset)).collect())
+}
+
+fn shift_call(fc: ASTFunctionCall, offset: isize) -> ASTFunctionCall {
+    let definition = fc.definition.map(|d| match d {
+        Definition::Object(o) => Definition::Object(shift_object(o, offset)),
+        Definition::List(l) => Definition::List(shift_list(l, offset)),
+    });
+    ASTFunctionCall {
+        identifier: fc.identifier,
+        args: fc.args.into_iter().map(|e| shift(e, offset)).collect(),
+        definition,
+        span: Span {
+            start: (fc.span.start as isize + offset) as usize,
+            end: (fc.span.end as isize + offset) as usize,
+        },
+    }
+}
@@ -1,9 +1,23 @@
 #[macro_use]
 extern crate pest_derive;
 
+mod backend;
+mod incremental;
+mod macros;
+mod strings;
+#[cfg(feature = "tree-sitter")]
+mod treesitter;
+mod version;
+
+pub use crate::backend::{Backend, ParserBackend, PestBackend};
+pub use crate::incremental::{reparse, Reparse, TextEdit};
+pub use crate::macros::Macro;
+#[cfg(feature = "tree-sitter")]
+pub use crate::treesitter::TreeSitterBackend;
+pub use crate::version::LanguageVersion;
+
 use anyhow::{anyhow, Result};
 use pest::iterators::Pairs;
-use pest::Parser;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
@@ -15,6 +29,52 @@ struct Tokenizer;
 #[derive(Default)]
 pub struct ParseConfig {
     pub use_64_bit_numbers: bool,
+    /// Version used for files without a `#!rigz` or `use rigz` pragma
+    pub version: LanguageVersion,
+    pub identifiers: IdentifierPolicy,
+    /// Keep `macro` definitions and calls in the AST instead of expanding them
+    pub preserve_macros: bool,
+    pub backend: Backend,
+}
+
+/// Characters allowed in identifiers, by default ASCII letters, digits, and `_`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct IdentifierPolicy {
+    /// Allow Unicode XID identifiers, `größe`
+    pub unicode: bool,
+    /// Allow `-` between characters, `max-retries`; leading or trailing `-` is never allowed
+    pub hyphens: bool,
+}
+
+impl IdentifierPolicy {
+    pub fn validate(&self, identifier: &str) -> Result<()> {
+        let name = identifier.trim_start_matches(['$', ':']);
+        for segment in name.split('.') {
+            if segment.starts_with('-') || segment.ends_with('-') {
+                return Err(anyhow!(
+                    "Invalid identifier `{}`, `-` is only allowed between characters",
+                    identifier
+                ));
+            }
+            for c in segment.chars() {
+                if c == '-' && !self.hyphens {
+                    return Err(anyhow!(
+                        "Invalid identifier `{}`, enable `identifiers.hyphens` to use `-`",
+                        identifier
+                    ));
+                }
+                if !c.is_ascii() && !self.unicode {
+                    return Err(anyhow!(
+                        "Invalid identifier `{}`, enable `identifiers.unicode` to use `{}`",
+                        identifier,
+                        c
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -22,6 +82,38 @@ pub struct ASTFunctionCall {
     pub identifier: Identifier,
     pub args: Vec<Element>,
     pub definition: Option<Definition>,
+    #[serde(default)]
+    pub span: Span,
+}
+
+/// Byte range of an element in its source file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// 1-based line and column of `start`
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let start = self.start.min(source.len());
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let column = match before.rfind('\n') {
+            None => before.chars().count() + 1,
+            Some(i) => before[i + 1..].chars().count() + 1,
+        };
+        (line, column)
+    }
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(value: pest::Span<'_>) -> Self {
+        Span {
+            start: value.start(),
+            end: value.end(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -102,6 +194,7 @@ pub enum Element {
     Bool(bool),
     String(String),
     NamedArg(Identifier, Value),
+    Macro(Macro),
     None,
 }
 
@@ -138,18 +231,23 @@ impl Display for Element {
             Element::String(s) => write!(f, "{}", s),
             Element::None => write!(f, "none"),
             Element::NamedArg(key, value) => write!(f, "{}: {}", key, value),
+            Element::Macro(m) => write!(f, "macro {}({})", m.name, m.params.join(", ")),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct AST {
     pub elements: Vec<Element>,
+    pub version: LanguageVersion,
 }
 
 impl AST {
     pub fn init() -> AST {
-        AST { elements: vec![] }
+        AST {
+            elements: vec![],
+            version: LanguageVersion::default(),
+        }
     }
 
     pub fn merge(&mut self, other: AST) {
@@ -159,9 +257,52 @@ impl AST {
 }
 
 pub fn parse(input: String, config: &ParseConfig) -> Result<AST> {
-    let tokens = Tokenizer::parse(Rule::program, input.as_str())?;
-    let elements = parse_pairs(tokens, config)?;
-    Ok(AST { elements })
+    config.backend.parser()?.parse(input.as_str(), config)
+}
+
+fn parse_version(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<LanguageVersion> {
+    let pragma = pairs
+        .flat_map(|program| program.into_inner())
+        .find(|pair| pair.as_rule() == Rule::header)
+        .and_then(|header| header.into_inner().next());
+    match pragma {
+        None => Ok(config.version),
+        Some(pragma) => match pragma.into_inner().find(|p| p.as_rule() == Rule::version) {
+            None => Err(anyhow!("`version` not set for pragma")),
+            Some(version) => version.as_str().parse(),
+        },
+    }
+}
+
+/// Fails on syntax the file's version doesn't have.
+fn check_version(pairs: Pairs<Rule>, version: LanguageVersion) -> Result<()> {
+    for pair in pairs.flatten() {
+        let feature = match pair.as_rule() {
+            Rule::heredoc => "Heredoc strings",
+            Rule::triple_quoted => "Triple quoted strings",
+            _ => continue,
+        };
+        let (line, column) = pair.line_col();
+        version
+            .require(feature, LanguageVersion::V0_2)
+            .map_err(|e| anyhow!("{} ({}:{})", e, line, column))?;
+    }
+    Ok(())
+}
+
+fn parse_number(value: &str, config: &ParseConfig) -> Result<Element> {
+    let num = if value.contains('.') {
+        if config.use_64_bit_numbers {
+            Element::Float(value.parse()?)
+        } else {
+            Element::Double(value.parse()?)
+        }
+    } else if config.use_64_bit_numbers {
+        Element::Long(value.parse()?)
+    } else {
+        Element::Int(value.parse()?)
+    };
+    Ok(num)
 }
 
 fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>> {
@@ -169,9 +310,59 @@ fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>>
     for pair in pairs {
         match pair.as_rule() {
             Rule::program => results.append(parse_pairs(pair.into_inner(), config)?.as_mut()),
+            Rule::fragment => results.append(parse_pairs(pair.into_inner(), config)?.as_mut()),
             Rule::function_body => results.append(parse_pairs(pair.into_inner(), config)?.as_mut()),
             Rule::definition => results.append(parse_pairs(pair.into_inner(), config)?.as_mut()),
+            Rule::macro_definition => {
+                let span = pair.as_span().into();
+                let mut name = None;
+                let mut params = Vec::new();
+                let mut body = Vec::new();
+                for inner in pair.into_inner() {
+                    match inner.as_rule() {
+                        Rule::macro_keyword => continue,
+                        Rule::identifier => {
+                            let identifier = inner.as_str().trim();
+                            config.identifiers.validate(identifier)?;
+                            name = Some(identifier.to_string());
+                        }
+                        Rule::macro_params => {
+                            for element in parse_pairs(inner.into_inner(), config)? {
+                                match element {
+                                    Element::Identifier(i) => params.push(i),
+                                    _ => {
+                                        return Err(anyhow!(
+                                            "Unexpected Element in `macro_params`: {:?}",
+                                            element
+                                        ))
+                                    }
+                                }
+                            }
+                        }
+                        Rule::macro_body => body = parse_pairs(inner.into_inner(), config)?,
+                        _ => {
+                            return Err(anyhow!(
+                                "Unexpected Rule in `macro_definition`: {:?}",
+                                inner.as_rule()
+                            ))
+                        }
+                    }
+                }
+                results.push(Element::Macro(Macro {
+                    name: name.expect("`identifier` not set for macro_definition"),
+                    params,
+                    body,
+                    span,
+                }))
+            }
+            Rule::macro_keyword | Rule::macro_params | Rule::macro_body => {
+                return Err(anyhow!(
+                    "`{:?}` called directly, it should be handled in parent",
+                    pair.as_rule()
+                ))
+            }
             Rule::function_call => {
+                let span = pair.as_span().into();
                 let inner = parse_pairs(pair.into_inner(), config)?;
                 let mut identifier = None;
                 let mut definition = None;
@@ -200,10 +391,12 @@ fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>>
                     identifier: identifier.expect("`identifier` not set for function_call"),
                     args,
                     definition,
+                    span,
                 }))
             }
             Rule::identifier => {
                 let identifier = pair.as_str().trim();
+                config.identifiers.validate(identifier)?;
                 results.push(Element::Identifier(identifier.into()));
             }
             Rule::named_arg => {
@@ -306,24 +499,36 @@ fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>>
                 };
                 results.push(Element::Bool(b));
             }
-            Rule::number => {
-                let value = pair.as_str().trim();
-                let num = if value.contains('.') {
-                    if config.use_64_bit_numbers {
-                        Element::Float(value.parse()?)
-                    } else {
-                        Element::Double(value.parse()?)
-                    }
-                } else if config.use_64_bit_numbers {
-                    Element::Long(value.parse()?)
-                } else {
-                    Element::Int(value.parse()?)
-                };
-                results.push(num);
-            }
+            Rule::number => results.push(parse_number(pair.as_str().trim(), config)?),
             Rule::string => {
                 let raw = pair.as_str().trim();
-                results.push(Element::String(raw[1..raw.len() - 1].to_string()));
+                let value = match pair.clone().into_inner().next() {
+                    None => raw[1..raw.len() - 1].to_string(),
+                    Some(inner) => match inner.as_rule() {
+                        Rule::heredoc => {
+                            let body = inner
+                                .into_inner()
+                                .find(|p| p.as_rule() == Rule::heredoc_body)
+                                .map(|p| p.as_str())
+                                .unwrap_or_default();
+                            strings::heredoc(body)
+                        }
+                        Rule::triple_quoted => strings::triple_quoted(inner.as_str()),
+                        _ => {
+                            return Err(anyhow!(
+                                "Unexpected Rule in `string`: {:?}",
+                                inner.as_rule()
+                            ))
+                        }
+                    },
+                };
+                results.push(Element::String(value));
+            }
+            Rule::heredoc | Rule::heredoc_tag | Rule::heredoc_body | Rule::triple_quoted => {
+                return Err(anyhow!(
+                    "`{:?}` called directly, it should be handled in `string`",
+                    pair.as_rule()
+                ))
             }
             Rule::VALID_CHARS => {
                 return Err(anyhow!(
@@ -333,6 +538,10 @@ fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>>
             Rule::none => {
                 results.push(Element::None);
             }
+            Rule::header => continue,
+            Rule::gap => continue,
+            Rule::pragma => continue,
+            Rule::version => continue,
             Rule::EOI => break,
             Rule::COMMENT => continue,
             Rule::single_line_comment => continue,
@@ -346,6 +555,7 @@ fn parse_pairs(pairs: Pairs<Rule>, config: &ParseConfig) -> Result<Vec<Element>>
 #[cfg(test)]
 mod is_valid {
     use super::*;
+    use pest::Parser;
 
     #[test]
     fn named_args_new_line_separator() {
@@ -367,19 +577,62 @@ mod is_valid {
 mod tests {
     use super::*;
 
+    /// Resets every span, for comparing parsed elements with ones built by hand
+    fn without_spans(mut ast: AST) -> AST {
+        fn call(fc: &mut ASTFunctionCall) {
+            fc.span = Span::default();
+            fc.args.iter_mut().for_each(element);
+            match &mut fc.definition {
+                Some(Definition::Object(o)) => o.0.values_mut().for_each(element),
+                Some(Definition::List(l)) => l.0.iter_mut().for_each(element),
+                None => {}
+            }
+        }
+        fn value(v: &mut Value) {
+            match v {
+                Value::Object(o) => o.0.values_mut().for_each(element),
+                Value::List(l) => l.0.iter_mut().for_each(element),
+                Value::FunctionCall(fc) => call(fc),
+                _ => {}
+            }
+        }
+        fn element(e: &mut Element) {
+            match e {
+                Element::FunctionCall(fc) => call(fc),
+                Element::Args(args) => args.iter_mut().for_each(element),
+                Element::Value(v) | Element::NamedArg(_, v) => value(v),
+                Element::Object(o) => o.0.values_mut().for_each(element),
+                Element::List(l) => l.0.iter_mut().for_each(element),
+                Element::Macro(m) => {
+                    m.span = Span::default();
+                    m.body.iter_mut().for_each(element);
+                }
+                _ => {}
+            }
+        }
+        ast.elements.iter_mut().for_each(element);
+        ast
+    }
+
     #[test]
     fn puts_works() {
         let mut elements = Vec::new();
-        let mut args = Vec::new();
-        args.push(Element::Value(Value::String("Hello World".to_string())));
+        let args = vec![Element::Value(Value::String("Hello World".to_string()))];
         elements.push(Element::FunctionCall(ASTFunctionCall {
             identifier: "puts".into(),
             args,
             definition: None,
+            span: Span::default(),
         }));
 
         let result = parse("puts 'Hello World'".to_string(), &ParseConfig::default()).unwrap();
-        assert_eq!(result, AST { elements });
+        assert_eq!(
+            without_spans(result),
+            AST {
+                elements,
+                version: LanguageVersion::default()
+            }
+        );
     }
 
     #[test]
@@ -399,6 +652,7 @@ mod tests {
             identifier: "let".into(),
             args: Vec::new(),
             definition,
+            span: Span::default(),
         }));
 
         let input = r#"
@@ -408,7 +662,13 @@ mod tests {
         "#
         .to_string();
         let result = parse(input, &ParseConfig::default()).unwrap();
-        assert_eq!(result, AST { elements });
+        assert_eq!(
+            without_spans(result),
+            AST {
+                elements,
+                version: LanguageVersion::default()
+            }
+        );
     }
 
     #[test]
@@ -421,6 +681,7 @@ mod tests {
                 identifier: ":valid_account".to_string(),
                 args: vec![],
                 definition: None,
+                span: Span::default(),
             })),
         );
         let definition = Some(Definition::Object(Object(details)));
@@ -428,6 +689,7 @@ mod tests {
             identifier: ":allow".to_string(),
             args: Vec::new(),
             definition,
+            span: Span::default(),
         }));
 
         let input = r#"
@@ -437,7 +699,13 @@ mod tests {
         "#
         .to_string();
         let result = parse(input, &ParseConfig::default()).unwrap();
-        assert_eq!(result, AST { elements });
+        assert_eq!(
+            without_spans(result),
+            AST {
+                elements,
+                version: LanguageVersion::default()
+            }
+        );
     }
 
     #[test]
@@ -455,6 +723,7 @@ mod tests {
                 identifier: "one_of".into(),
                 args: Vec::new(),
                 definition: Some(Definition::List(List(accounts))),
+                span: Span::default(),
             })),
         );
         details.insert(
@@ -463,6 +732,7 @@ mod tests {
                 identifier: "variables".into(),
                 args: vec![],
                 definition: Some(Definition::Object(Object(inner_details))),
+                span: Span::default(),
             }),
         );
         let definition = Some(Definition::Object(Object(details)));
@@ -470,6 +740,7 @@ mod tests {
             identifier: "allow".into(),
             args: Vec::new(),
             definition,
+            span: Span::default(),
         }));
 
         let input = r#"
@@ -481,6 +752,444 @@ mod tests {
         "#
         .to_string();
         let result = parse(input, &ParseConfig::default()).unwrap();
-        assert_eq!(result, AST { elements });
+        assert_eq!(
+            without_spans(result),
+            AST {
+                elements,
+                version: LanguageVersion::default()
+            }
+        );
+    }
+
+    #[test]
+    fn pragma_sets_version() {
+        for input in [
+            "#!rigz 0.1\nputs 'Hello World'",
+            "use rigz 0.1\nputs 'Hello World'",
+        ] {
+            let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+            assert_eq!(result.version, LanguageVersion::V0_1);
+            assert_eq!(result.elements.len(), 1);
+        }
+    }
+
+    #[test]
+    fn pragma_is_only_read_at_the_start() {
+        let input = "#!rigz 0.1\nputs('Hello')\n#!rigz 0.2\n# use rigz 0.2\nputs 'World'";
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        assert_eq!(result.version, LanguageVersion::V0_1);
+        assert_eq!(result.elements.len(), 2);
+
+        let input = "# generated\n#!rigz 0.1\nputs 'Hello'";
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        assert_eq!(result.version, LanguageVersion::default());
+        assert_eq!(result.elements.len(), 1);
+    }
+
+    #[test]
+    fn unknown_version_fails() {
+        let result = parse(
+            "#!rigz 9.3\nputs 'Hello'".to_string(),
+            &ParseConfig::default(),
+        );
+        let error = result.expect_err("9.3 is not a rigz version").to_string();
+        assert!(
+            error.contains("Unsupported rigz version `9.3`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn older_versions_reject_newer_syntax() {
+        let heredoc = "run <<~EOS\n  exit 0\nEOS\n";
+        for input in [
+            format!("#!rigz 0.1\n{}", heredoc),
+            "use rigz 0.1\nquery \"\"\"SELECT 1\"\"\"".to_string(),
+        ] {
+            let error = parse(input, &ParseConfig::default())
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.contains("need rigz 0.2, this file is rigz 0.1"),
+                "{}",
+                error
+            );
+        }
+        let config = ParseConfig {
+            version: LanguageVersion::V0_1,
+            ..Default::default()
+        };
+        assert!(parse(heredoc.to_string(), &config).is_err());
+        let result = parse(format!("#!rigz 0.2\n{}", heredoc), &config).unwrap();
+        assert_eq!(result.version, LanguageVersion::V0_2);
+    }
+
+    #[test]
+    fn whitespace_ends_identifier() {
+        let result = parse("puts hello".to_string(), &ParseConfig::default()).unwrap();
+        let expected = ASTFunctionCall {
+            identifier: "puts".into(),
+            args: vec![Element::Value(Value::FunctionCall(ASTFunctionCall {
+                identifier: "hello".into(),
+                args: vec![],
+                definition: None,
+                span: Span::default(),
+            }))],
+            definition: None,
+            span: Span::default(),
+        };
+        assert_eq!(
+            without_spans(result).elements,
+            vec![Element::FunctionCall(expected)]
+        );
+    }
+
+    #[test]
+    fn identifier_policy() {
+        let input = "resource-name { max-retries = 3, größe = 1 }";
+        let error = parse(input.to_string(), &ParseConfig::default())
+            .expect_err("hyphens are disabled by default")
+            .to_string();
+        assert!(error.contains("identifiers.hyphens"), "{}", error);
+
+        let config = ParseConfig {
+            identifiers: IdentifierPolicy {
+                unicode: true,
+                hyphens: true,
+            },
+            ..Default::default()
+        };
+        let result = parse(input.to_string(), &config).unwrap();
+        match &result.elements[0] {
+            Element::FunctionCall(fc) => {
+                assert_eq!(fc.identifier, "resource-name");
+                match &fc.definition {
+                    Some(Definition::Object(o)) => {
+                        assert!(o.0.contains_key("max-retries"));
+                        assert!(o.0.contains_key("größe"));
+                    }
+                    _ => panic!("Unexpected definition: {:?}", fc.definition),
+                }
+            }
+            e => panic!("Unexpected element: {:?}", e),
+        }
+
+        let error = parse("-retries 3".to_string(), &config).expect_err("leading -");
+        assert!(
+            error.to_string().contains("only allowed between"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn macros_expand() {
+        let input = r#"
+            macro allow_account(acct, kind) {
+                allow {
+                    variables {
+                        account = acct,
+                        type = kind
+                    }
+                }
+            }
+            allow_account(:valid_account, kind: 'user')
+            allow_account(:admin, 'admin')
+        "#;
+        let expected = r#"
+            allow {
+                variables {
+                    account = :valid_account,
+                    type = 'user'
+                }
+            }
+            allow {
+                variables {
+                    account = :admin,
+                    type = 'admin'
+                }
+            }
+        "#;
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        let expected = parse(expected.to_string(), &ParseConfig::default()).unwrap();
+        assert_eq!(without_spans(result), without_spans(expected));
+    }
+
+    #[test]
+    fn macro_errors_point_to_call_and_definition() {
+        let input = "macro pair(a, b) {\n  puts a, b\n}\npair 1";
+        let error = parse(input.to_string(), &ParseConfig::default())
+            .expect_err("pair is missing b")
+            .to_string();
+        assert!(error.contains("missing argument(s): b"), "{}", error);
+        assert!(error.contains("called at line 4, column 1"), "{}", error);
+        assert!(error.contains("defined at line 1, column 1"), "{}", error);
+    }
+
+    #[test]
+    fn macros_in_objects_keep_their_key() {
+        let input = r#"
+            macro user(name) { lookup(name) }
+            macro typed(kind) { variables { type = kind } }
+            allow {
+                account = user('admin'),
+                typed('user')
+            }
+        "#;
+        let expected = r#"
+            allow {
+                account = lookup('admin'),
+                variables { type = 'user' }
+            }
+        "#;
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        let expected = parse(expected.to_string(), &ParseConfig::default()).unwrap();
+        assert_eq!(without_spans(result), without_spans(expected));
+    }
+
+    #[test]
+    fn macros_in_objects_expand_to_one_new_attribute() {
+        let input = "macro both() {\n  account(1)\n  kind(2)\n}\nallow {\n  both()\n}";
+        let error = parse(input.to_string(), &ParseConfig::default())
+            .expect_err("both expands to two calls")
+            .to_string();
+        assert!(error.contains("expands to 2 calls"), "{}", error);
+        assert!(error.contains("called at line 6, column 3"), "{}", error);
+        assert!(error.contains("defined at line 1, column 1"), "{}", error);
+
+        let input = "macro account() { kind(1) }\nallow {\n  kind = 2,\n  account()\n}";
+        let error = parse(input.to_string(), &ParseConfig::default())
+            .expect_err("kind is set twice")
+            .to_string();
+        assert!(error.contains("expands to `kind`"), "{}", error);
+        assert!(error.contains("called at line 4, column 3"), "{}", error);
+        assert!(error.contains("defined at line 1, column 1"), "{}", error);
+    }
+
+    #[test]
+    fn preserve_macros() {
+        let input = "macro hello(name) { puts name }\nhello 'World'";
+        let config = ParseConfig {
+            preserve_macros: true,
+            ..Default::default()
+        };
+        let result = parse(input.to_string(), &config).unwrap();
+        assert_eq!(result.elements.len(), 2);
+        match &result.elements[0] {
+            Element::Macro(m) => {
+                assert_eq!(m.name, "hello");
+                assert_eq!(m.params, vec!["name".to_string()]);
+            }
+            e => panic!("Unexpected element: {:?}", e),
+        }
+    }
+
+    fn parse_string(input: &str) -> String {
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        match &result.elements[0] {
+            Element::FunctionCall(fc) => match &fc.args[0] {
+                Element::Value(Value::String(s)) => s.clone(),
+                e => panic!("Unexpected arg: {:?}", e),
+            },
+            e => panic!("Unexpected element: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn heredoc_strips_indentation() {
+        let input =
+            "run <<~EOS\n    set -e\n      echo 'hi' # not a comment\n\n    exit 0\n  EOS\n";
+        assert_eq!(
+            parse_string(input),
+            "set -e\n  echo 'hi' # not a comment\n\nexit 0\n"
+        );
+        assert_eq!(
+            parse_string(input.replace('\n', "\r\n").as_str()),
+            "set -e\n  echo 'hi' # not a comment\n\nexit 0\n"
+        );
+    }
+
+    #[test]
+    fn heredoc_ends_on_a_line_with_only_the_tag() {
+        let input = "run <<~EOS\n  EOS is here\n  EOS_2\n  EOS\t\n";
+        let result = parse(input.to_string(), &ParseConfig::default()).unwrap();
+        match &result.elements[..] {
+            [Element::FunctionCall(fc)] => assert_eq!(fc.args.len(), 1),
+            e => panic!("Unexpected elements: {:?}", e),
+        }
+        assert_eq!(parse_string(input), "EOS is here\nEOS_2\n");
+    }
+
+    #[test]
+    fn heredoc_must_be_terminated() {
+        let input = "run <<~EOS\n  select 1\n  EOSX\n";
+        assert!(parse(input.to_string(), &ParseConfig::default()).is_err());
+    }
+
+    #[test]
+    fn triple_quoted_strips_indentation() {
+        let input = "query \"\"\"\n    SELECT *\n      FROM users\n    \"\"\"";
+        assert_eq!(parse_string(input), "SELECT *\n  FROM users");
+        assert_eq!(
+            parse_string(input.replace('\n', "\r\n").as_str()),
+            "SELECT *\n  FROM users"
+        );
+    }
+
+    fn top_level_ranges(ast: &AST) -> Vec<std::ops::Range<usize>> {
+        ast.elements
+            .iter()
+            .map(|e| match e {
+                Element::FunctionCall(fc) => fc.span.start..fc.span.end,
+                e => panic!("Unexpected element: {:?}", e),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reparse_matches_full_parse() {
+        let source = "#!rigz 0.1\nputs('a')\nallow { account = 1 }\nputs('b')\nputs('c')\n";
+        let config = ParseConfig::default();
+        let previous = parse(source.to_string(), &config).unwrap();
+        let edits = [
+            (
+                source.find("'b'").unwrap()..source.find("'b'").unwrap() + 3,
+                "'bb'",
+            ),
+            (
+                source.find("1 }").unwrap()..source.find("1 }").unwrap() + 1,
+                "[1, 2, 3]",
+            ),
+            (source.len()..source.len(), "deny { account = 2 }\n"),
+            (source.find("puts('c')").unwrap()..source.len(), ""),
+            (0..0, "# comment\n"),
+        ];
+        for (range, text) in edits {
+            let edit = TextEdit {
+                range: range.clone(),
+                text: text.to_string(),
+            };
+            let result = reparse(&previous, source, &edit, &config).unwrap();
+            let expected = parse(result.source.clone(), &config).unwrap();
+            assert_eq!(result.ast, expected, "{:?}", edit);
+            assert_eq!(top_level_ranges(&result.ast), top_level_ranges(&expected));
+        }
+    }
+
+    #[test]
+    fn reparse_matches_full_parse_when_arguments_run_on() {
+        let sources = [
+            "a()\nb()\nc()\nd()\ne()\nf()\n",
+            "puts('x')\nputs('y')\nputs('z')",
+            "a(1)\nb 2\nc(3)\nd { e = 4 }\nf(5)\n",
+        ];
+        let edits = [
+            ("c()", "c"),
+            ("c()", "c 1"),
+            ("b()", "b"),
+            ("a()", "a"),
+            ("f()", "f"),
+            ("puts('x')", "puts 'x'"),
+            ("puts('y')", "puts 'y'"),
+            ("puts('z')", "puts 'z'"),
+            ("b 2", "b(2)"),
+            ("c(3)", "c 3"),
+            ("d {", "d(1) {"),
+            ("f(5)", "f 5"),
+        ];
+        let config = ParseConfig::default();
+        for source in sources {
+            let previous = parse(source.to_string(), &config).unwrap();
+            for (find, text) in edits {
+                let Some(start) = source.find(find) else {
+                    continue;
+                };
+                let edit = TextEdit {
+                    range: start..start + find.len(),
+                    text: text.to_string(),
+                };
+                let result = reparse(&previous, source, &edit, &config).unwrap();
+                let expected = parse(result.source.clone(), &config).unwrap();
+                assert_eq!(result.ast, expected, "{:?} in {:?}", edit, source);
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_only_parses_in_full_when_macros_are_defined() {
+        let config = ParseConfig::default();
+        let edit = |source: &str, find: &str, text: &str| {
+            let previous = parse(source.to_string(), &config).unwrap();
+            let start = source.find(find).unwrap();
+            let edit = TextEdit {
+                range: start..start + find.len(),
+                text: text.to_string(),
+            };
+            let result = reparse(&previous, source, &edit, &config).unwrap();
+            let expected = parse(result.source.clone(), &config).unwrap();
+            assert_eq!(result.ast, expected, "{:?} in {:?}", edit, source);
+            result.changed.len()
+        };
+
+        let source = "macro_count('macro')\n# a macro\nputs('a')\nputs('b')\nputs('c')\n";
+        assert_eq!(edit(source, "'c'", "'d'"), 2);
+        let source = "macro hi(n) { puts n }\nputs('a')\nputs('b')\nputs('c')\nhi('d')\n";
+        assert_eq!(edit(source, "'a'", "'e'"), 4);
+        let source = "puts('a')\nputs('b')\nhi('c')\n";
+        assert_eq!(edit(source, "hi(", "macro hi(n) { puts n }\nhi("), 3);
+    }
+
+    #[test]
+    fn reparse_reports_changed_calls() {
+        let source = "puts('a')\nputs('b')\nputs('c')\nputs('d')\nputs('e')\n";
+        let config = ParseConfig::default();
+        let previous = parse(source.to_string(), &config).unwrap();
+        let start = source.find("'c'").unwrap();
+        let edit = TextEdit {
+            range: start..start + 3,
+            text: "'changed'".to_string(),
+        };
+        let result = reparse(&previous, source, &edit, &config).unwrap();
+        let changed: Vec<&str> = result
+            .changed
+            .iter()
+            .map(|r| result.source[r.clone()].trim())
+            .collect();
+        assert_eq!(changed, vec!["puts('b')", "puts('changed')", "puts('d')"]);
+    }
+
+    #[cfg(feature = "tree-sitter")]
+    #[test]
+    fn tree_sitter_matches_pest() {
+        let inputs = [
+            "puts 'Hello World'",
+            "#!rigz 0.1\nputs('a', 1, 2.5, true, none)\nputs(\"b\")",
+            "use rigz 0.1\nhello $name, :symbol",
+            "allow { account = 1, region = 'us', tags = [1, 2], nested { a = `x` } }",
+            "# comment\nlist [1, 'two', { three = 3 }, [4]]\n/* block */ puts(none)",
+            "outer(inner(1, 2), other { a = 1 })",
+        ];
+        let tree_sitter = ParseConfig {
+            backend: Backend::TreeSitter,
+            ..Default::default()
+        };
+        for input in inputs {
+            let expected = parse(input.to_string(), &ParseConfig::default()).unwrap();
+            let result = parse(input.to_string(), &tree_sitter).unwrap();
+            let text = |ast: &AST| -> Vec<String> {
+                top_level_ranges(ast)
+                    .into_iter()
+                    .map(|r| input[r].trim().to_string())
+                    .collect()
+            };
+            assert_eq!(text(&result), text(&expected), "{}", input);
+            assert_eq!(without_spans(result), without_spans(expected), "{}", input);
+        }
+
+        let error = parse("puts(1".to_string(), &tree_sitter)
+            .expect_err("unclosed call")
+            .to_string();
+        assert!(error.contains("line 1"), "{}", error);
     }
 }
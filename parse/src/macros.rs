@@ -0,0 +1,400 @@
+use crate::{ASTFunctionCall, Definition, Element, Identifier, List, Object, Span, Value};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// `macro name(params) { ... }`, a template of function calls expanded where `name(args)` is called.
+///
+/// Parameters are only replaced where they are used as a value in the body, `account = acct`; arguments are
+/// substituted once and never rescanned, so a value passed in can't refer to another parameter.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Macro {
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    pub body: Vec<Element>,
+    pub span: Span,
+}
+
+pub(crate) fn expand_macros(elements: Vec<Element>, source: &str) -> Result<Vec<Element>> {
+    let mut macros = HashMap::new();
+    let mut remaining = Vec::with_capacity(elements.len());
+    for element in elements {
+        match element {
+            Element::Macro(m) => {
+                if let Some(existing) = macros.get(&m.name) {
+                    let existing: &Macro = existing;
+                    return Err(anyhow!(
+                        "macro `{}` is already defined\n  --> {}\n  --> first defined at {}",
+                        m.name,
+                        location(m.span, source),
+                        location(existing.span, source)
+                    ));
+                }
+                macros.insert(m.name.clone(), m);
+            }
+            e => remaining.push(e),
+        }
+    }
+
+    if macros.is_empty() {
+        return Ok(remaining);
+    }
+
+    let expander = Expander { macros, source };
+    let mut results = Vec::with_capacity(remaining.len());
+    for element in remaining {
+        results.append(expander.expand_element(element, 0)?.as_mut());
+    }
+    Ok(results)
+}
+
+fn location(span: Span, source: &str) -> String {
+    let (line, column) = span.line_col(source);
+    format!("line {}, column {}", line, column)
+}
+
+struct Expander<'a> {
+    macros: HashMap<Identifier, Macro>,
+    source: &'a str,
+}
+
+impl Expander<'_> {
+    fn error(&self, message: String, call: &ASTFunctionCall, m: &Macro) -> anyhow::Error {
+        anyhow!(
+            "{}\n  --> called at {}\n  --> macro `{}` defined at {}",
+            message,
+            location(call.span, self.source),
+            m.name,
+            location(m.span, self.source)
+        )
+    }
+
+    /// Top level calls can expand to many calls, everywhere else a macro must expand to one.
+    fn expand_element(&self, element: Element, depth: usize) -> Result<Vec<Element>> {
+        let element = match element {
+            Element::FunctionCall(fc) => match self.macros.get(&fc.identifier) {
+                Some(m) => {
+                    return Ok(self
+                        .expand_call(fc, m, depth)?
+                        .into_iter()
+                        .map(Element::FunctionCall)
+                        .collect())
+                }
+                None => Element::FunctionCall(self.expand_nested(fc, depth)?),
+            },
+            Element::Value(v) => Element::Value(self.expand_value(v, depth)?),
+            Element::NamedArg(k, v) => Element::NamedArg(k, self.expand_value(v, depth)?),
+            Element::Object(o) => Element::Object(self.expand_object(o, depth)?),
+            Element::List(l) => Element::List(self.expand_list(l, depth)?),
+            Element::Args(a) => Element::Args(self.expand_elements(a, depth)?),
+            e => e,
+        };
+        Ok(vec![element])
+    }
+
+    fn expand_elements(&self, elements: Vec<Element>, depth: usize) -> Result<Vec<Element>> {
+        let mut results = Vec::with_capacity(elements.len());
+        for element in elements {
+            results.push(self.expand_single(element, depth)?);
+        }
+        Ok(results)
+    }
+
+    fn expand_single(&self, element: Element, depth: usize) -> Result<Element> {
+        match element {
+            Element::FunctionCall(fc) => Ok(Element::FunctionCall(self.expand_one(fc, depth)?)),
+            e => Ok(self
+                .expand_element(e, depth)?
+                .pop()
+                .expect("expand_element returns an element")),
+        }
+    }
+
+    fn expand_value(&self, value: Value, depth: usize) -> Result<Value> {
+        let value = match value {
+            Value::FunctionCall(fc) => Value::FunctionCall(self.expand_one(fc, depth)?),
+            Value::Object(o) => Value::Object(self.expand_object(o, depth)?),
+            Value::List(l) => Value::List(self.expand_list(l, depth)?),
+            v => v,
+        };
+        Ok(value)
+    }
+
+    /// Attributes keep their key, except a bare macro call like `{ my_macro(...) }`, keyed by the macro's name, which
+    /// takes the name of the one call it expands to.
+    fn expand_object(&self, object: Object, depth: usize) -> Result<Object> {
+        let mut results = HashMap::with_capacity(object.0.len());
+        let mut renamed = Vec::new();
+        for (key, element) in object.0 {
+            match element {
+                Element::FunctionCall(fc)
+                    if key == fc.identifier && self.macros.contains_key(&key) =>
+                {
+                    let m = &self.macros[&key];
+                    renamed.push((self.expand_one(fc.clone(), depth)?, fc, m));
+                }
+                e => {
+                    results.insert(key, self.expand_single(e, depth)?);
+                }
+            }
+        }
+        for (call, fc, m) in renamed {
+            if results.contains_key(&call.identifier) {
+                return Err(self.error(
+                    format!(
+                        "macro `{}` expands to `{}`, which is already set in this object",
+                        m.name, call.identifier
+                    ),
+                    &fc,
+                    m,
+                ));
+            }
+            results.insert(call.identifier.clone(), Element::FunctionCall(call));
+        }
+        Ok(Object(results))
+    }
+
+    fn expand_list(&self, list: List, depth: usize) -> Result<List> {
+        Ok(List(self.expand_elements(list.0, depth)?))
+    }
+
+    fn expand_one(&self, fc: ASTFunctionCall, depth: usize) -> Result<ASTFunctionCall> {
+        match self.macros.get(&fc.identifier) {
+            None => self.expand_nested(fc, depth),
+            Some(m) => {
+                let span = fc.span;
+                let mut calls = self.expand_call(fc.clone(), m, depth)?;
+                if calls.len() != 1 {
+                    return Err(self.error(
+                        format!(
+                            "macro `{}` expands to {} calls, only one is allowed here",
+                            m.name,
+                            calls.len()
+                        ),
+                        &fc,
+                        m,
+                    ));
+                }
+                let mut call = calls.remove(0);
+                call.span = span;
+                Ok(call)
+            }
+        }
+    }
+
+    fn expand_nested(&self, fc: ASTFunctionCall, depth: usize) -> Result<ASTFunctionCall> {
+        let definition = match fc.definition {
+            None => None,
+            Some(Definition::Object(o)) => Some(Definition::Object(self.expand_object(o, depth)?)),
+            Some(Definition::List(l)) => Some(Definition::List(self.expand_list(l, depth)?)),
+        };
+        Ok(ASTFunctionCall {
+            identifier: fc.identifier,
+            args: self.expand_elements(fc.args, depth)?,
+            definition,
+            span: fc.span,
+        })
+    }
+
+    fn expand_call(
+        &self,
+        call: ASTFunctionCall,
+        m: &Macro,
+        depth: usize,
+    ) -> Result<Vec<ASTFunctionCall>> {
+        if depth >= MAX_EXPANSION_DEPTH {
+            return Err(self.error(
+                format!(
+                    "macro `{}` exceeded the expansion limit of {}, is it recursive?",
+                    m.name, MAX_EXPANSION_DEPTH
+                ),
+                &call,
+                m,
+            ));
+        }
+        if call.definition.is_some() {
+            return Err(self.error(
+                format!("macro `{}` does not take a definition", m.name),
+                &call,
+                m,
+            ));
+        }
+
+        let bindings = self.bind_arguments(&call, m)?;
+        let mut results = Vec::with_capacity(m.body.len());
+        for element in &m.body {
+            let substituted = substitute(element.clone(), &bindings);
+            for expanded in self.expand_element(substituted, depth + 1)? {
+                match expanded {
+                    Element::FunctionCall(mut fc) => {
+                        fc.span = call.span;
+                        results.push(fc)
+                    }
+                    e => {
+                        return Err(self.error(
+                            format!("Unexpected Element in macro body: {:?}", e),
+                            &call,
+                            m,
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn bind(
+        &self,
+        bindings: &mut HashMap<Identifier, Value>,
+        param: &Identifier,
+        value: &Value,
+        call: &ASTFunctionCall,
+        m: &Macro,
+    ) -> Result<()> {
+        if bindings.insert(param.clone(), value.clone()).is_some() {
+            return Err(self.error(
+                format!(
+                    "macro `{}` argument `{}` is given more than once",
+                    m.name, param
+                ),
+                call,
+                m,
+            ));
+        }
+        Ok(())
+    }
+
+    fn bind_arguments(
+        &self,
+        call: &ASTFunctionCall,
+        m: &Macro,
+    ) -> Result<HashMap<Identifier, Value>> {
+        let mut bindings = HashMap::with_capacity(m.params.len());
+        let mut position = 0;
+        for arg in &call.args {
+            match arg {
+                Element::Value(v) => {
+                    let param = match m.params.get(position) {
+                        None => {
+                            return Err(self.error(
+                                format!(
+                                    "macro `{}` expects {} argument(s), found {}",
+                                    m.name,
+                                    m.params.len(),
+                                    call.args.len()
+                                ),
+                                call,
+                                m,
+                            ))
+                        }
+                        Some(p) => p,
+                    };
+                    self.bind(&mut bindings, param, v, call, m)?;
+                    position += 1;
+                }
+                Element::NamedArg(name, v) => {
+                    if !m.params.contains(name) {
+                        return Err(self.error(
+                            format!("macro `{}` has no parameter `{}`", m.name, name),
+                            call,
+                            m,
+                        ));
+                    }
+                    self.bind(&mut bindings, name, v, call, m)?;
+                }
+                e => {
+                    return Err(self.error(
+                        format!("Unexpected Element in macro arguments: {:?}", e),
+                        call,
+                        m,
+                    ))
+                }
+            }
+        }
+
+        let missing: Vec<&str> = m
+            .params
+            .iter()
+            .filter(|p| !bindings.contains_key(*p))
+            .map(|p| p.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(self.error(
+                format!(
+                    "macro `{}` is missing argument(s): {}",
+                    m.name,
+                    missing.join(", ")
+                ),
+                call,
+                m,
+            ));
+        }
+        Ok(bindings)
+    }
+}
+
+/// A parameter is a bare call, `acct` with no args or definition, everything else is left alone.
+fn substitute(element: Element, bindings: &HashMap<Identifier, Value>) -> Element {
+    match element {
+        Element::FunctionCall(fc) => Element::FunctionCall(substitute_call(fc, bindings)),
+        Element::Value(v) => Element::Value(substitute_value(v, bindings)),
+        Element::NamedArg(k, v) => Element::NamedArg(k, substitute_value(v, bindings)),
+        Element::Object(o) => Element::Object(substitute_object(o, bindings)),
+        Element::List(l) => Element::List(substitute_list(l, bindings)),
+        Element::Args(a) => Element::Args(a.into_iter().map(|e| substitute(e, bindings)).collect()),
+        e => e,
+    }
+}
+
+fn substitute_value(value: Value, bindings: &HashMap<Identifier, Value>) -> Value {
+    match value {
+        Value::FunctionCall(fc) => {
+            if fc.args.is_empty() && fc.definition.is_none() {
+                if let Some(v) = bindings.get(&fc.identifier) {
+                    return v.clone();
+                }
+            }
+            Value::FunctionCall(substitute_call(fc, bindings))
+        }
+        Value::Object(o) => Value::Object(substitute_object(o, bindings)),
+        Value::List(l) => Value::List(substitute_list(l, bindings)),
+        v => v,
+    }
+}
+
+fn substitute_call(fc: ASTFunctionCall, bindings: &HashMap<Identifier, Value>) -> ASTFunctionCall {
+    ASTFunctionCall {
+        identifier: fc.identifier,
+        args: fc
+            .args
+            .into_iter()
+            .map(|e| substitute(e, bindings))
+            .collect(),
+        definition: fc.definition.map(|d| match d {
+            Definition::Object(o) => Definition::Object(substitute_object(o, bindings)),
+            Definition::List(l) => Definition::List(substitute_list(l, bindings)),
+        }),
+        span: fc.span,
+    }
+}
+
+fn substitute_object(object: Object, bindings: &HashMap<Identifier, Value>) -> Object {
+    Object(
+        object
+            .0
+            .into_iter()
+            .map(|(k, v)| (k, substitute(v, bindings)))
+            .collect(),
+    )
+}
+
+fn substitute_list(list: List, bindings: &HashMap<Identifier, Value>) -> List {
+    List(
+        list.0
+            .into_iter()
+            .map(|e| substitute(e, bindings))
+            .collect(),
+    )
+}
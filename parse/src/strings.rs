@@ -0,0 +1,45 @@
+/// Body of a `<<~EOS` heredoc, the least indented line sets how much is removed and every line keeps its `\n`.
+pub(crate) fn heredoc(body: &str) -> String {
+    let normalized = body.replace("\r\n", "\n");
+    let lines: Vec<&str> = normalized.lines().collect();
+    let mut result = String::with_capacity(normalized.len());
+    for line in dedent(&lines) {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// `"""` string, a line break right after the opening quotes and a blank line before the closing quotes are
+/// dropped, then common indentation is removed. Unlike a heredoc there is no trailing `\n`.
+pub(crate) fn triple_quoted(raw: &str) -> String {
+    let normalized = raw[3..raw.len() - 3].replace("\r\n", "\n");
+    let mut lines: Vec<&str> = normalized.split('\n').collect();
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+    dedent(&lines).join("\n")
+}
+
+/// Spaces and tabs each count as one column, whitespace only lines don't affect the indentation.
+fn dedent<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| {
+            if l.trim().is_empty() {
+                ""
+            } else {
+                &l[indent..]
+            }
+        })
+        .collect()
+}
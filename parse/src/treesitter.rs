@@ -0,0 +1,237 @@
+use crate::{
+    parse_number, ASTFunctionCall, Definition, Element, List, Object, ParseConfig, ParserBackend,
+    Span, Value, AST,
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::ops::Range;
+use tree_sitter::{Node, Parser};
+
+/// Parses with tree-sitter-rigz, the grammar used by editors, into the same elements as `PestBackend`.
+pub struct TreeSitterBackend;
+
+impl ParserBackend for TreeSitterBackend {
+    fn parse(&self, input: &str, config: &ParseConfig) -> Result<AST> {
+        let mut source = input.to_string();
+        let version = match split_pragma(input) {
+            None => config.version,
+            Some((range, version)) => {
+                let version = version.parse()?;
+                source.replace_range(range.clone(), &" ".repeat(range.len()));
+                version
+            }
+        };
+
+        let mut parser = Parser::new();
+        parser
+            .set_language(&tree_sitter_rigz::language())
+            .map_err(|e| anyhow!("tree-sitter: Error loading Rigz grammar - {}", e))?;
+        let tree = match parser.parse(&source, None) {
+            None => return Err(anyhow!("tree-sitter: parser returned no tree")),
+            Some(tree) => tree,
+        };
+        let root = tree.root_node();
+        if root.has_error() {
+            return Err(syntax_error(root, &source));
+        }
+
+        let converter = Converter {
+            source: &source,
+            config,
+        };
+        let mut elements = Vec::new();
+        for node in children(root) {
+            elements.push(Element::FunctionCall(converter.function_call(node)?));
+        }
+        Ok(AST { elements, version })
+    }
+}
+
+/// tree-sitter-rigz has no pragma, it's read here and replaced with spaces so spans still line up.
+fn split_pragma(input: &str) -> Option<(Range<usize>, &str)> {
+    let start = input.len() - input.trim_start().len();
+    let rest = &input[start..];
+    let after = match rest.strip_prefix("#!") {
+        Some(after) => after,
+        None => {
+            let after = rest.strip_prefix("use")?;
+            let trimmed = after.trim_start_matches([' ', '\t']);
+            if trimmed.len() == after.len() {
+                return None;
+            }
+            trimmed
+        }
+    };
+    let after = after.strip_prefix("rigz")?;
+    let version = after.trim_start_matches([' ', '\t']);
+    if version.len() == after.len() {
+        return None;
+    }
+    let len = version
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(version.len());
+    if len == 0 {
+        return None;
+    }
+    let end = input.len() - version.len() + len;
+    Some((start..end, &version[..len]))
+}
+
+fn syntax_error(root: Node, source: &str) -> anyhow::Error {
+    let mut pending = vec![root];
+    while let Some(node) = pending.pop() {
+        if node.is_error() || node.is_missing() {
+            let (line, column) = Span {
+                start: node.start_byte(),
+                end: node.end_byte(),
+            }
+            .line_col(source);
+            return if node.is_missing() {
+                anyhow!(
+                    "tree-sitter: missing `{}` at line {}, column {}",
+                    node.kind(),
+                    line,
+                    column
+                )
+            } else {
+                anyhow!(
+                    "tree-sitter: syntax error at line {}, column {}",
+                    line,
+                    column
+                )
+            };
+        }
+        let mut cursor = node.walk();
+        let mut nested: Vec<Node> = node
+            .children(&mut cursor)
+            .filter(|n| n.has_error())
+            .collect();
+        nested.reverse();
+        pending.append(&mut nested);
+    }
+    anyhow!("tree-sitter: syntax error")
+}
+
+/// Named children without comments
+fn children(node: Node) -> Vec<Node> {
+    let mut cursor = node.walk();
+    node.named_children(&mut cursor)
+        .filter(|n| !n.is_extra())
+        .collect()
+}
+
+struct Converter<'a> {
+    source: &'a str,
+    config: &'a ParseConfig,
+}
+
+impl Converter<'_> {
+    fn text(&self, node: Node) -> Result<&str> {
+        Ok(node.utf8_text(self.source.as_bytes())?.trim())
+    }
+
+    fn function_call(&self, node: Node) -> Result<ASTFunctionCall> {
+        let name = match node.child_by_field_name("name") {
+            Some(name) => name,
+            None => return Err(anyhow!("Unsupported `function_call`: {}", self.text(node)?)),
+        };
+        let identifier = self.text(name)?;
+        self.config.identifiers.validate(identifier)?;
+
+        let mut args = Vec::new();
+        if let Some(body) = node.child_by_field_name("args") {
+            for arg in children(body) {
+                args.push(Element::Value(self.value(arg)?));
+            }
+        }
+
+        let definition = match node.child_by_field_name("definition") {
+            None => None,
+            Some(definition) => match children(definition).first() {
+                Some(d) if d.kind() == "object" => Some(Definition::Object(self.object(*d)?)),
+                Some(d) if d.kind() == "list" => Some(Definition::List(self.list(*d)?)),
+                _ => {
+                    return Err(anyhow!(
+                        "Unexpected node in `definition`: {}",
+                        definition.to_sexp()
+                    ))
+                }
+            },
+        };
+
+        Ok(ASTFunctionCall {
+            identifier: identifier.to_string(),
+            args,
+            definition,
+            span: Span {
+                start: node.start_byte(),
+                end: node.end_byte(),
+            },
+        })
+    }
+
+    fn value(&self, node: Node) -> Result<Value> {
+        let inner = match children(node).first() {
+            None => return Err(anyhow!("Empty `value`: {}", self.text(node)?)),
+            Some(inner) => *inner,
+        };
+        let value = match inner.kind() {
+            "none" => Value::None,
+            "bool" => match self.text(inner)? {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                value => return Err(anyhow!("Unsupported `bool`: {}", value)),
+            },
+            "number" => match parse_number(self.text(inner)?, self.config)? {
+                Element::Int(i) => Value::Int(i),
+                Element::Long(l) => Value::Long(l),
+                Element::Float(f) => Value::Float(f),
+                Element::Double(d) => Value::Double(d),
+                e => return Err(anyhow!("Unexpected Element in `number`: {:?}", e)),
+            },
+            "string" => {
+                let raw = self.text(inner)?;
+                Value::String(raw[1..raw.len() - 1].to_string())
+            }
+            "object" => Value::Object(self.object(inner)?),
+            "list" => Value::List(self.list(inner)?),
+            "function_call" => Value::FunctionCall(self.function_call(inner)?),
+            kind => return Err(anyhow!("Unexpected node in `value`: {}", kind)),
+        };
+        Ok(value)
+    }
+
+    fn object(&self, node: Node) -> Result<Object> {
+        let mut definition = HashMap::new();
+        let mut last = None;
+        for child in children(node) {
+            match child.kind() {
+                "identifier" => {
+                    let identifier = self.text(child)?;
+                    self.config.identifiers.validate(identifier)?;
+                    last = Some(identifier.to_string());
+                }
+                "value" => match last.take() {
+                    None => return Err(anyhow!("Missing Identifier for Object")),
+                    Some(key) => {
+                        definition.insert(key, Element::Value(self.value(child)?));
+                    }
+                },
+                "function_call" => {
+                    let fc = self.function_call(child)?;
+                    definition.insert(fc.identifier.clone(), Element::FunctionCall(fc));
+                }
+                kind => return Err(anyhow!("Unexpected node in `object`: {}", kind)),
+            }
+        }
+        Ok(Object(definition))
+    }
+
+    fn list(&self, node: Node) -> Result<List> {
+        let mut results = Vec::new();
+        for child in children(node) {
+            results.push(Element::Value(self.value(child)?));
+        }
+        Ok(List(results))
+    }
+}
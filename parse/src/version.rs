@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Language version of a rigz file, set with `#!rigz 0.1` or `use rigz 0.1` on the first line.
+///
+/// Files without a pragma use `ParseConfig::version`, which defaults to the latest version.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize,
+)]
+pub enum LanguageVersion {
+    V0_1,
+    /// Heredoc and triple quoted strings
+    #[default]
+    V0_2,
+}
+
+impl LanguageVersion {
+    pub const SUPPORTED: [LanguageVersion; 2] = [LanguageVersion::V0_1, LanguageVersion::V0_2];
+
+    pub fn latest() -> LanguageVersion {
+        LanguageVersion::SUPPORTED[LanguageVersion::SUPPORTED.len() - 1]
+    }
+
+    pub fn major(&self) -> u32 {
+        match self {
+            LanguageVersion::V0_1 | LanguageVersion::V0_2 => 0,
+        }
+    }
+
+    pub fn minor(&self) -> u32 {
+        match self {
+            LanguageVersion::V0_1 => 1,
+            LanguageVersion::V0_2 => 2,
+        }
+    }
+
+    /// Fails when `feature` was added after this version.
+    pub fn require(&self, feature: &str, since: LanguageVersion) -> Result<()> {
+        if *self < since {
+            return Err(anyhow!(
+                "{} need rigz {}, this file is rigz {}",
+                feature,
+                since,
+                self
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Display for LanguageVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major(), self.minor())
+    }
+}
+
+impl FromStr for LanguageVersion {
+    type Err = Error;
+
+    /// Accepts `major.minor` with an optional patch component, `0.1` and `0.1.3` are both `V0_1`.
+    fn from_str(s: &str) -> Result<Self> {
+        let value = s.trim();
+        let mut parts = value.split('.');
+        let major = parts.next().and_then(|p| p.parse::<u32>().ok());
+        let minor = parts.next().and_then(|p| p.parse::<u32>().ok());
+        let patch_valid = parts.all(|p| p.parse::<u32>().is_ok());
+        if let (Some(major), Some(minor), true) = (major, minor, patch_valid) {
+            for version in LanguageVersion::SUPPORTED {
+                if version.major() == major && version.minor() == minor {
+                    return Ok(version);
+                }
+            }
+        }
+        let supported: Vec<String> = LanguageVersion::SUPPORTED
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        Err(anyhow!(
+            "Unsupported rigz version `{}`, supported versions: {}",
+            value,
+            supported.join(", ")
+        ))
+    }
+}
@@ -0,0 +1,101 @@
+use rigz_core::{
+    rigz_function, rigz_module, Argument, ArgumentPath, ErrorKind, PathError, RigzError,
+    RuntimeStatus,
+};
+
+/// Name the built-in functions are registered under, they're tried first unless a module lists the function
+pub const BUILTINS: &str = "builtins";
+
+/// Functions every runtime has, without any module loaded.
+pub struct Builtins;
+
+#[rigz_module(name = "builtins")]
+impl Builtins {
+    /// Value at `path`, like `a.b[2].c`, in `value`, or in the definition or prior result when only given a path.
+    /// The path can come first when the value isn't a string, like a `do ... end` block. `none` if nothing is there,
+    /// wildcards like `items[*].name` return a list of every match. A module listing `get` is called instead,
+    /// `builtins.get` always calls this one.
+    #[rigz_function]
+    fn get(
+        value_or_path: Argument,
+        path_or_value: Option<Argument>,
+        #[definition] definition: Argument,
+        #[prior_result] prior_result: Argument,
+    ) -> RuntimeStatus<Argument> {
+        let Some((value, path)) = target(value_or_path, path_or_value, definition, prior_result)
+        else {
+            return expected_path();
+        };
+        match lookup(&value, &path) {
+            Ok(found) => RuntimeStatus::Ok(found.unwrap_or(Argument::None)),
+            Err(e) => RuntimeStatus::Err(e.into()),
+        }
+    }
+
+    /// Like `get`, but fails when nothing is at `path`
+    #[rigz_function]
+    fn dig(
+        value_or_path: Argument,
+        path_or_value: Option<Argument>,
+        #[definition] definition: Argument,
+        #[prior_result] prior_result: Argument,
+    ) -> RuntimeStatus<Argument> {
+        let Some((value, path)) = target(value_or_path, path_or_value, definition, prior_result)
+        else {
+            return expected_path();
+        };
+        match lookup(&value, &path) {
+            Ok(Some(Argument::List(found))) if found.is_empty() && is_wildcard(&path) => {
+                missing(&path)
+            }
+            Ok(Some(found)) => RuntimeStatus::Ok(found),
+            Ok(None) => missing(&path),
+            Err(e) => RuntimeStatus::Err(e.into()),
+        }
+    }
+}
+
+/// `get value, path`, `get path, value` or `get path`, which reads the definition if there is one and the prior
+/// result otherwise
+fn target(
+    value_or_path: Argument,
+    path_or_value: Option<Argument>,
+    definition: Argument,
+    prior_result: Argument,
+) -> Option<(Argument, String)> {
+    match (value_or_path, path_or_value) {
+        (value, Some(Argument::String(path))) => Some((value, path)),
+        (Argument::String(path), Some(value)) => Some((value, path)),
+        (Argument::String(path), None) => match definition {
+            Argument::None => Some((prior_result, path)),
+            definition => Some((definition, path)),
+        },
+        _ => None,
+    }
+}
+
+/// Wildcard paths always return a list of their matches
+fn lookup(value: &Argument, path: &str) -> Result<Option<Argument>, PathError> {
+    if is_wildcard(path) {
+        let found = value.select_path(path)?.into_iter().cloned().collect();
+        return Ok(Some(Argument::List(found)));
+    }
+    Ok(value.get_path(path)?.cloned())
+}
+
+/// Invalid paths fail in `lookup`
+fn is_wildcard(path: &str) -> bool {
+    path.parse::<ArgumentPath>()
+        .is_ok_and(|path| path.has_wildcard())
+}
+
+fn expected_path() -> RuntimeStatus<Argument> {
+    RuntimeStatus::Err(RigzError::new(
+        ErrorKind::InvalidArgument,
+        "expected a path, or a value and a path",
+    ))
+}
+
+fn missing(path: &str) -> RuntimeStatus<Argument> {
+    RuntimeStatus::Err(RigzError::runtime(format!("nothing at `{}`", path)))
+}
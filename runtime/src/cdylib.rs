@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use rigz_core::{
+    Argument, CallContext, CallRequest, Definition, FunctionSignature, HookRequest,
+    InitializationArgs, Module, RigzError, RuntimeStatus, ABI_VERSION,
+};
+use serde::de::DeserializeOwned;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+
+type AbiVersion = unsafe extern "C" fn() -> u32;
+type Request = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type Functions = unsafe extern "C" fn() -> *mut c_char;
+type Free = unsafe extern "C" fn(*mut c_char);
+
+/// `library` from module.rigz, relative to the module's root. Without an extension the platform's prefix and
+/// extension are added, `lib/math` is `lib/libmath.so` on Linux and `lib/math.dll` on Windows.
+pub fn library_path(root: &Path, library: &str) -> PathBuf {
+    let path = root.join(library);
+    if path.extension().is_some() {
+        return path;
+    }
+    match path.file_name().and_then(|f| f.to_str()) {
+        Some(file) => path.with_file_name(format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            file,
+            std::env::consts::DLL_SUFFIX
+        )),
+        None => path,
+    }
+}
+
+/// `Module` backed by a shared library exporting the C ABI in `modules/cdylib/rigz_module.h`.
+///
+/// The library runs unsandboxed, declared permissions only restrict the `File` arguments passed to it. Its calls
+/// get the file they're in but can't call back through a `CallContext`.
+pub struct CdylibModule {
+    name: String,
+    root: PathBuf,
+    function_call: Request,
+    hook: Option<Request>,
+    functions: Option<Functions>,
+    free: Free,
+    /// Last so the library is closed after everything else is dropped
+    _library: Library,
+}
+
+impl CdylibModule {
+    /// Opens the library and checks its `rigz_abi_version`.
+    pub fn load(name: impl Into<String>, root: PathBuf, path: &Path) -> Result<CdylibModule> {
+        CdylibModule::load_version(name, root, path, ABI_VERSION)
+    }
+
+    /// `load` for a runtime supporting ABI version `supported`
+    pub(crate) fn load_version(
+        name: impl Into<String>,
+        root: PathBuf,
+        path: &Path,
+        supported: u32,
+    ) -> Result<CdylibModule> {
+        let name = name.into();
+        let library = Library::open(path)?;
+        let missing = |symbol: &str| {
+            anyhow!(
+                "{} doesn't export `{}`, required by rigz modules",
+                path.display(),
+                symbol
+            )
+        };
+        // SAFETY: the types are the ones in rigz_module.h
+        unsafe {
+            let abi_version: AbiVersion = library
+                .symbol("rigz_abi_version")
+                .ok_or_else(|| missing("rigz_abi_version"))?;
+            let version = abi_version();
+            if version != supported {
+                return Err(anyhow!(
+                    "{} was built for module ABI version {}, this runtime supports version {}",
+                    path.display(),
+                    version,
+                    supported
+                ));
+            }
+            Ok(CdylibModule {
+                name,
+                root,
+                function_call: library
+                    .symbol("rigz_function_call")
+                    .ok_or_else(|| missing("rigz_function_call"))?,
+                hook: library.symbol("rigz_hook"),
+                functions: library.symbol("rigz_functions"),
+                free: library
+                    .symbol("rigz_free")
+                    .ok_or_else(|| missing("rigz_free"))?,
+                _library: library,
+            })
+        }
+    }
+
+    /// Sends `request` as JSON and reads the `RuntimeStatus` the library returns.
+    fn request<T: DeserializeOwned>(
+        &self,
+        function: Request,
+        request: &impl serde::Serialize,
+    ) -> RuntimeStatus<T> {
+        let request = match serde_json::to_string(request) {
+            Ok(request) => CString::new(request).expect("JSON has no NUL"),
+            Err(e) => {
+                return RuntimeStatus::Err(
+                    self.error(format!("failed to serialize request: {}", e)),
+                )
+            }
+        };
+        // SAFETY: `request` outlives the call and the response is freed by the library that allocated it
+        let response = unsafe { self.take(function(request.as_ptr())) };
+        match response {
+            None => RuntimeStatus::Err(self.error("library returned null")),
+            Some(response) => serde_json::from_str(&response).unwrap_or_else(|e| {
+                RuntimeStatus::Err(self.error(format!("invalid response: {}", e)))
+            }),
+        }
+    }
+
+    /// Copies a string returned by the library and frees it.
+    unsafe fn take(&self, s: *mut c_char) -> Option<String> {
+        if s.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(s).to_string_lossy().into_owned();
+        (self.free)(s);
+        Some(owned)
+    }
+
+    fn error(&self, message: impl Into<String>) -> RigzError {
+        RigzError::runtime(message).with_module(&self.name)
+    }
+
+    /// Hooks the library doesn't export succeed
+    fn hook(&self, request: HookRequest) -> RuntimeStatus<()> {
+        match self.hook {
+            Some(hook) => self.request(hook, &request),
+            None => RuntimeStatus::Ok(()),
+        }
+    }
+}
+
+impl Module for CdylibModule {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn root(&self) -> PathBuf {
+        self.root.clone()
+    }
+
+    fn function_call(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: Argument,
+        context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
+        let request = CallRequest {
+            name: name.to_string(),
+            arguments,
+            definition,
+            prior_result,
+            file: context.file.map(str::to_string),
+        };
+        self.request(self.function_call, &request)
+    }
+
+    fn initialize(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Initialize { args })
+    }
+
+    fn functions(&self) -> Vec<FunctionSignature> {
+        let Some(functions) = self.functions else {
+            return Vec::new();
+        };
+        // SAFETY: freed by the library that allocated it
+        let Some(json) = (unsafe { self.take(functions()) }) else {
+            return Vec::new();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|e| {
+            warn!("{}: invalid rigz_functions: {}", self.name, e);
+            Vec::new()
+        })
+    }
+
+    fn on_file_start(&self, file: &str) -> RuntimeStatus<()> {
+        self.hook(HookRequest::OnFileStart {
+            file: file.to_string(),
+        })
+    }
+
+    fn on_file_end(&self, file: &str, result: &Argument) -> RuntimeStatus<()> {
+        self.hook(HookRequest::OnFileEnd {
+            file: file.to_string(),
+            result: result.clone(),
+        })
+    }
+
+    fn shutdown(&self) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Shutdown)
+    }
+
+    fn reload(&self, args: InitializationArgs) -> RuntimeStatus<()> {
+        self.hook(HookRequest::Reload { args })
+    }
+}
+
+/// Handle from `dlopen`, closed when dropped
+struct Library(*mut c_void);
+
+// The handle is only passed to dlsym and dlclose, which are thread safe
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+#[cfg(unix)]
+impl Library {
+    fn open(path: &Path) -> Result<Library> {
+        use std::os::unix::ffi::OsStrExt;
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: `c_path` is NUL terminated, the library's initializers run here
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(anyhow!("Failed to load {}: {}", path.display(), dlerror()));
+        }
+        Ok(Library(handle))
+    }
+
+    /// # Safety
+    /// `T` has to be the symbol's function pointer type.
+    unsafe fn symbol<T: Copy>(&self, name: &str) -> Option<T> {
+        let name = CString::new(name).ok()?;
+        let symbol = libc::dlsym(self.0, name.as_ptr());
+        if symbol.is_null() {
+            None
+        } else {
+            Some(std::mem::transmute_copy(&symbol))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn dlerror() -> String {
+    // SAFETY: dlerror returns null or a NUL terminated string
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        "unknown error".to_string()
+    } else {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Library {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from dlopen and nothing from the library is used after this
+        unsafe {
+            libc::dlclose(self.0);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Library {
+    fn open(path: &Path) -> Result<Library> {
+        Err(anyhow!(
+            "cdylib modules are only supported on unix, can't load {}",
+            path.display()
+        ))
+    }
+
+    unsafe fn symbol<T: Copy>(&self, _name: &str) -> Option<T> {
+        None
+    }
+}
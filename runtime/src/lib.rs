@@ -1,18 +1,25 @@
+pub mod builtins;
+pub mod cdylib;
 pub mod modules;
 pub mod parse;
 pub mod run;
 
+use crate::builtins::BUILTINS;
 use crate::modules::{ModuleDefinition, ModuleOptions};
 use crate::parse::{parse_source_files, ParseOptions};
-use crate::run::RunArgs;
+use crate::run::{to_rigz_error, RunArgs};
 use anyhow::{anyhow, Error, Result};
-use log::{trace, warn};
-use rigz_core::{Argument, Definition, InitializationArgs, Module, RuntimeStatus};
-use rigz_parse::AST;
+use log::{info, trace, warn};
+use rigz_core::{
+    Argument, AsyncModule, BoxFuture, CallContext, Capability, Definition, ErrorKind, FileMode,
+    FunctionSignature, InitializationArgs, Invoker, ModulePermissions, RigzError, RuntimeStatus,
+    Scope, SourceSpan, SyncModule,
+};
+use rigz_parse::{Span, AST};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone, Default, Deserialize)]
 pub struct Options {
@@ -20,29 +27,41 @@ pub struct Options {
     pub disable_std_lib: Option<bool>,
     pub modules: Option<Vec<ModuleOptions>>,
     pub parse: Option<ParseOptions>,
+    /// Short names for functions, `{"deploy": "k8s.apply"}`, these win over aliases from modules
+    pub aliases: Option<HashMap<String, String>>,
 }
 
 pub struct RuntimeConfig {
     asts: HashMap<String, AST>,
+    sources: HashMap<String, String>,
     pub modules: Vec<ModuleDefinition>,
+    aliases: HashMap<String, String>,
 }
 
 impl RuntimeConfig {
-    pub(crate) fn initialize_args(&self, args: Rc<RunArgs>) -> InitializationArgs {
-        InitializationArgs {
-            all_errors_fatal: args.all_errors_fatal,
-            ignore_symbol_not_found: args.ignore_symbol_not_found,
-            prefer_none_over_prior_result: args.prefer_none_over_prior_result,
-            require_aliases: args.require_aliases,
-        }
+    pub(crate) fn initialize_args(&self, args: Arc<RunArgs>) -> InitializationArgs {
+        args.as_ref().into()
     }
 }
 
+pub type GlobalFunction = Box<dyn Fn(Vec<Argument>, Definition, &Argument) + Send + Sync>;
+pub type LookupFunction = Box<dyn Fn(String, Vec<Argument>, Definition, &Argument) + Send + Sync>;
+
+/// Everything a run needs, `Send + Sync` so it can be shared in an `Arc` and files can run on separate threads.
 pub struct Runtime {
     asts: HashMap<String, AST>,
-    pub modules: HashMap<String, Box<dyn Module>>,
-    pub globals: HashMap<String, Box<dyn Fn(Vec<Argument>, Definition, &Argument)>>,
-    pub lookup: Vec<Box<dyn Fn(String, Vec<Argument>, Definition, &Argument)>>,
+    sources: HashMap<String, String>,
+    pub modules: HashMap<String, Arc<dyn SyncModule>>,
+    pub async_modules: HashMap<String, Arc<dyn AsyncModule>>,
+    signatures: HashMap<String, HashMap<String, FunctionSignature>>,
+    /// Modules loaded from a `module.rigz`, modules added in code aren't restricted
+    permissions: HashMap<String, ModulePermissions>,
+    pub globals: HashMap<String, GlobalFunction>,
+    pub lookup: Vec<LookupFunction>,
+    /// `module.function` each alias calls
+    aliases: HashMap<String, String>,
+    /// Module names in the order unqualified calls try them, from `Options.modules`
+    order: Vec<String>,
 }
 
 pub enum RunResult<T> {
@@ -51,7 +70,24 @@ pub enum RunResult<T> {
     Err(Error),
 }
 
+/// Module a call resolved to
+#[derive(Clone, Copy)]
+enum ModuleRef<'a> {
+    Sync(&'a Arc<dyn SyncModule>),
+    Async(&'a Arc<dyn AsyncModule>),
+}
+
+/// A module to try for a call, and the function to call it with
+struct Candidate<'a> {
+    module_name: &'a str,
+    function: String,
+    module: ModuleRef<'a>,
+    /// Called as `module.function` or through an alias
+    qualified: bool,
+}
+
 impl Runtime {
+    /// Calls `name` outside of a file, with an empty scope.
     pub fn invoke_symbol(
         &self,
         name: &str,
@@ -60,105 +96,633 @@ impl Runtime {
         prior_result: &Argument,
         config: &RunArgs,
     ) -> Result<Argument> {
-        if config.require_aliases {
-            // let module = self.module(name)?;
-            // check global symbols
-            // if name contains '.' split and try to find module
-            todo!()
-        }
-
-        match self.attempt_call_module_function(name, &arguments, &definition, prior_result) {
-            RuntimeStatus::Ok(a) => return Ok(a),
-            RuntimeStatus::NotFound => {}
-            RuntimeStatus::Err(e) => return Err(anyhow!("Function Call Failed - {}", e)),
-        }
-
-        // fallback, check each module
-        let mut actual_result = None;
-        for (module_name, module) in &self.modules {
-            trace!("Checking `{}` in Module: {}", name, module_name);
-            let result = match module.function_call(
-                name,
-                arguments.clone(),
-                definition.clone(),
-                prior_result.clone(),
-            ) {
-                RuntimeStatus::Ok(a) => a,
-                RuntimeStatus::NotFound => {
-                    warn!("Not Found: {}", name);
-                    continue;
+        let scope = Scope::default();
+        let context = CallContext::new(self, &scope, config.into());
+        self.invoke_in(name, arguments, definition, prior_result, &context)
+    }
+
+    /// Calls `name`, the module gets `context`. The run's arguments are `context.args`.
+    pub fn invoke_in(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: &Argument,
+        context: &CallContext,
+    ) -> Result<Argument> {
+        let config = RunArgs::from(context.args);
+        for candidate in self.candidates(name, &config)? {
+            let Candidate {
+                module_name,
+                function,
+                module,
+                qualified,
+            } = candidate;
+            if let Some(e) = self.validate(module_name, &function, &arguments, &definition) {
+                return Err(e.with_module(module_name).into());
+            }
+            let permissions = self.permissions.get(module_name);
+            if let Some(e) = permissions.and_then(|p| check_files(p, &arguments, &definition)) {
+                // only the module handling the call has to be allowed its files
+                if qualified || self.lists(module_name, &function) {
+                    return Err(e.with_function(function).into());
                 }
-                RuntimeStatus::Err(e) => {
-                    return Err(anyhow!("Function Invocation Failed: {} {}", name, e))
+                trace!(
+                    "{} can't use the files passed to {}, skipping it",
+                    module_name,
+                    function
+                );
+                continue;
+            }
+            let context = context.clone().with_permissions(permissions);
+            let status = match module {
+                ModuleRef::Sync(m) => m.function_call(
+                    &function,
+                    arguments.clone(),
+                    definition.clone(),
+                    prior_result.clone(),
+                    &context,
+                ),
+                ModuleRef::Async(_) => {
+                    warn!(
+                        "{} is async, skipping `{}`, use invoke_symbol_async",
+                        module_name, name
+                    );
+                    continue;
                 }
             };
-            actual_result = Some(result);
-            break;
-        }
-
-        match actual_result {
-            Some(a) => Ok(a),
-            None => {
-                let message = std::format!("`Failed to find function - {}", name);
-                if config.all_errors_fatal {
-                    Err(anyhow!("{}", message))
-                } else {
-                    warn!("{}", message);
-                    Ok(Argument::Error(format!("`Failed to find function - {}", name)))
-                }
+            if let Some(result) = call_result(module_name, &function, status) {
+                return result;
             }
         }
+        function_not_found(name, &config)
     }
 
-    fn attempt_call_module_function(
+    /// Like `invoke_symbol`, awaiting `AsyncModule`s. `SyncModule`s are called directly, blocking until they return.
+    pub async fn invoke_symbol_async(
         &self,
         name: &str,
-        arguments: &Vec<Argument>,
-        definition: &Definition,
+        arguments: Vec<Argument>,
+        definition: Definition,
         prior_result: &Argument,
-    ) -> RuntimeStatus<Argument> {
-        if name.contains('.') {
-            trace!("Attempting to find module call for {}", name);
-            let mut parts = name.split('.');
-            let module_name = parts.next();
-            if module_name.is_none() {
-                warn!(
-                    "module_name starts with ., {}, defaulting to fall back method",
-                    module_name.unwrap()
+        config: &RunArgs,
+    ) -> Result<Argument> {
+        let scope = Scope::default();
+        let context = CallContext::new(self, &scope, config.into());
+        self.invoke_in_async(name, arguments, definition, prior_result, &context)
+            .await
+    }
+
+    /// Like `invoke_in`, awaiting `AsyncModule`s. Calls they make through `context` are synchronous.
+    pub async fn invoke_in_async(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: &Argument,
+        context: &CallContext<'_>,
+    ) -> Result<Argument> {
+        let config = RunArgs::from(context.args);
+        for candidate in self.candidates(name, &config)? {
+            let Candidate {
+                module_name,
+                function,
+                module,
+                qualified,
+            } = candidate;
+            if let Some(e) = self.validate(module_name, &function, &arguments, &definition) {
+                return Err(e.with_module(module_name).into());
+            }
+            let permissions = self.permissions.get(module_name);
+            if let Some(e) = permissions.and_then(|p| check_files(p, &arguments, &definition)) {
+                // only the module handling the call has to be allowed its files
+                if qualified || self.lists(module_name, &function) {
+                    return Err(e.with_function(function).into());
+                }
+                trace!(
+                    "{} can't use the files passed to {}, skipping it",
+                    module_name,
+                    function
                 );
-                return RuntimeStatus::NotFound;
+                continue;
             }
-            if module_name != Some(".") {
-                let module = self.modules.get(module_name.unwrap());
-                if module.is_none() {
+            let context = context.clone().with_permissions(permissions);
+            let (arguments, definition, prior_result) =
+                (arguments.clone(), definition.clone(), prior_result.clone());
+            let status = match module {
+                ModuleRef::Sync(m) => {
+                    m.function_call(&function, arguments, definition, prior_result, &context)
+                }
+                ModuleRef::Async(m) => {
+                    m.function_call(&function, arguments, definition, prior_result, &context)
+                        .await
+                }
+            };
+            if let Some(result) = call_result(module_name, &function, status) {
+                return result;
+            }
+        }
+        function_not_found(name, &config)
+    }
+
+    /// Registers and initializes an `AsyncModule`, it's only called by `invoke_symbol_async` and `run_async`.
+    pub async fn add_async_module(
+        &mut self,
+        module: Arc<dyn AsyncModule>,
+        args: &RunArgs,
+    ) -> Result<()> {
+        let name = module.name().to_string();
+        if self.module_ref(&name).is_some() {
+            return Err(run::duplicate_module(&name).into());
+        }
+        match module.initialize(args.into()).await {
+            RuntimeStatus::Ok(_) => {}
+            RuntimeStatus::NotFound => {
+                info!("Not Initialization Method for {}", name);
+            }
+            RuntimeStatus::Err(e) => {
+                return Err(RigzError::new(
+                    ErrorKind::Initialization,
+                    "Module initialization failed",
+                )
+                .with_module(name)
+                .with_cause(e)
+                .into())
+            }
+        }
+        let functions = module.functions().into_iter().map(|f| (f.name.clone(), f));
+        self.signatures.insert(name.clone(), functions.collect());
+        self.order.push(name.clone());
+        self.async_modules.insert(name, module);
+        Ok(())
+    }
+
+    /// Where `span` is in `file`, with its line and column
+    pub fn source_span(&self, file: &str, span: Span) -> SourceSpan {
+        let source = self
+            .sources
+            .get(file)
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        let (line, column) = span.line_col(source);
+        SourceSpan {
+            file: file.to_string(),
+            start: span.start,
+            end: span.end,
+            line,
+            column,
+        }
+    }
+
+    /// Functions each module defines, by module name
+    pub fn functions(&self) -> HashMap<&str, Vec<FunctionSignature>> {
+        self.modules
+            .iter()
+            .map(|(name, module)| (name.as_str(), module.functions()))
+            .collect()
+    }
+
+    /// Calls `on_file_start` on every module.
+    pub fn on_file_start(&self, file: &str) -> Result<()> {
+        self.each_module("on_file_start", |m| m.on_file_start(file))
+    }
+
+    /// Like `on_file_start`, awaiting `AsyncModule`s
+    pub async fn on_file_start_async<'a>(&'a self, file: &'a str) -> Result<()> {
+        self.each_module_async(
+            "on_file_start",
+            |m| m.on_file_start(file),
+            |m| m.on_file_start(file),
+        )
+        .await
+    }
+
+    /// Calls `on_file_end` on every module, `result` is the file's last value or the error it failed with.
+    pub fn on_file_end(&self, file: &str, result: &Argument) -> Result<()> {
+        self.each_module("on_file_end", |m| m.on_file_end(file, result))
+    }
+
+    /// Like `on_file_end`, awaiting `AsyncModule`s
+    pub async fn on_file_end_async<'a>(
+        &'a self,
+        file: &'a str,
+        result: &'a Argument,
+    ) -> Result<()> {
+        self.each_module_async(
+            "on_file_end",
+            |m| m.on_file_end(file, result),
+            |m| m.on_file_end(file, result),
+        )
+        .await
+    }
+
+    /// Calls `shutdown` on every module, `run` does this once all files have run.
+    pub fn shutdown(&self) -> Result<()> {
+        self.each_module("shutdown", |m| m.shutdown())
+    }
+
+    /// Like `shutdown`, awaiting `AsyncModule`s, `run_async` does this once all files have run.
+    pub async fn shutdown_async(&self) -> Result<()> {
+        self.each_module_async("shutdown", |m| m.shutdown(), |m| m.shutdown())
+            .await
+    }
+
+    /// Calls `reload` on every module and reads their function signatures again.
+    pub fn reload(&mut self, config: &RunArgs) -> Result<()> {
+        let result = self.each_module("reload", |m| m.reload(config.into()));
+        self.read_signatures();
+        result
+    }
+
+    /// Like `reload`, awaiting `AsyncModule`s
+    pub async fn reload_async(&mut self, config: &RunArgs) -> Result<()> {
+        let result = self
+            .each_module_async(
+                "reload",
+                |m| m.reload(config.into()),
+                |m| m.reload(config.into()),
+            )
+            .await;
+        self.read_signatures();
+        result
+    }
+
+    fn read_signatures(&mut self) {
+        let sync = self.modules.iter().map(|(n, m)| (n, m.functions()));
+        let functions: Vec<_> = sync
+            .chain(self.async_modules.iter().map(|(n, m)| (n, m.functions())))
+            .collect();
+        for (name, functions) in functions {
+            let functions = functions.into_iter().map(|f| (f.name.clone(), f));
+            self.signatures.insert(name.clone(), functions.collect());
+        }
+        if let Some(builtins) = self.signatures.get(BUILTINS) {
+            for name in builtins.keys() {
+                for module in self.defined_by(name) {
                     warn!(
-                        "Module not found, {}, defaulting to fall back method",
-                        module_name.unwrap()
+                        "{}.{} shadows the built-in `{}`, call `{}.{}` for the built-in",
+                        module, name, name, BUILTINS, name
                     );
-                    return RuntimeStatus::NotFound;
                 }
+            }
+        }
+    }
 
-                let module = module.unwrap();
-                let mut new_name = String::new();
-                for str in parts {
-                    new_name.push_str(str);
+    /// Calls `hook` on every module, even after one fails. The first error is returned, the rest are logged.
+    /// `AsyncModule`s are skipped, the `_async` hooks await them.
+    fn each_module(
+        &self,
+        hook: &str,
+        call: impl Fn(&dyn SyncModule) -> RuntimeStatus<()>,
+    ) -> Result<()> {
+        let mut error = None;
+        for (name, module) in self.module_order() {
+            let ModuleRef::Sync(module) = module else {
+                warn!("{} is async, skipping `{}`, use {}_async", name, hook, hook);
+                continue;
+            };
+            keep_first_error(&mut error, name, hook, call(module.as_ref()));
+        }
+        match error {
+            None => Ok(()),
+            Some(e) => Err(e.into()),
+        }
+    }
+
+    /// Like `each_module`, awaiting `call_async` for `AsyncModule`s
+    async fn each_module_async<'a>(
+        &'a self,
+        hook: &str,
+        call: impl Fn(&dyn SyncModule) -> RuntimeStatus<()>,
+        call_async: impl Fn(&'a dyn AsyncModule) -> BoxFuture<'a, RuntimeStatus<()>>,
+    ) -> Result<()> {
+        let mut error = None;
+        for (name, module) in self.module_order() {
+            let status = match module {
+                ModuleRef::Sync(module) => call(module.as_ref()),
+                ModuleRef::Async(module) => call_async(module.as_ref()).await,
+            };
+            keep_first_error(&mut error, name, hook, status);
+        }
+        match error {
+            None => Ok(()),
+            Some(e) => Err(e.into()),
+        }
+    }
+
+    /// Checks a call against the module's signature for `name`, functions without a signature aren't checked. Like
+    /// `LuaModule`, `max-retries` falls back to `max_retries`.
+    fn validate(
+        &self,
+        module_name: &str,
+        name: &str,
+        arguments: &[Argument],
+        definition: &Definition,
+    ) -> Option<RigzError> {
+        let signature = self.signatures.get(module_name).and_then(|functions| {
+            functions
+                .get(name)
+                .or_else(|| functions.get(&name.replace('-', "_")))
+        });
+        signature.and_then(|s| s.validate(arguments, definition))
+    }
+
+    /// The first alias, sorted by name, whose `module.function` isn't a loaded module, or a function it doesn't list.
+    /// Modules without signatures aren't checked.
+    pub(crate) fn unknown_alias(&self) -> Option<RigzError> {
+        let mut aliases: Vec<_> = self.aliases.iter().collect();
+        aliases.sort();
+        aliases.into_iter().find_map(|(alias, target)| {
+            let (module_name, function) = target.split_once('.')?;
+            let message = match self.signatures.get(module_name) {
+                None if self.module_ref(module_name).is_none() => {
+                    format!(
+                        "Alias `{}` calls `{}`, module `{}` isn't loaded",
+                        alias, target, module_name
+                    )
                 }
-                return module.function_call(
-                    new_name.as_str(),
-                    arguments.clone(),
-                    definition.clone(),
-                    prior_result.clone(),
-                );
+                Some(functions)
+                    if !functions.is_empty()
+                        && !functions.contains_key(function)
+                        && !functions.contains_key(&function.replace('-', "_")) =>
+                {
+                    format!(
+                        "Alias `{}` calls `{}`, {} has no function `{}`",
+                        alias, target, module_name, function
+                    )
+                }
+                _ => return None,
+            };
+            Some(
+                RigzError::new(ErrorKind::Initialization, message)
+                    .with_module(module_name)
+                    .with_function(alias.as_str()),
+            )
+        })
+    }
+
+    /// Modules whose `functions()` list `name`, sorted, the built-in functions aren't included.
+    pub fn defined_by(&self, name: &str) -> Vec<&str> {
+        let snake_case = name.replace('-', "_");
+        let mut modules: Vec<&str> = self
+            .signatures
+            .iter()
+            .filter(|(module, functions)| {
+                module.as_str() != BUILTINS
+                    && (functions.contains_key(name) || functions.contains_key(&snake_case))
+            })
+            .map(|(module, _)| module.as_str())
+            .collect();
+        modules.sort();
+        modules
+    }
+
+    /// Module and function `name` resolves to, the first candidate whose `functions()` list it. `None` if no module
+    /// lists it, modules without signatures may still handle the call.
+    pub fn which(&self, name: &str, config: &RunArgs) -> Result<Option<(&str, String)>> {
+        let found = self
+            .candidates(name, config)?
+            .into_iter()
+            .find(|c| self.lists(c.module_name, &c.function));
+        Ok(found.map(|c| (c.module_name, c.function)))
+    }
+
+    /// Whether `module_name`'s `functions()` list `function`, or its `_` version
+    fn lists(&self, module_name: &str, function: &str) -> bool {
+        self.signatures.get(module_name).is_some_and(|functions| {
+            functions.contains_key(function) || functions.contains_key(&function.replace('-', "_"))
+        })
+    }
+
+    /// Every module in the order unqualified calls try them, modules missing from `order` follow sorted by name and
+    /// the built-in functions are last. `candidates` moves them first for their own functions.
+    fn module_order(&self) -> Vec<(&str, ModuleRef<'_>)> {
+        let mut rest: Vec<&str> = self
+            .modules
+            .keys()
+            .chain(self.async_modules.keys())
+            .map(|name| name.as_str())
+            .filter(|name| *name != BUILTINS && !self.order.iter().any(|o| o == name))
+            .collect();
+        rest.sort();
+        self.order
+            .iter()
+            .map(|name| name.as_str())
+            .filter(|name| *name != BUILTINS)
+            .chain(rest)
+            .chain([BUILTINS])
+            .filter_map(|name| self.module_ref(name))
+            .collect()
+    }
+
+    fn module_ref(&self, name: &str) -> Option<(&str, ModuleRef<'_>)> {
+        self.modules
+            .get_key_value(name)
+            .map(|(k, m)| (k.as_str(), ModuleRef::Sync(m)))
+            .or_else(|| {
+                self.async_modules
+                    .get_key_value(name)
+                    .map(|(k, m)| (k.as_str(), ModuleRef::Async(m)))
+            })
+    }
+
+    /// Modules to try for `name`, and the function name to call each with. Aliases are replaced by their
+    /// `module.function`, which tries `module` first, then every module is tried with the full name in the order of
+    /// `Options.modules`, the built-in functions last. A built-in function no module lists is tried first, so modules
+    /// answering any name can't shadow it.
+    ///
+    /// With `require_aliases` only aliases, `module.function` and built-in functions resolve. Other names fail with
+    /// the modules that define them, names no module handles fail in `function_not_found`.
+    fn candidates(&self, name: &str, config: &RunArgs) -> Result<Vec<Candidate<'_>>> {
+        let name = match self.aliases.get(name) {
+            Some(target) => {
+                trace!("Alias {} calls {}", name, target);
+                target.as_str()
+            }
+            None => name,
+        };
+        let mut candidates = Vec::with_capacity(self.modules.len() + self.async_modules.len() + 1);
+        if let Some((module_name, function)) = name.split_once('.') {
+            trace!("Attempting to find module call for {}", name);
+            match self.module_ref(module_name) {
+                Some((module_name, module)) => candidates.push(Candidate {
+                    module_name,
+                    function: function.replace('.', ""),
+                    module,
+                    qualified: true,
+                }),
+                None => warn!(
+                    "Module not found, {}, defaulting to fall back method",
+                    module_name
+                ),
+            }
+        }
+
+        if config.require_aliases {
+            let modules = self.defined_by(name);
+            if candidates.is_empty() && !modules.is_empty() {
+                return Err(aliases_required(name, modules).into());
+            }
+        }
+        let mut order = self.module_order();
+        if self.lists(BUILTINS, name) && self.defined_by(name).is_empty() {
+            order.sort_by_key(|(module_name, _)| *module_name != BUILTINS);
+        }
+        for (module_name, module) in order {
+            // only the built-in functions are tried when aliases are required
+            if config.require_aliases && module_name != BUILTINS {
+                continue;
+            }
+            candidates.push(Candidate {
+                module_name,
+                function: name.to_string(),
+                module,
+                qualified: false,
+            });
+        }
+        Ok(candidates)
+    }
+}
+
+/// Error for a name that isn't an alias or `module.function` when aliases are required, with the modules defining
+/// it in `data.modules`
+fn aliases_required(name: &str, modules: Vec<&str>) -> RigzError {
+    let message = match modules.as_slice() {
+        [] => format!(
+            "Failed to find function - {}, no module defines it and aliases are required",
+            name
+        ),
+        [module] => format!(
+            "`{}` isn't an alias, call it as `{}.{}` or add an alias, aliases are required",
+            name, module, name
+        ),
+        _ => format!(
+            "`{}` is ambiguous, it's defined by {}, call it as `<module>.{}` or add an alias",
+            name,
+            modules.join(", "),
+            name
+        ),
+    };
+    let list = modules
+        .iter()
+        .map(|m| Argument::String(m.to_string()))
+        .collect();
+    RigzError::not_found(message)
+        .with_function(name)
+        .with_data("modules", Argument::List(list))
+}
+
+/// Calls modules make through their `CallContext`
+impl Invoker for Runtime {
+    fn invoke(
+        &self,
+        name: &str,
+        arguments: Vec<Argument>,
+        definition: Definition,
+        prior_result: &Argument,
+        context: &CallContext,
+    ) -> RuntimeStatus<Argument> {
+        match self.invoke_in(name, arguments, definition, prior_result, context) {
+            Ok(a) => RuntimeStatus::Ok(a),
+            Err(e) => RuntimeStatus::Err(to_rigz_error(e)),
+        }
+    }
+}
+
+/// Keeps the first error a hook returns, later ones are logged
+fn keep_first_error(
+    error: &mut Option<RigzError>,
+    module: &str,
+    hook: &str,
+    status: RuntimeStatus<()>,
+) {
+    if let RuntimeStatus::Err(e) = status {
+        let e = e.with_module(module).with_function(hook);
+        match error {
+            None => *error = Some(e),
+            Some(_) => warn!("{}", e),
+        }
+    }
+}
+
+/// Files passed to a restricted module have to be readable, or writable if they aren't opened for reading.
+fn check_files(
+    permissions: &ModulePermissions,
+    arguments: &[Argument],
+    definition: &Definition,
+) -> Option<RigzError> {
+    let definition = match definition {
+        Definition::None => Vec::new(),
+        Definition::One(o) => o.values().collect(),
+        Definition::Many(l) => l.iter().collect(),
+    };
+    let mut pending: Vec<&Argument> = arguments.iter().chain(definition).collect();
+    while let Some(argument) = pending.pop() {
+        let capability = match argument {
+            Argument::File(f) if f.mode() == FileMode::Read => Capability::FsRead(f.path()),
+            Argument::File(f) => Capability::FsWrite(f.path()),
+            Argument::List(l) => {
+                pending.extend(l);
+                continue;
+            }
+            Argument::Object(o) => {
+                pending.extend(o.values());
+                continue;
             }
+            _ => continue,
+        };
+        if let Err(e) = permissions.check(capability) {
+            return Some(e.into());
         }
-        RuntimeStatus::NotFound
+    }
+    None
+}
+
+/// `Some` once a module handled the call, `None` to keep looking
+fn call_result(
+    module_name: &str,
+    function: &str,
+    status: RuntimeStatus<Argument>,
+) -> Option<Result<Argument>> {
+    match status {
+        RuntimeStatus::Ok(a) => Some(Ok(a)),
+        RuntimeStatus::NotFound => {
+            trace!("Not Found: {} in Module: {}", function, module_name);
+            None
+        }
+        RuntimeStatus::Err(e) => Some(Err(e
+            .with_module(module_name)
+            .with_function(function)
+            .into())),
+    }
+}
+
+/// An `Argument::Error`, unless `all_errors_fatal` or `require_aliases` is set. With `require_aliases` every name
+/// that doesn't resolve is an error, like a name defined by modules without an alias.
+fn function_not_found(name: &str, config: &RunArgs) -> Result<Argument> {
+    if config.require_aliases {
+        return Err(aliases_required(name, Vec::new()).into());
+    }
+    let error =
+        RigzError::not_found(format!("Failed to find function - {}", name)).with_function(name);
+    if config.all_errors_fatal {
+        Err(error.into())
+    } else {
+        warn!("{}", error);
+        Ok(Argument::Error(error))
     }
 }
 
 pub fn initialize(options: Options) -> Result<RuntimeConfig> {
-    let asts = parse_source_files(options.parse.clone().unwrap_or_default())?;
+    let (asts, sources) = parse_source_files(options.parse.clone().unwrap_or_default())?;
+    let aliases = options.aliases.clone().unwrap_or_default();
     let modules = setup_modules(options)?;
-    Ok(RuntimeConfig { asts, modules })
+    Ok(RuntimeConfig {
+        asts,
+        sources,
+        modules,
+        aliases,
+    })
 }
 
 fn create_cache_dir(options: &Options) -> String {
@@ -179,11 +743,13 @@ fn setup_modules(options: Options) -> Result<Vec<ModuleDefinition>> {
     if !options.disable_std_lib.unwrap_or(false) {
         base_modules.append(ModuleOptions::default_options().as_mut())
     }
+    // stable, modules with the same priority keep their order
+    base_modules.sort_by_key(|m| std::cmp::Reverse(m.priority.unwrap_or(0)));
     for module in base_modules {
         let name = module.name.as_str();
         let definition = module
             .download(PathBuf::from(cache_directory.clone()))
-            .unwrap_or_else(|_| panic!("Failed to Download Module {}", name));
+            .unwrap_or_else(|e| panic!("Failed to Download Module {}: {}", name, e));
         modules.push(definition);
     }
     Ok(modules)
@@ -201,6 +767,28 @@ pub(crate) fn path_to_string(path: &PathBuf) -> Result<String> {
 mod tests {
     use super::*;
     use log::LevelFilter;
+    use rigz_core::Module;
+
+    impl Runtime {
+        /// A runtime with only `modules`, without files, permissions, or aliases
+        fn for_test(
+            modules: HashMap<String, Arc<dyn SyncModule>>,
+            signatures: HashMap<String, HashMap<String, FunctionSignature>>,
+        ) -> Runtime {
+            Runtime {
+                asts: HashMap::new(),
+                sources: HashMap::new(),
+                modules,
+                async_modules: HashMap::new(),
+                signatures,
+                permissions: HashMap::new(),
+                globals: HashMap::new(),
+                lookup: Vec::new(),
+                aliases: HashMap::new(),
+                order: Vec::new(),
+            }
+        }
+    }
 
     fn hello_world_options() -> Options {
         log::set_max_level(LevelFilter::Trace);
@@ -208,11 +796,15 @@ mod tests {
             cache_directory: None,
             parse: Some(ParseOptions {
                 use_64_bit_numbers: None,
+                version: None,
+                identifiers: None,
+                backend: None,
                 source_files: vec!["../examples/hello_world/hello.rigz".to_string()],
                 glob_options: None,
             }),
             disable_std_lib: None,
             modules: None,
+            aliases: None,
         }
     }
 
@@ -221,4 +813,997 @@ mod tests {
         let result = initialize(hello_world_options()).expect("Failed to initialize");
         assert!(!result.asts.is_empty());
     }
+
+    struct Notify;
+
+    impl Module for Notify {
+        fn name(&self) -> &str {
+            "notify"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call(
+            &self,
+            _name: &str,
+            _arguments: Vec<Argument>,
+            _definition: Definition,
+            _prior_result: Argument,
+            _context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            RuntimeStatus::Ok(Argument::Bool(true))
+        }
+
+        fn functions(&self) -> Vec<FunctionSignature> {
+            let schema = rigz_core::DefinitionSchema::new()
+                .field("channel", rigz_core::ArgumentType::String)
+                .optional_field("retries", rigz_core::ArgumentType::Long);
+            vec![FunctionSignature::new("send")
+                .parameter("message", rigz_core::ArgumentType::String)
+                .with_schema(schema)]
+        }
+    }
+
+    #[test]
+    fn runtime_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Runtime>();
+    }
+
+    #[test]
+    fn invalid_arguments_fail_before_dispatch() {
+        let module: Arc<dyn SyncModule> = Arc::new(Notify);
+        let signatures = HashMap::from([(
+            "notify".to_string(),
+            HashMap::from([("send".to_string(), module.functions().remove(0))]),
+        )]);
+        let runtime =
+            Runtime::for_test(HashMap::from([("notify".to_string(), module)]), signatures);
+        let args = RunArgs::default();
+
+        let definition = Definition::One(HashMap::from([(
+            "channel".to_string(),
+            Argument::String("ops".into()),
+        )]));
+        let result = runtime.invoke_symbol(
+            "notify.send",
+            vec![Argument::String("deployed".into())],
+            definition,
+            &Argument::None,
+            &args,
+        );
+        assert_eq!(result.unwrap(), Argument::Bool(true));
+
+        let definition = Definition::One(HashMap::from([
+            ("chanel".to_string(), Argument::String("ops".into())),
+            ("retries".to_string(), Argument::String("3".into())),
+        ]));
+        let error = runtime
+            .invoke_symbol("send", vec![], definition, &Argument::None, &args)
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, rigz_core::ErrorKind::InvalidArgument);
+        assert_eq!(error.location(), Some("notify.send".to_string()));
+        assert_eq!(
+            error.data["errors"],
+            Argument::List(vec![
+                Argument::String("expected 1 argument, found 0".into()),
+                Argument::String("missing key `channel`".into()),
+                Argument::String(
+                    "unknown key `chanel`, expected one of `channel`, `retries`".into()
+                ),
+                Argument::String("`retries` expected long, found String".into()),
+            ])
+        );
+    }
+
+    /// Pending on the first poll, like a function waiting on a socket
+    struct Fetch;
+
+    impl AsyncModule for Fetch {
+        fn name(&self) -> &str {
+            "fetch"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call<'a>(
+            &'a self,
+            name: &'a str,
+            arguments: Vec<Argument>,
+            _definition: Definition,
+            _prior_result: Argument,
+            _context: &'a CallContext<'a>,
+        ) -> rigz_core::BoxFuture<'a, RuntimeStatus<Argument>> {
+            Box::pin(async move {
+                if name != "get" {
+                    return RuntimeStatus::NotFound;
+                }
+                let mut yielded = false;
+                std::future::poll_fn(|cx| {
+                    if yielded {
+                        std::task::Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        std::task::Poll::Pending
+                    }
+                })
+                .await;
+                RuntimeStatus::Ok(Argument::List(arguments))
+            })
+        }
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark()
+            }
+        }
+
+        let waker = Arc::new(Unpark(std::thread::current())).into();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                std::task::Poll::Ready(output) => return output,
+                std::task::Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn invoke_symbol_async_awaits_async_and_calls_sync_modules() {
+        let mut runtime = Runtime::for_test(
+            HashMap::from([(
+                "notify".to_string(),
+                Arc::new(Notify) as Arc<dyn SyncModule>,
+            )]),
+            HashMap::new(),
+        );
+        let args = RunArgs::default();
+        block_on(runtime.add_async_module(Arc::new(Fetch), &args)).expect("Failed to add module");
+
+        let url = vec![Argument::String("https://example.com".into())];
+        let result = block_on(runtime.invoke_symbol_async(
+            "fetch.get",
+            url.clone(),
+            Definition::None,
+            &Argument::None,
+            &args,
+        ));
+        assert_eq!(result.unwrap(), Argument::List(url.clone()));
+
+        let result = block_on(runtime.invoke_symbol_async(
+            "notify.send",
+            vec![Argument::String("deployed".into())],
+            Definition::None,
+            &Argument::None,
+            &args,
+        ));
+        assert_eq!(result.unwrap(), Argument::Bool(true));
+
+        // sync callers skip async modules
+        let result =
+            runtime.invoke_symbol("fetch.get", url, Definition::None, &Argument::None, &args);
+        assert_eq!(result.unwrap(), Argument::Bool(true));
+
+        fn assert_send<T: Send>(_: T) {}
+        assert_send(run::run_async(&runtime, args));
+    }
+
+    /// Records every hook and call
+    #[derive(Default)]
+    struct Hooks {
+        events: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Hooks {
+        fn record(&self, event: String) -> RuntimeStatus<()> {
+            self.events.lock().unwrap().push(event);
+            RuntimeStatus::Ok(())
+        }
+    }
+
+    impl Module for Hooks {
+        fn name(&self) -> &str {
+            "hooks"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call(
+            &self,
+            name: &str,
+            _arguments: Vec<Argument>,
+            _definition: Definition,
+            _prior_result: Argument,
+            _context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            self.record(format!("call {}", name));
+            RuntimeStatus::Ok(Argument::Bool(true))
+        }
+
+        fn on_file_start(&self, file: &str) -> RuntimeStatus<()> {
+            self.record(format!("start {}", file))
+        }
+
+        fn on_file_end(&self, file: &str, result: &Argument) -> RuntimeStatus<()> {
+            self.record(format!("end {} {}", file, result))
+        }
+
+        fn shutdown(&self) -> RuntimeStatus<()> {
+            self.record("shutdown".to_string());
+            RuntimeStatus::Err(RigzError::runtime("connection already closed"))
+        }
+
+        fn reload(&self, _args: InitializationArgs) -> RuntimeStatus<()> {
+            self.record("reload".to_string())
+        }
+    }
+
+    #[test]
+    fn run_calls_lifecycle_hooks() {
+        let hooks = Arc::new(Hooks::default());
+        let ast = rigz_parse::parse("flush".to_string(), &Default::default()).unwrap();
+        let mut runtime = Runtime::for_test(
+            HashMap::from([("hooks".to_string(), hooks.clone() as Arc<dyn SyncModule>)]),
+            HashMap::new(),
+        );
+        runtime.asts = HashMap::from([("main.rigz".to_string(), ast)]);
+        let args = RunArgs::default();
+        runtime.reload(&args).expect("Failed to reload");
+
+        let error = run::run(&runtime, args)
+            .err()
+            .expect("shutdown should fail the run");
+        let error = error.downcast::<RigzError>().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "hooks.shutdown: connection already closed"
+        );
+        assert_eq!(
+            *hooks.events.lock().unwrap(),
+            vec![
+                "reload",
+                "start main.rigz",
+                "call flush",
+                "end main.rigz true",
+                "shutdown"
+            ]
+        );
+    }
+
+    /// `Hooks` as an `AsyncModule`
+    #[derive(Default)]
+    struct AsyncHooks(Hooks);
+
+    impl AsyncModule for AsyncHooks {
+        fn name(&self) -> &str {
+            "async_hooks"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call<'a>(
+            &'a self,
+            name: &'a str,
+            arguments: Vec<Argument>,
+            definition: Definition,
+            prior_result: Argument,
+            context: &'a CallContext<'a>,
+        ) -> rigz_core::BoxFuture<'a, RuntimeStatus<Argument>> {
+            let status = self
+                .0
+                .function_call(name, arguments, definition, prior_result, context);
+            Box::pin(std::future::ready(status))
+        }
+
+        fn initialize(
+            &self,
+            _args: InitializationArgs,
+        ) -> rigz_core::BoxFuture<'_, RuntimeStatus<()>> {
+            Box::pin(std::future::ready(RuntimeStatus::Ok(())))
+        }
+
+        fn on_file_start<'a>(
+            &'a self,
+            file: &'a str,
+        ) -> rigz_core::BoxFuture<'a, RuntimeStatus<()>> {
+            Box::pin(std::future::ready(self.0.on_file_start(file)))
+        }
+
+        fn on_file_end<'a>(
+            &'a self,
+            file: &'a str,
+            result: &'a Argument,
+        ) -> rigz_core::BoxFuture<'a, RuntimeStatus<()>> {
+            Box::pin(std::future::ready(self.0.on_file_end(file, result)))
+        }
+
+        fn shutdown(&self) -> rigz_core::BoxFuture<'_, RuntimeStatus<()>> {
+            Box::pin(std::future::ready(Module::shutdown(&self.0)))
+        }
+
+        fn reload(&self, args: InitializationArgs) -> rigz_core::BoxFuture<'_, RuntimeStatus<()>> {
+            Box::pin(std::future::ready(Module::reload(&self.0, args)))
+        }
+    }
+
+    #[test]
+    fn run_async_calls_lifecycle_hooks_of_async_modules() {
+        let hooks = Arc::new(AsyncHooks::default());
+        let ast = rigz_parse::parse("flush".to_string(), &Default::default()).unwrap();
+        let mut runtime = Runtime::for_test(HashMap::new(), HashMap::new());
+        runtime.asts = HashMap::from([("main.rigz".to_string(), ast)]);
+        let args = RunArgs::default();
+        block_on(runtime.add_async_module(hooks.clone(), &args)).expect("Failed to add module");
+        block_on(runtime.reload_async(&args)).expect("Failed to reload");
+
+        let error = block_on(run::run_async(&runtime, args))
+            .err()
+            .expect("shutdown should fail the run");
+        assert_eq!(
+            error.to_string(),
+            "async_hooks.shutdown: connection already closed"
+        );
+        assert_eq!(
+            *hooks.0.events.lock().unwrap(),
+            vec![
+                "reload",
+                "start main.rigz",
+                "call flush",
+                "end main.rigz true",
+                "shutdown"
+            ]
+        );
+        // sync hooks skip async modules
+        runtime.shutdown().expect("async modules aren't shut down");
+        assert_eq!(hooks.0.events.lock().unwrap().len(), 5);
+    }
+
+    /// Calls back into the runtime through its `CallContext`
+    struct Compose;
+
+    impl Module for Compose {
+        fn name(&self) -> &str {
+            "compose"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call(
+            &self,
+            name: &str,
+            mut arguments: Vec<Argument>,
+            _definition: Definition,
+            prior_result: Argument,
+            context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            match name {
+                "call" => match arguments.remove(0) {
+                    Argument::String(symbol) => {
+                        context.invoke_symbol(&symbol, arguments, Definition::None, &prior_result)
+                    }
+                    _ => RuntimeStatus::NotFound,
+                },
+                "eval" => match arguments.remove(0) {
+                    Argument::FunctionCall(fc) => context.evaluate(fc, &prior_result),
+                    _ => RuntimeStatus::NotFound,
+                },
+                "remember" => {
+                    context.scope.set("last", prior_result);
+                    RuntimeStatus::Ok(Argument::None)
+                }
+                "recall" => RuntimeStatus::Ok(context.scope.get("last").unwrap_or(Argument::None)),
+                "where" => RuntimeStatus::Ok(Argument::String(format!(
+                    "{}:{}",
+                    context.file.unwrap_or_default(),
+                    context.span.as_ref().map(|s| s.line).unwrap_or_default()
+                ))),
+                "forever" => context.invoke_symbol(
+                    "compose.forever",
+                    vec![],
+                    Definition::None,
+                    &prior_result,
+                ),
+                "again" => RuntimeStatus::Ok(Argument::FunctionCall(rigz_core::FunctionCall {
+                    name: "compose.again".into(),
+                    args: vec![],
+                    definition: Definition::None,
+                })),
+                _ => RuntimeStatus::NotFound,
+            }
+        }
+    }
+
+    /// The directory modules/cdylib/rigz_math is built in. It's a dev-dependency, so cargo builds it next to the test
+    /// binary, wherever the target directory is.
+    /// Every call returns a call to itself
+    struct Again;
+
+    impl Module for Again {
+        fn name(&self) -> &str {
+            "again"
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call(
+            &self,
+            name: &str,
+            _arguments: Vec<Argument>,
+            _definition: Definition,
+            _prior_result: Argument,
+            _context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            RuntimeStatus::Ok(Argument::FunctionCall(rigz_core::FunctionCall {
+                name: name.to_string(),
+                args: vec![],
+                definition: Definition::None,
+            }))
+        }
+    }
+
+    #[test]
+    fn returned_calls_stop_at_the_call_depth() {
+        let ast = rigz_parse::parse("again.forever".to_string(), &Default::default()).unwrap();
+        let mut runtime = Runtime::for_test(
+            HashMap::from([("again".to_string(), Arc::new(Again) as Arc<dyn SyncModule>)]),
+            HashMap::new(),
+        );
+        runtime.asts = HashMap::from([("main.rigz".to_string(), ast)]);
+
+        let error = run::run(&runtime, RunArgs::default()).err().unwrap();
+        assert!(
+            error.to_string().contains("Call depth exceeded 64"),
+            "{}",
+            error
+        );
+        let error = block_on(run::run_async(&runtime, RunArgs::default()))
+            .err()
+            .unwrap();
+        assert!(
+            error.to_string().contains("Call depth exceeded 64"),
+            "{}",
+            error
+        );
+    }
+
+    fn rigz_math_dir() -> PathBuf {
+        let test = std::env::current_exe().expect("failed to find the test binary");
+        test.parent()
+            .expect("the test binary is in a directory")
+            .to_path_buf()
+    }
+
+    #[test]
+    fn cdylib_modules_are_called_through_the_c_abi() {
+        let dir = rigz_math_dir();
+        let library = cdylib::library_path(&dir, "rigz_math");
+        let math = cdylib::CdylibModule::load("math", dir.clone(), &library).unwrap();
+        let functions: Vec<String> = math.functions().iter().map(|f| f.to_string()).collect();
+        assert_eq!(
+            functions,
+            vec![
+                "add(a: long, b: long) -> long",
+                "div(a: double, b: double) -> double"
+            ]
+        );
+
+        let runtime = Runtime::for_test(
+            HashMap::from([("math".to_string(), Arc::new(math) as Arc<dyn SyncModule>)]),
+            HashMap::new(),
+        );
+        let args = RunArgs::default();
+        let call = |name: &str, arguments: Vec<Argument>| {
+            runtime.invoke_symbol(name, arguments, Definition::None, &Argument::None, &args)
+        };
+        assert_eq!(
+            call("math.add", vec![Argument::Int(1), Argument::Long(2)]).unwrap(),
+            Argument::Long(3)
+        );
+        let error = call("math.div", vec![Argument::Int(1), Argument::Int(0)]).unwrap_err();
+        assert!(error.to_string().contains("division by zero"), "{}", error);
+        let error = call(
+            "math.add",
+            vec![Argument::String("one".into()), Argument::Int(2)],
+        );
+        assert!(error.unwrap_err().to_string().contains("at `a`"));
+        assert!(matches!(
+            call("math.sub", vec![]).unwrap(),
+            Argument::Error(e) if e.kind == ErrorKind::NotFound
+        ));
+
+        let Err(error) = cdylib::CdylibModule::load("math", dir.clone(), &dir.join("missing.so"))
+        else {
+            panic!("missing.so doesn't exist");
+        };
+        assert!(error.to_string().starts_with("Failed to load"), "{}", error);
+    }
+
+    #[test]
+    fn cdylib_modules_built_for_another_abi_version_are_not_loaded() {
+        let dir = rigz_math_dir();
+        let library = cdylib::library_path(&dir, "rigz_math");
+        let supported = rigz_core::ABI_VERSION + 1;
+        let Err(error) = cdylib::CdylibModule::load_version("math", dir, &library, supported)
+        else {
+            panic!("rigz_math is built for version {}", rigz_core::ABI_VERSION);
+        };
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} was built for module ABI version {}, this runtime supports version {}",
+                library.display(),
+                rigz_core::ABI_VERSION,
+                supported
+            )
+        );
+    }
+
+    #[test]
+    fn modules_call_back_through_call_context() {
+        let mut runtime = Runtime::for_test(
+            HashMap::from([
+                (
+                    "notify".to_string(),
+                    Arc::new(Notify) as Arc<dyn SyncModule>,
+                ),
+                (
+                    "compose".to_string(),
+                    Arc::new(Compose) as Arc<dyn SyncModule>,
+                ),
+            ]),
+            HashMap::new(),
+        );
+        runtime.sources = HashMap::from([("main.rigz".to_string(), "\nnotify.ping".to_string())]);
+        let args = RunArgs::default();
+        let call = |name: &str, arguments: Vec<Argument>| {
+            runtime.invoke_symbol(name, arguments, Definition::None, &Argument::None, &args)
+        };
+
+        let ping = Argument::String("notify.ping".into());
+        assert_eq!(
+            call("compose.call", vec![ping]).unwrap(),
+            Argument::Bool(true)
+        );
+        let block = Argument::FunctionCall(rigz_core::FunctionCall {
+            name: "compose.call".into(),
+            args: vec![Argument::String("notify.ping".into())],
+            definition: Definition::None,
+        });
+        assert_eq!(
+            call("compose.eval", vec![block]).unwrap(),
+            Argument::Bool(true)
+        );
+        let error = call("compose.forever", vec![]).unwrap_err().to_string();
+        assert!(error.contains("Call depth exceeded 64"), "{}", error);
+        // a block that keeps returning itself
+        let again = Argument::FunctionCall(rigz_core::FunctionCall {
+            name: "compose.again".into(),
+            args: vec![],
+            definition: Definition::None,
+        });
+        let error = call("compose.eval", vec![again]).unwrap_err().to_string();
+        assert!(error.contains("Call depth exceeded 64"), "{}", error);
+
+        let scope = Scope::default();
+        let context = CallContext::new(&runtime, &scope, (&args).into())
+            .with_file("main.rigz")
+            .with_span(runtime.source_span("main.rigz", Span { start: 1, end: 12 }));
+        let invoke = |name: &str, prior_result: &Argument| {
+            runtime.invoke_in(name, vec![], Definition::None, prior_result, &context)
+        };
+        invoke("compose.remember", &Argument::Int(42)).unwrap();
+        assert_eq!(
+            invoke("compose.recall", &Argument::None).unwrap(),
+            Argument::Int(42)
+        );
+        assert_eq!(
+            invoke("compose.where", &Argument::None).unwrap(),
+            Argument::String("main.rigz:2".into())
+        );
+    }
+
+    #[test]
+    fn restricted_modules_only_get_permitted_files() {
+        let granted = rigz_core::Permissions {
+            fs_read: vec![PathBuf::from("reports")],
+            fs_write: vec![PathBuf::from("out")],
+            ..Default::default()
+        };
+        let permissions = ModulePermissions::new("notify", granted.clone(), granted);
+        let mut runtime = Runtime::for_test(
+            HashMap::from([(
+                "notify".to_string(),
+                Arc::new(Notify) as Arc<dyn SyncModule>,
+            )]),
+            HashMap::new(),
+        );
+        runtime.permissions = HashMap::from([("notify".to_string(), permissions)]);
+        let args = RunArgs::default();
+        let attach = |file: rigz_core::RigzFile| {
+            let arguments = vec![Argument::List(vec![Argument::File(file)])];
+            runtime.invoke_symbol(
+                "notify.attach",
+                arguments,
+                Definition::None,
+                &Argument::None,
+                &args,
+            )
+        };
+
+        let report = rigz_core::RigzFile::new("reports/daily.csv");
+        assert_eq!(attach(report.clone()).unwrap(), Argument::Bool(true));
+        let output = rigz_core::RigzFile::new("out/daily.csv").with_mode(FileMode::Write);
+        assert_eq!(attach(output).unwrap(), Argument::Bool(true));
+
+        let error = attach(report.with_mode(FileMode::Append))
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::PermissionDenied);
+        assert_eq!(error.location(), Some("notify.attach".to_string()));
+        assert!(
+            error.message.contains("`fs_write reports/daily.csv`"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn files_are_only_checked_for_the_module_handling_the_call() {
+        let granted = rigz_core::Permissions {
+            fs_read: vec![PathBuf::from("reports")],
+            ..Default::default()
+        };
+        let mut runtime = Runtime::for_test(
+            HashMap::from([named("notify"), named("slack")]),
+            HashMap::new(),
+        );
+        runtime.order = vec!["notify".to_string(), "slack".to_string()];
+        runtime.permissions = HashMap::from([
+            (
+                "notify".to_string(),
+                ModulePermissions::new("notify", Default::default(), Default::default()),
+            ),
+            (
+                "slack".to_string(),
+                ModulePermissions::new("slack", granted.clone(), granted),
+            ),
+        ]);
+        let args = RunArgs::default();
+        let attach = |runtime: &Runtime| {
+            let report = rigz_core::RigzFile::new("reports/daily.csv");
+            runtime.invoke_symbol(
+                "attach",
+                vec![Argument::File(report)],
+                Definition::None,
+                &Argument::None,
+                &args,
+            )
+        };
+
+        assert_eq!(attach(&runtime).unwrap(), Argument::String("slack".into()));
+
+        // notify handles the call once it lists `attach`
+        let attach_signature =
+            FunctionSignature::new("attach").parameter("report", rigz_core::ArgumentType::File);
+        runtime.signatures = HashMap::from([(
+            "notify".to_string(),
+            HashMap::from([("attach".to_string(), attach_signature)]),
+        )]);
+        let error = attach(&runtime)
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn builtins_get_nested_values_unless_a_module_lists_them() {
+        let config = RuntimeConfig {
+            asts: HashMap::new(),
+            sources: HashMap::new(),
+            modules: Vec::new(),
+            aliases: HashMap::new(),
+        };
+        let mut runtime = run::initialize_runtime(config, Arc::new(RunArgs::default())).unwrap();
+        let args = RunArgs::default();
+        let config = Argument::Object(HashMap::from([(
+            "ports".to_string(),
+            Argument::List(vec![Argument::Int(80), Argument::Int(443)]),
+        )]));
+        let definition = Definition::One(HashMap::from([("server".to_string(), config.clone())]));
+        let call = |runtime: &Runtime, name: &str, arguments: Vec<Argument>, prior: &Argument| {
+            runtime.invoke_symbol(name, arguments, definition.clone(), prior, &args)
+        };
+        let path = |p: &str| Argument::String(p.into());
+
+        assert_eq!(
+            call(
+                &runtime,
+                "get",
+                vec![path("server.ports[-1]")],
+                &Argument::None
+            )
+            .unwrap(),
+            Argument::Int(443)
+        );
+        assert_eq!(
+            call(
+                &runtime,
+                "get",
+                vec![config.clone(), path("ports[*]")],
+                &Argument::None
+            )
+            .unwrap(),
+            Argument::List(vec![Argument::Int(80), Argument::Int(443)])
+        );
+        let error = runtime
+            .invoke_symbol(
+                "dig",
+                vec![path("ports[2]")],
+                Definition::None,
+                &config,
+                &args,
+            )
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.message, "nothing at `ports[2]`");
+        assert_eq!(error.location(), Some("builtins.dig".to_string()));
+        let error = call(&runtime, "get", vec![path("server[")], &Argument::None).unwrap_err();
+        assert!(error.to_string().contains("unclosed `[`"), "{}", error);
+
+        // notify answers any name, but only shadows `get` once it lists it
+        runtime
+            .modules
+            .insert("notify".to_string(), Arc::new(Notify));
+        assert_eq!(
+            call(&runtime, "get", vec![path("server")], &Argument::None).unwrap(),
+            config
+        );
+        runtime.signatures.insert(
+            "notify".to_string(),
+            HashMap::from([("get".to_string(), FunctionSignature::new("get").variadic())]),
+        );
+        assert_eq!(
+            call(&runtime, "get", vec![path("server")], &Argument::None).unwrap(),
+            Argument::Bool(true)
+        );
+        assert_eq!(
+            call(
+                &runtime,
+                "builtins.get",
+                vec![path("server")],
+                &Argument::None
+            )
+            .unwrap(),
+            config
+        );
+    }
+
+    /// `notify` and `slack` both defining `send`
+    fn send_runtime() -> Runtime {
+        let send = HashMap::from([("send".to_string(), FunctionSignature::new("send"))]);
+        Runtime::for_test(
+            HashMap::from([named("notify"), named("slack")]),
+            HashMap::from([
+                ("notify".to_string(), send.clone()),
+                ("slack".to_string(), send),
+            ]),
+        )
+    }
+
+    fn call_requiring_aliases(runtime: &Runtime, name: &str) -> Result<Argument> {
+        let args = RunArgs {
+            require_aliases: true,
+            ..Default::default()
+        };
+        runtime.invoke_symbol(name, vec![], Definition::None, &Argument::None, &args)
+    }
+
+    #[test]
+    fn aliases_call_their_module_function() {
+        let mut runtime = send_runtime();
+        runtime.aliases = HashMap::from([("page".to_string(), "slack.send".to_string())]);
+
+        assert_eq!(
+            call_requiring_aliases(&runtime, "page").unwrap(),
+            Argument::String("slack".into())
+        );
+    }
+
+    #[test]
+    fn require_aliases_resolves_qualified_names() {
+        let runtime = send_runtime();
+
+        assert_eq!(
+            call_requiring_aliases(&runtime, "notify.send").unwrap(),
+            Argument::String("notify".into())
+        );
+    }
+
+    #[test]
+    fn require_aliases_rejects_names_defined_by_modules() {
+        let runtime = send_runtime();
+
+        let error = call_requiring_aliases(&runtime, "send")
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::NotFound);
+        assert!(
+            error.message.contains("defined by notify, slack"),
+            "{}",
+            error.message
+        );
+        assert_eq!(
+            error.data["modules"],
+            Argument::List(vec![
+                Argument::String("notify".into()),
+                Argument::String("slack".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn require_aliases_rejects_unknown_names() {
+        let runtime = send_runtime();
+
+        let error = call_requiring_aliases(&runtime, "missing")
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::NotFound);
+        assert_eq!(error.data["modules"], Argument::List(vec![]));
+    }
+
+    #[test]
+    fn modules_defining_an_alias_differently_fail() {
+        let declared = |target: &str| HashMap::from([("page".to_string(), target.to_string())]);
+        let error = run::resolve_aliases(
+            HashMap::new(),
+            vec![
+                ("slack".to_string(), declared("slack.send")),
+                ("notify".to_string(), declared("notify.send")),
+            ],
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Alias `page` is defined by notify (notify.send), slack (slack.send), set it in the options' `aliases`"
+        );
+        let aliases = run::resolve_aliases(
+            declared("notify.send"),
+            vec![("slack".to_string(), declared("slack.send"))],
+        )
+        .unwrap();
+        assert_eq!(aliases["page"], "notify.send");
+    }
+
+    #[test]
+    fn alias_targets_have_to_exist() {
+        let initialize = |target: &str| {
+            let config = RuntimeConfig {
+                asts: HashMap::new(),
+                sources: HashMap::new(),
+                modules: Vec::new(),
+                aliases: HashMap::from([("page".to_string(), target.to_string())]),
+            };
+            run::initialize_runtime(config, Arc::new(RunArgs::default()))
+        };
+
+        assert!(initialize("builtins.get").is_ok());
+        let error = initialize("slack.send")
+            .err()
+            .unwrap()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::Initialization);
+        assert_eq!(
+            error.message,
+            "Alias `page` calls `slack.send`, module `slack` isn't loaded"
+        );
+        let error = initialize("builtins.send")
+            .err()
+            .unwrap()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(
+            error.message,
+            "Alias `page` calls `builtins.send`, builtins has no function `send`"
+        );
+    }
+
+    /// Returns its name for every call
+    struct Named(&'static str);
+
+    fn named(name: &'static str) -> (String, Arc<dyn SyncModule>) {
+        (name.to_string(), Arc::new(Named(name)))
+    }
+
+    impl Module for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn root(&self) -> PathBuf {
+            PathBuf::new()
+        }
+
+        fn function_call(
+            &self,
+            _name: &str,
+            _arguments: Vec<Argument>,
+            _definition: Definition,
+            _prior_result: Argument,
+            _context: &CallContext,
+        ) -> RuntimeStatus<Argument> {
+            RuntimeStatus::Ok(Argument::String(self.0.to_string()))
+        }
+    }
+
+    #[test]
+    fn modules_resolve_in_order_and_names_are_unique() {
+        let send = HashMap::from([("send".to_string(), FunctionSignature::new("send"))]);
+        let mut runtime = Runtime::for_test(
+            HashMap::from([named("notify"), named("slack"), named("fetch")]),
+            HashMap::from([
+                ("notify".to_string(), send.clone()),
+                ("slack".to_string(), send),
+            ]),
+        );
+        runtime.order = vec!["slack".to_string(), "notify".to_string()];
+        let args = RunArgs::default();
+        let call = |runtime: &Runtime, name: &str| {
+            runtime
+                .invoke_symbol(name, vec![], Definition::None, &Argument::None, &args)
+                .unwrap()
+        };
+
+        assert_eq!(call(&runtime, "send"), Argument::String("slack".into()));
+        assert_eq!(
+            call(&runtime, "notify.send"),
+            Argument::String("notify".into())
+        );
+        // modules missing from `order` come after it
+        assert_eq!(call(&runtime, "lookup"), Argument::String("slack".into()));
+        assert_eq!(
+            runtime.which("send", &args).unwrap(),
+            Some(("slack", "send".to_string()))
+        );
+        assert_eq!(runtime.which("lookup", &args).unwrap(), None);
+
+        runtime.order.reverse();
+        assert_eq!(call(&runtime, "send"), Argument::String("notify".into()));
+
+        let error = block_on(runtime.add_async_module(Arc::new(Fetch), &args))
+            .unwrap_err()
+            .downcast::<RigzError>()
+            .unwrap();
+        assert_eq!(error.kind, ErrorKind::Initialization);
+        assert_eq!(
+            error.message,
+            "Module `fetch` is already loaded, module names have to be unique"
+        );
+    }
 }
@@ -1,18 +1,21 @@
-use crate::run::RunArgs;
-use crate::{path_to_string, Module};
+use crate::cdylib::{library_path, CdylibModule};
+use crate::run::{element_to_arg, RunArgs};
+use crate::path_to_string;
 use anyhow::{anyhow, Result};
 use glob::{glob};
 use log::{info, warn};
-use rigz_lua::LuaModule;
+use rigz_lua::LuaPool;
+use rigz_core::{from_argument, ModulePermissions, Permissions, SyncModule};
 use rigz_parse::{parse, Definition, Element, ParseConfig};
+use rigz_wasm::{WasmLimits, WasmModule};
 use serde::Deserialize;
 use serde_value::Value;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone, Default, Deserialize)]
 pub struct ModuleOptions {
@@ -23,6 +26,9 @@ pub struct ModuleOptions {
     pub dist: Option<String>,
     pub metadata: Option<HashMap<String, String>>,
     pub config: Option<Value>,
+    /// Higher priorities are tried first for unqualified calls, defaults to 0. Ties keep the order of
+    /// `Options.modules`, std is last unless a module's priority is negative.
+    pub priority: Option<i32>,
 }
 
 impl ModuleOptions {
@@ -35,6 +41,7 @@ impl ModuleOptions {
             dist: None, // TODO: Not used, for future module support
             metadata: None,
             config: None,
+            priority: None,
         }]
     }
 }
@@ -108,13 +115,32 @@ impl ModuleOptions {
     pub(crate) fn download(&self, cache_path: PathBuf) -> Result<ModuleDefinition> {
         let dest = cache_path.join(self.clone_path());
         let _repo = self.download_source(&dest)?;
-        self.load_config(&dest)
+        let mut definition = self.load_config(&dest)?;
+        definition.granted = self.config_value("permissions")?;
+        definition.limits = self.config_value("limits")?;
+        Ok(definition)
+    }
+
+    /// `config.<key>` set by the user, like `permissions`, what the module is allowed to do, or the `limits` of a
+    /// `wasm` module
+    fn config_value<T: Default + for<'de> Deserialize<'de>>(&self, key: &str) -> Result<T> {
+        let value = match &self.config {
+            Some(Value::Map(config)) => config.get(&Value::String(key.to_string())),
+            _ => None,
+        };
+        match value {
+            None => Ok(T::default()),
+            Some(v) => v
+                .clone()
+                .deserialize_into()
+                .map_err(|e| anyhow!("Invalid `config.{}` for module {}: {}", key, self.name, e)),
+        }
     }
 
     fn clone_path(&self) -> &str {
         self.source
             .split('/')
-            .last()
+            .next_back()
             .expect("failed to split module.source on '/'")
             .split('.')
             .nth(0)
@@ -137,15 +163,15 @@ impl ModuleOptions {
         Ok(repo)
     }
 
-    fn module_source_path(&self, dest: &PathBuf) -> PathBuf {
-        let base_path = dest.clone();
+    fn module_source_path(&self, dest: &Path) -> PathBuf {
+        let base_path = dest.to_path_buf();
         match &self.sub_folder {
             None => base_path,
             Some(p) => base_path.join(p),
         }
     }
 
-    fn load_config(&self, dest: &PathBuf) -> Result<ModuleDefinition> {
+    fn load_config(&self, dest: &Path) -> Result<ModuleDefinition> {
         let config_path = self.module_source_path(dest).join("module.rigz");
 
         if !config_path.exists() {
@@ -186,30 +212,98 @@ impl ModuleOptions {
     }
 }
 
+/// `type` in module.rigz
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModuleType {
+    /// `source_files` run in a Lua state
+    #[default]
+    Lua,
+    /// A shared library exporting the C ABI in `modules/cdylib/rigz_module.h`, at `library`
+    Cdylib,
+    /// `source_files` compiled to WebAssembly, run sandboxed within `config.limits`
+    Wasm,
+}
+
 #[derive(Default, Deserialize)]
 pub struct ModuleDefinition {
     name: String,
+    #[serde(default, rename = "type")]
+    module_type: ModuleType,
+    /// Path to the library of a `cdylib` module, relative to its root
+    library: Option<String>,
     config: Option<Value>,
     #[serde(skip_deserializing)]
     root: Option<PathBuf>,
     source_files: Option<Vec<String>>,
+    /// Declared in `module.rigz`
+    #[serde(default)]
+    permissions: Permissions,
+    #[serde(skip_deserializing)]
+    granted: Permissions,
+    #[serde(skip_deserializing)]
+    limits: WasmLimits,
+    /// Short names for the module's functions, `deploy = "apply"` or `deploy = "k8s.apply"`
+    #[serde(default)]
+    aliases: HashMap<String, String>,
 }
 
 impl ModuleDefinition {
-    pub fn to_module(self, _run_args: Rc<RunArgs>) -> Result<Box<dyn Module>> {
+    pub fn to_module(self, _run_args: Arc<RunArgs>) -> Result<Arc<dyn SyncModule>> {
+        let root = self.root.clone().expect("Missing root directory for module");
+        if self.module_type == ModuleType::Cdylib {
+            let library = self
+                .library
+                .as_deref()
+                .ok_or_else(|| anyhow!("{} has `type = \"cdylib\"` but no `library`", self.name))?;
+            let module =
+                CdylibModule::load(self.name, root.clone(), &library_path(&root, library))?;
+            return Ok(Arc::new(module));
+        }
         let source_files = self.source_files()?;
-        let name = self.name.clone();
-        let module: Box<dyn Module> = LuaModule::new(
-            name,
-            self.root.expect("Missing root directory for module"),
-            source_files,
-            Default::default(),
-            self.config,
-        );
+        if self.module_type == ModuleType::Wasm {
+            let module = WasmModule::new(self.name, root, source_files, self.limits)?;
+            return Ok(Arc::new(module));
+        }
+        let permissions =
+            ModulePermissions::new(&self.name, self.permissions.clone(), self.granted.clone());
+        let module = LuaPool::new(self.name, root, source_files, self.config, permissions);
         Ok(module)
     }
 
-    fn create(dest: &PathBuf, value: Definition) -> Result<ModuleDefinition> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// `aliases` from module.rigz, functions without a module are the module's own
+    pub fn aliases(&self) -> HashMap<String, String> {
+        self.aliases
+            .iter()
+            .map(|(alias, target)| {
+                let target = if target.contains('.') {
+                    target.clone()
+                } else {
+                    format!("{}.{}", self.name, target)
+                };
+                (alias.clone(), target)
+            })
+            .collect()
+    }
+
+    /// What the module declared and was granted, capabilities that are declared but not granted are logged.
+    pub fn permissions(&self) -> ModulePermissions {
+        let permissions =
+            ModulePermissions::new(&self.name, self.permissions.clone(), self.granted.clone());
+        for capability in permissions.ungranted() {
+            warn!(
+                "{} declares `{}`, it's denied until granted in `config.permissions`",
+                self.name, capability
+            );
+        }
+        permissions
+    }
+
+    fn create(dest: &Path, value: Definition) -> Result<ModuleDefinition> {
         match value {
             Definition::Object(o) => {
                 let mut o = o.0;
@@ -218,7 +312,9 @@ impl ModuleDefinition {
                         .remove("name")
                         .expect("`module { name }` is missing")
                         .to_string(),
-                    root: Some(dest.clone()),
+                    module_type: module_type(o.remove("type"))?,
+                    library: o.remove("library").map(|l| l.to_string()),
+                    root: Some(dest.to_path_buf()),
                     source_files: o.remove("source_files").map(|s| {
                         s.to_list()
                             .unwrap_or_default()
@@ -227,6 +323,10 @@ impl ModuleDefinition {
                             .collect()
                     }),
                     config: convert_to_value(o.remove("config"))?,
+                    permissions: declared_permissions(o.remove("permissions"))?,
+                    granted: Permissions::default(),
+                    limits: WasmLimits::default(),
+                    aliases: declared_aliases(o.remove("aliases"))?,
                 })
             }
             Definition::List(_l) => Err(anyhow!("Lists are not currently supported here")),
@@ -235,14 +335,18 @@ impl ModuleDefinition {
 
     pub fn source_files(&self) -> Result<Vec<PathBuf>> {
         let mut files = Vec::new();
+        let default = match self.module_type {
+            ModuleType::Wasm => "**/*.wasm",
+            _ => "**/*.lua",
+        };
         let mut patterns = match &self.source_files {
             None => {
-                vec!["**/*.lua".to_string()]
+                vec![default.to_string()]
             }
             Some(v) => v.clone(),
         };
         if patterns.is_empty() {
-            patterns = vec!["**/*.lua".to_string()];
+            patterns = vec![default.to_string()];
         }
         let root = self.root.clone().expect("root is missing for module");
         for s in patterns {
@@ -257,6 +361,36 @@ impl ModuleDefinition {
     }
 }
 
+fn module_type(element: Option<Element>) -> Result<ModuleType> {
+    match element.map(|e| e.to_string()).as_deref() {
+        None | Some("lua") => Ok(ModuleType::Lua),
+        Some("cdylib") => Ok(ModuleType::Cdylib),
+        Some("wasm") => Ok(ModuleType::Wasm),
+        Some(other) => Err(anyhow!(
+            "Unknown module type `{}`, expected `lua`, `cdylib` or `wasm`",
+            other
+        )),
+    }
+}
+
+/// `module { permissions = { fs_read = ["data"], env = ["HOME"] } }`
+fn declared_permissions(element: Option<Element>) -> Result<Permissions> {
+    match element {
+        None => Ok(Permissions::default()),
+        Some(e) => from_argument(element_to_arg(&e)?)
+            .map_err(|e| anyhow!("Invalid `permissions` in module.rigz: {}", e)),
+    }
+}
+
+/// `module { aliases = { deploy = "apply" } }`
+fn declared_aliases(element: Option<Element>) -> Result<HashMap<String, String>> {
+    match element {
+        None => Ok(HashMap::new()),
+        Some(e) => from_argument(element_to_arg(&e)?)
+            .map_err(|e| anyhow!("Invalid `aliases` in module.rigz: {}", e)),
+    }
+}
+
 fn convert_to_value(element: Option<Element>) -> Result<Option<Value>> {
     if element.is_none() {
         return Ok(None);
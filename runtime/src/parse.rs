@@ -1,7 +1,7 @@
 use anyhow::anyhow;
 use glob::{glob_with, MatchOptions};
 use log::warn;
-use rigz_parse::{parse, ParseConfig, AST};
+use rigz_parse::{parse, Backend, IdentifierPolicy, LanguageVersion, ParseConfig, AST};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::File;
@@ -11,6 +11,11 @@ use std::path::PathBuf;
 #[derive(Clone, Default, Deserialize)]
 pub struct ParseOptions {
     pub use_64_bit_numbers: Option<bool>,
+    /// Default language version for files without a pragma, e.g. "0.1"
+    pub version: Option<String>,
+    pub identifiers: Option<IdentifierPolicy>,
+    /// "pest" (default) or "tree-sitter", which needs the `tree-sitter` feature
+    pub backend: Option<Backend>,
     pub source_files: Vec<String>,
     pub glob_options: Option<GlobOptions>,
 }
@@ -60,17 +65,39 @@ fn find_source_files(
     Ok(paths)
 }
 
+/// ASTs and file contents by filename, the contents are kept to report where errors happen.
 pub(crate) fn parse_source_files(
     parse_options: ParseOptions,
+) -> anyhow::Result<(HashMap<String, AST>, HashMap<String, String>)> {
+    parse_files(parse_options, false)
+}
+
+/// Parse `parse.source_files`, `preserve_macros` keeps `macro` definitions and calls unexpanded.
+pub fn parse_sources(
+    parse_options: ParseOptions,
+    preserve_macros: bool,
 ) -> anyhow::Result<HashMap<String, AST>> {
+    Ok(parse_files(parse_options, preserve_macros)?.0)
+}
+
+fn parse_files(
+    parse_options: ParseOptions,
+    preserve_macros: bool,
+) -> anyhow::Result<(HashMap<String, AST>, HashMap<String, String>)> {
     let mut asts = HashMap::new();
+    let mut sources = HashMap::new();
+    let version = match &parse_options.version {
+        None => LanguageVersion::latest(),
+        Some(v) => v.parse()?,
+    };
     let ast_config = ParseConfig {
         use_64_bit_numbers: parse_options.use_64_bit_numbers.unwrap_or(false),
+        version,
+        identifiers: parse_options.identifiers.unwrap_or_default(),
+        preserve_macros,
+        backend: parse_options.backend.unwrap_or_default(),
     };
-    let glob = parse_options
-        .glob_options
-        .unwrap_or_default()
-        .into();
+    let glob = parse_options.glob_options.unwrap_or_default().into();
     for path in find_source_files(parse_options.source_files, glob)? {
         let mut file = File::open(&path)?;
         let mut contents = String::new();
@@ -80,7 +107,8 @@ pub(crate) fn parse_source_files(
             .file_name()
             .map(|s| s.to_str().expect("Failed to convert OsStr to string"))
             .unwrap_or_else(|| panic!("Failed to get filename for {:?}", path));
-        asts.insert(filename.to_string(), parse(contents, &ast_config)?);
+        asts.insert(filename.to_string(), parse(contents.clone(), &ast_config)?);
+        sources.insert(filename.to_string(), contents);
     }
-    Ok(asts)
+    Ok((asts, sources))
 }
@@ -1,11 +1,16 @@
+use crate::builtins::{Builtins, BUILTINS};
 use crate::{Runtime, RuntimeConfig};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Error, Result};
 use log::{info, warn};
-use rigz_core::{Argument, FunctionCall, RuntimeStatus};
-use rigz_parse::{ASTFunctionCall, Definition, Element, Object, Value};
+use rigz_core::{
+    Argument, CallContext, ErrorKind, FunctionCall, InitializationArgs, RigzError, RuntimeStatus,
+    Scope, SyncModule, MAX_CALL_DEPTH,
+};
+use rigz_parse::{ASTFunctionCall, Definition, Element, Object, Value, AST};
 use serde::Serialize;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::thread;
 
 #[derive(Clone, Default, Debug, Serialize, Copy)]
 pub struct RunArgs {
@@ -15,84 +20,406 @@ pub struct RunArgs {
     pub require_aliases: bool,
 }
 
+impl From<&RunArgs> for InitializationArgs {
+    fn from(args: &RunArgs) -> Self {
+        InitializationArgs {
+            all_errors_fatal: args.all_errors_fatal,
+            ignore_symbol_not_found: args.ignore_symbol_not_found,
+            prefer_none_over_prior_result: args.prefer_none_over_prior_result,
+            require_aliases: args.require_aliases,
+        }
+    }
+}
+
+impl From<InitializationArgs> for RunArgs {
+    fn from(args: InitializationArgs) -> Self {
+        RunArgs {
+            all_errors_fatal: args.all_errors_fatal,
+            ignore_symbol_not_found: args.ignore_symbol_not_found,
+            prefer_none_over_prior_result: args.prefer_none_over_prior_result,
+            require_aliases: args.require_aliases,
+        }
+    }
+}
+
 pub struct RunResult {
     pub value: HashMap<String, Argument>,
 }
 
-pub fn initialize_runtime(config: RuntimeConfig, args: Rc<RunArgs>) -> Result<Runtime> {
+pub fn initialize_runtime(config: RuntimeConfig, args: Arc<RunArgs>) -> Result<Runtime> {
     let mut modules = HashMap::with_capacity(config.modules.len());
-    let mut globals = HashMap::new();
-    let mut lookup = Vec::new();
+    let mut signatures = HashMap::with_capacity(config.modules.len());
+    let globals = HashMap::new();
+    let lookup = Vec::new();
     let base_config = config.initialize_args(args.clone());
+    let mut permissions = HashMap::with_capacity(config.modules.len());
+    let mut declared_aliases = Vec::with_capacity(config.modules.len());
+    let mut order = Vec::with_capacity(config.modules.len());
+    let builtins: Arc<dyn SyncModule> = Arc::new(Builtins);
+    signatures.insert(
+        BUILTINS.to_string(),
+        builtins
+            .functions()
+            .into_iter()
+            .map(|f| (f.name.clone(), f))
+            .collect(),
+    );
+    modules.insert(BUILTINS.to_string(), builtins);
     for definition in config.modules {
+        permissions.insert(definition.name().to_string(), definition.permissions());
+        declared_aliases.push((definition.name().to_string(), definition.aliases()));
         let module = definition.to_module(args.clone())?;
         let name = module.name().to_string();
+        if modules.contains_key(&name) {
+            return Err(duplicate_module(&name).into());
+        }
         info!("Initializing {}", name);
         match module.initialize(base_config) {
             RuntimeStatus::Ok(_) => {}
             RuntimeStatus::NotFound => {
                 info!("Not Initialization Method for {}", name);
             }
-            RuntimeStatus::Err(e) => return Err(anyhow!("Module initialization failed - {}", e)),
-        }
-        match modules.insert(name, module) {
-            None => {}
-            Some(old) => {
-                warn!("Overwrote Module {}", old.name());
+            RuntimeStatus::Err(e) => {
+                return Err(RigzError::new(
+                    ErrorKind::Initialization,
+                    "Module initialization failed",
+                )
+                .with_module(name)
+                .with_cause(e)
+                .into())
             }
-        };
+        }
+        let functions = module.functions().into_iter().map(|f| (f.name.clone(), f));
+        signatures.insert(name.clone(), functions.collect());
+        order.push(name.clone());
+        modules.insert(name, module);
     }
-    Ok(Runtime {
+    let runtime = Runtime {
         asts: config.asts,
+        sources: config.sources,
         modules,
+        async_modules: HashMap::new(),
+        signatures,
+        permissions,
         globals,
         lookup,
-    })
+        aliases: resolve_aliases(config.aliases, declared_aliases)?,
+        order,
+    };
+    match runtime.unknown_alias() {
+        None => Ok(runtime),
+        Some(e) => Err(e.into()),
+    }
+}
+
+/// Module names have to be unique, otherwise which one a call resolves to would depend on load order
+pub(crate) fn duplicate_module(name: &str) -> RigzError {
+    RigzError::new(
+        ErrorKind::Initialization,
+        format!(
+            "Module `{}` is already loaded, module names have to be unique",
+            name
+        ),
+    )
+    .with_module(name)
 }
 
+/// Aliases from the options and each module.rigz, the options' win. Modules giving an alias different functions fail
+/// with every module that defines it.
+pub(crate) fn resolve_aliases(
+    options: HashMap<String, String>,
+    declared: Vec<(String, HashMap<String, String>)>,
+) -> Result<HashMap<String, String>> {
+    let mut defined_by: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (module, aliases) in declared {
+        for (alias, target) in aliases {
+            defined_by
+                .entry(alias)
+                .or_default()
+                .push((module.clone(), target));
+        }
+    }
+    let mut aliases = HashMap::with_capacity(defined_by.len() + options.len());
+    for (alias, mut targets) in defined_by {
+        if options.contains_key(&alias) {
+            continue;
+        }
+        targets.sort();
+        if targets.iter().any(|(_, target)| *target != targets[0].1) {
+            let modules: Vec<String> = targets
+                .iter()
+                .map(|(module, target)| format!("{} ({})", module, target))
+                .collect();
+            return Err(anyhow!(
+                "Alias `{}` is defined by {}, set it in the options' `aliases`",
+                alias,
+                modules.join(", ")
+            ));
+        }
+        aliases.insert(alias, targets.swap_remove(0).1);
+    }
+    for (alias, target) in options {
+        if !target.contains('.') {
+            return Err(anyhow!(
+                "Alias `{}` has to call a `module.function`, not `{}`",
+                alias,
+                target
+            ));
+        }
+        aliases.insert(alias, target);
+    }
+    Ok(aliases)
+}
+
+/// Runs every file, then shuts down the modules. Modules are told when each file starts and ends.
 pub fn run(runtime: &Runtime, args: RunArgs) -> Result<RunResult> {
-    let mut value = HashMap::with_capacity(runtime.asts.len());
-    for (file, ast) in &runtime.asts {
-        let mut prior_result = Argument::None;
-        info!("Running {}", file);
-        for element in &ast.elements {
-            match element {
-                Element::FunctionCall(fc) => {
-                    prior_result = call_function(runtime, convert(fc)?, prior_result, &args)?;
-                }
-                _ => return Err(anyhow!("Invalid Element in root of AST: {:?}", element)),
+    let run = || {
+        let mut value = HashMap::with_capacity(runtime.asts.len());
+        for (file, ast) in &runtime.asts {
+            value.insert(file.to_string(), run_file(runtime, file, ast, &args)?);
+        }
+        Ok(RunResult { value })
+    };
+    after_hook(run(), runtime.shutdown())
+}
+
+/// Like `run`, with each file on its own thread. Files can't depend on each other's globals, Lua modules keep a
+/// separate state per thread.
+pub fn run_parallel(runtime: &Runtime, args: RunArgs) -> Result<RunResult> {
+    let results: Vec<(String, Result<Argument>)> = thread::scope(|scope| {
+        let handles: Vec<_> = runtime
+            .asts
+            .iter()
+            .map(|(file, ast)| {
+                let handle = scope.spawn(move || run_file(runtime, file, ast, &args));
+                (file, handle)
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(file, handle)| {
+                let result = handle
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("Thread running {} panicked", file)));
+                (file.to_string(), result)
+            })
+            .collect()
+    });
+
+    let run = || {
+        let mut value = HashMap::with_capacity(results.len());
+        for (file, result) in results {
+            value.insert(file, result?);
+        }
+        Ok(RunResult { value })
+    };
+    after_hook(run(), runtime.shutdown())
+}
+
+fn run_file(runtime: &Runtime, file: &str, ast: &AST, args: &RunArgs) -> Result<Argument> {
+    let result = runtime
+        .on_file_start(file)
+        .and_then(|_| run_elements(runtime, file, ast, args));
+    end_file(runtime, file, result)
+}
+
+fn run_elements(runtime: &Runtime, file: &str, ast: &AST, args: &RunArgs) -> Result<Argument> {
+    let mut prior_result = Argument::None;
+    let scope = Scope::default();
+    info!("Running {} (rigz {})", file, ast.version);
+    for element in &ast.elements {
+        match element {
+            Element::FunctionCall(fc) => {
+                let span = runtime.source_span(file, fc.span);
+                let context = CallContext::new(runtime, &scope, args.into())
+                    .with_file(file)
+                    .with_span(span.clone());
+                prior_result = match call_function(runtime, convert(fc)?, prior_result, &context) {
+                    Ok(Argument::Error(e)) => Argument::Error(e.with_span(span)),
+                    Ok(a) => a,
+                    Err(e) => return Err(to_rigz_error(e).with_span(span).into()),
+                };
+            }
+            _ => return Err(anyhow!("Invalid Element in root of AST: {:?}", element)),
+        }
+    }
+    Ok(prior_result)
+}
+
+/// Like `run`, awaiting calls to `AsyncModule`s
+pub async fn run_async(runtime: &Runtime, args: RunArgs) -> Result<RunResult> {
+    let run = async {
+        let mut value = HashMap::with_capacity(runtime.asts.len());
+        for (file, ast) in &runtime.asts {
+            let result = match runtime.on_file_start_async(file).await {
+                Ok(()) => run_elements_async(runtime, file, ast, &args).await,
+                Err(e) => Err(e),
+            };
+            value.insert(
+                file.to_string(),
+                end_file_async(runtime, file, result).await?,
+            );
+        }
+        Ok(RunResult { value })
+    };
+    let result = run.await;
+    after_hook(result, runtime.shutdown_async().await)
+}
+
+async fn run_elements_async(
+    runtime: &Runtime,
+    file: &str,
+    ast: &AST,
+    args: &RunArgs,
+) -> Result<Argument> {
+    let mut prior_result = Argument::None;
+    let scope = Scope::default();
+    info!("Running {} (rigz {})", file, ast.version);
+    for element in &ast.elements {
+        match element {
+            Element::FunctionCall(fc) => {
+                let span = runtime.source_span(file, fc.span);
+                let context = CallContext::new(runtime, &scope, args.into())
+                    .with_file(file)
+                    .with_span(span.clone());
+                let result = call_function_async(runtime, convert(fc)?, prior_result, &context);
+                prior_result = match result.await {
+                    Ok(Argument::Error(e)) => Argument::Error(e.with_span(span)),
+                    Ok(a) => a,
+                    Err(e) => return Err(to_rigz_error(e).with_span(span).into()),
+                };
             }
+            _ => return Err(anyhow!("Invalid Element in root of AST: {:?}", element)),
         }
-        value.insert(file.to_string(), prior_result);
     }
-    Ok(RunResult { value })
+    Ok(prior_result)
 }
+
+/// Calls `on_file_end` with the file's value or error.
+fn end_file(runtime: &Runtime, file: &str, result: Result<Argument>) -> Result<Argument> {
+    let hook = match &result {
+        Ok(value) => runtime.on_file_end(file, value),
+        Err(e) => runtime.on_file_end(file, &Argument::Error(file_error(e))),
+    };
+    after_hook(result, hook)
+}
+
+/// Like `end_file`, awaiting `AsyncModule`s
+async fn end_file_async(
+    runtime: &Runtime,
+    file: &str,
+    result: Result<Argument>,
+) -> Result<Argument> {
+    let hook = match &result {
+        Ok(value) => runtime.on_file_end_async(file, value).await,
+        Err(e) => {
+            let error = Argument::Error(file_error(e));
+            runtime.on_file_end_async(file, &error).await
+        }
+    };
+    after_hook(result, hook)
+}
+
+/// The error a file failed with, as `on_file_end` gets it
+fn file_error(error: &Error) -> RigzError {
+    error
+        .downcast_ref::<RigzError>()
+        .cloned()
+        .unwrap_or_else(|| RigzError::runtime(format!("{:#}", error)))
+}
+
+/// `result` unless only the hook failed, a hook's error is logged if `result` already failed.
+fn after_hook<T>(result: Result<T>, hook: Result<()>) -> Result<T> {
+    match (result, hook) {
+        (result, Ok(())) => result,
+        (Ok(_), Err(e)) => Err(e),
+        (Err(e), Err(hook)) => {
+            warn!("{:#}", hook);
+            Err(e)
+        }
+    }
+}
+
+/// Keeps a `RigzError` returned by a module, anything else becomes a runtime error with the full context.
+pub(crate) fn to_rigz_error(error: anyhow::Error) -> RigzError {
+    match error.downcast::<RigzError>() {
+        Ok(e) => e,
+        Err(e) => RigzError::runtime(format!("{:#}", e)),
+    }
+}
+
+/// Calls `fc`, then every call it returns. Like `CallContext::evaluate`, each returned call is one more level of
+/// depth, so a call returning itself fails at `MAX_CALL_DEPTH`.
 fn call_function(
     runtime: &Runtime,
-    fc: FunctionCall,
+    mut fc: FunctionCall,
+    prior_result: Argument,
+    context: &CallContext,
+) -> Result<Argument> {
+    let mut depth = context.depth();
+    loop {
+        let result = runtime.invoke_in(
+            fc.name.as_str(),
+            fc.args,
+            fc.definition,
+            &prior_result,
+            context,
+        )?;
+        match result {
+            Argument::None => {
+                if context.args.prefer_none_over_prior_result {
+                    return Ok(Argument::None);
+                } else {
+                    return Ok(prior_result);
+                }
+            }
+            Argument::FunctionCall(next) => fc = returned_call(next, &mut depth)?,
+            _ => return Ok(result),
+        }
+    }
+}
+
+/// Like `call_function`, awaiting `AsyncModule`s
+async fn call_function_async(
+    runtime: &Runtime,
+    mut fc: FunctionCall,
     prior_result: Argument,
-    config: &RunArgs,
+    context: &CallContext<'_>,
 ) -> Result<Argument> {
-    let result = runtime.invoke_symbol(
-        fc.name.as_str(),
-        fc.args,
-        fc.definition,
-        &prior_result,
-        config,
-    )?;
-    match result {
-        Argument::None => {
-            if config.prefer_none_over_prior_result {
-                Ok(Argument::None)
-            } else {
-                Ok(prior_result)
+    let mut depth = context.depth();
+    loop {
+        let result = runtime
+            .invoke_in_async(
+                fc.name.as_str(),
+                fc.args,
+                fc.definition,
+                &prior_result,
+                context,
+            )
+            .await?;
+        match result {
+            Argument::None => {
+                if context.args.prefer_none_over_prior_result {
+                    return Ok(Argument::None);
+                } else {
+                    return Ok(prior_result);
+                }
             }
+            Argument::FunctionCall(next) => fc = returned_call(next, &mut depth)?,
+            _ => return Ok(result),
         }
-        Argument::FunctionCall(fc) => call_function(runtime, fc, prior_result, config),
-        _ => Ok(result),
     }
 }
 
+/// `next` unless following it would go deeper than `MAX_CALL_DEPTH`
+fn returned_call(next: FunctionCall, depth: &mut usize) -> Result<FunctionCall> {
+    *depth += 1;
+    if *depth >= MAX_CALL_DEPTH {
+        return Err(RigzError::call_depth_exceeded(&next.name).into());
+    }
+    Ok(next)
+}
+
 fn convert(function_call: &ASTFunctionCall) -> Result<FunctionCall> {
     let args = to_args(&function_call.args)?;
     let mut definition = rigz_core::Definition::None;
@@ -127,7 +454,7 @@ fn to_args(elements: &Vec<Element>) -> Result<Vec<Argument>> {
     Ok(args)
 }
 
-fn element_to_arg(element: &Element) -> Result<Argument> {
+pub(crate) fn element_to_arg(element: &Element) -> Result<Argument> {
     let argument = match element {
         Element::Value(v) => match v {
             Value::Int(i) => Argument::Int(*i),
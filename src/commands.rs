@@ -1,23 +1,25 @@
 use crate::init::{init_project, InitArgs};
 use anyhow::anyhow;
 use clap_derive::{Args, Subcommand};
-use rigz_runtime::run::{initialize_runtime, run, RunResult};
-use rigz_runtime::{initialize, Options};
+use rigz_runtime::parse::parse_sources;
+use rigz_runtime::run::{initialize_runtime, run, run_parallel, RunResult};
+use rigz_runtime::{initialize, Options, Runtime};
 use std::path::PathBuf;
 use std::process::exit;
-use std::rc::Rc;
+use std::sync::Arc;
 use log::error;
-use rustyline::config::Configurer;
-use rustyline::{ColorMode, DefaultEditor};
+use rustyline::DefaultEditor;
 use rustyline::error::ReadlineError;
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     Init(InitArgs),
+    Parse(ParseArgs),
     Run(RunArgs),
     Setup(SetupArgs),
     Test(TestArgs),
     Console(ConsoleArgs),
+    Modules(ModulesArgs),
 }
 
 impl Commands {
@@ -30,11 +32,27 @@ impl Commands {
                         let _config = initialize(options)?;
                         exit(0)
                     },
+                    Commands::Parse(args) => {
+                        let asts = parse_sources(options.parse.unwrap_or_default(), !args.expand)?;
+                        println!("{}", serde_json::to_string_pretty(&asts)?);
+                        exit(0)
+                    }
                     Commands::Run(args) => {
                         let config = initialize(options)?;
+                        let parallel = args.parallel;
                         let args = args.into();
-                        let mut runtime = initialize_runtime(config, Rc::new(args))?;
-                        run(&mut runtime, args)
+                        let runtime = initialize_runtime(config, Arc::new(args))?;
+                        if parallel {
+                            run_parallel(&runtime, args)
+                        } else {
+                            run(&runtime, args)
+                        }
+                    }
+                    Commands::Modules(args) => {
+                        for line in args.handle(options)? {
+                            println!("{}", line);
+                        }
+                        exit(0)
                     }
                     Commands::Test(args) => {
                         if !args.test_directory.exists() {
@@ -47,7 +65,7 @@ impl Commands {
                     Commands::Console(args) => {
                         let config = initialize(options)?;
                         let args = args.into();
-                        let mut runtime = initialize_runtime(config, Rc::new(args))?;
+                        let mut runtime = initialize_runtime(config, Arc::new(args))?;
                         let mut rl = DefaultEditor::new()?;
                         #[cfg(feature = "with-file-history")]
                         if rl.load_history("history.txt").is_err() {
@@ -69,6 +87,10 @@ impl Commands {
                                     match line.as_str() {
                                         "exit" => break,
                                         "help" => print_help_string(&line),
+                                        "reload" => match runtime.reload(&args) {
+                                            Ok(_) => println!("Reloaded modules"),
+                                            Err(e) => println!("Error: {:#}", e),
+                                        },
                                         &_ => {
                                             println!("Line: {}", line);
                                         }
@@ -90,6 +112,7 @@ impl Commands {
                         }
                         #[cfg(feature = "with-file-history")]
                         rl.save_history("history.txt");
+                        runtime.shutdown()?;
                         exit(0)
                     },
                     _ => return Err(anyhow!("Unimplemented command: {:?}", self)),
@@ -99,8 +122,8 @@ impl Commands {
     }
 }
 
-impl Into<rigz_runtime::run::RunArgs> for ConsoleArgs {
-    fn into(self) -> rigz_runtime::run::RunArgs {
+impl From<ConsoleArgs> for rigz_runtime::run::RunArgs {
+    fn from(_value: ConsoleArgs) -> Self {
         rigz_runtime::run::RunArgs {
             all_errors_fatal: false,
             ignore_symbol_not_found: false,
@@ -119,6 +142,13 @@ fn print_help_string(input: &str) {
 #[derive(Args, Debug)]
 pub struct ConsoleArgs {}
 
+#[derive(Args, Debug, Default)]
+pub struct ParseArgs {
+    /// Expand `macro` calls, by default macros are shown as written
+    #[arg(short, long, action)]
+    expand: bool,
+}
+
 #[derive(Args, Debug, Default)]
 pub struct RunArgs {
     #[arg(short, long, action)]
@@ -129,6 +159,9 @@ pub struct RunArgs {
     prefer_none_over_prior_result: bool,
     #[arg(short, long, action)]
     require_aliases: bool,
+    /// Run each source file on its own thread
+    #[arg(long, action)]
+    parallel: bool,
 }
 
 impl From<RunArgs> for rigz_runtime::run::RunArgs {
@@ -145,6 +178,74 @@ impl From<RunArgs> for rigz_runtime::run::RunArgs {
 #[derive(Args, Debug)]
 pub struct SetupArgs {}
 
+#[derive(Args, Debug)]
+pub struct ModulesArgs {
+    #[command(subcommand)]
+    command: ModulesCommand,
+}
+
+impl ModulesArgs {
+    /// Lines to print, the modules are shut down before returning
+    pub fn handle(self, options: Options) -> anyhow::Result<Vec<String>> {
+        match self.command {
+            ModulesCommand::Which(args) => {
+                let config = initialize(options)?;
+                let symbol = args.symbol.clone();
+                let args = args.into();
+                let runtime = initialize_runtime(config, Arc::new(args))?;
+                let lines = which(&runtime, &symbol, &args);
+                let shutdown = runtime.shutdown();
+                let lines = lines?;
+                shutdown?;
+                Ok(lines)
+            }
+        }
+    }
+}
+
+fn which(
+    runtime: &Runtime,
+    symbol: &str,
+    args: &rigz_runtime::run::RunArgs,
+) -> anyhow::Result<Vec<String>> {
+    let Some((module, function)) = runtime.which(symbol, args)? else {
+        return Err(anyhow!("`{}` isn't defined by any module", symbol));
+    };
+    let mut lines = vec![format!("{}.{}", module, function)];
+    let others: Vec<&str> = runtime
+        .defined_by(&function)
+        .into_iter()
+        .filter(|m| *m != module)
+        .collect();
+    if !others.is_empty() {
+        lines.push(format!("also defined by {}", others.join(", ")));
+    }
+    Ok(lines)
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ModulesCommand {
+    /// Module a function call resolves to, and the other modules defining it
+    Which(WhichArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct WhichArgs {
+    /// Function name, alias or `module.function`
+    symbol: String,
+    #[arg(short, long, action)]
+    require_aliases: bool,
+}
+
+impl From<WhichArgs> for rigz_runtime::run::RunArgs {
+    fn from(value: WhichArgs) -> Self {
+        rigz_runtime::run::RunArgs {
+            require_aliases: value.require_aliases,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub struct TestArgs {
     test_directory: PathBuf,
@@ -57,14 +57,20 @@ fn main() -> Result<()> {
     setup_logger(&cli);
     let options = cli.options()?;
 
-    let result = cli.command.unwrap().handle(options)?;
-    output::handle_result(cli.output.clone(), result)
+    match cli.command.unwrap().handle(options) {
+        Ok(result) => output::handle_result(cli.output.clone(), result),
+        Err(e) => {
+            output::handle_error(cli.output.clone(), e)?;
+            exit(1)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::commands::{Commands, RunArgs};
     use crate::CLI;
+    use clap::Parser;
     use rigz_core::Argument;
     use std::collections::HashMap;
     use std::path::PathBuf;
@@ -84,4 +90,24 @@ mod tests {
         expected.insert("hello.rigz".to_string(), Argument::None);
         assert_eq!(result.value, expected);
     }
+
+    #[test]
+    fn modules_which_prints_the_module() {
+        let cli = CLI::try_parse_from(["rigz", "-c", "local_run.json", "modules", "which", "puts"])
+            .expect("Parse Failed");
+        let options = cli.options().expect("Options Failed");
+        let Some(Commands::Modules(args)) = cli.command else {
+            panic!("Expected `modules`")
+        };
+
+        let lines = args.handle(options).expect("Which Failed");
+        assert_eq!(lines, vec!["std.puts".to_string()]);
+    }
+
+    #[test]
+    fn invalid_output_still_handles_the_error() {
+        let error = anyhow::anyhow!("Run Failed");
+        let result = crate::output::handle_error(Some("yaml".to_string()), error);
+        assert!(result.is_ok());
+    }
 }
@@ -1,6 +1,8 @@
 use anyhow::anyhow;
-use log::info;
+use log::{error, info, warn};
+use rigz_core::{Argument, RigzError};
 use rigz_runtime::run::RunResult;
+use serde_json::json;
 
 #[derive(Clone, Default, Debug)]
 pub enum OutputFormat {
@@ -10,29 +12,41 @@ pub enum OutputFormat {
     LOG,
 }
 
-pub fn handle_result(output: Option<String>, result: RunResult) -> anyhow::Result<()> {
-    let format = match output {
-        None => OutputFormat::default(),
+fn output_format(output: Option<String>) -> anyhow::Result<OutputFormat> {
+    match output {
+        None => Ok(OutputFormat::default()),
         Some(f) => {
             let fmt = f.trim().to_lowercase();
             if fmt == "json" {
-                OutputFormat::JSON
+                Ok(OutputFormat::JSON)
             } else {
-                return Err(anyhow!("Invalid Format: `{}`", fmt));
+                Err(anyhow!("Invalid Format: `{}`", fmt))
             }
         }
-    };
+    }
+}
+
+pub fn handle_result(output: Option<String>, result: RunResult) -> anyhow::Result<()> {
+    let format = output_format(output)?;
     match format {
         OutputFormat::PRINT => {
             println!("Results:");
             for (file, value) in result.value {
-                println!("\t{}: {}", file, value)
+                println!(
+                    "\t{}: {}",
+                    file,
+                    display_value(&value).replace('\n', "\n\t")
+                )
             }
         }
         OutputFormat::LOG => {
             info!("Results:");
             for (file, value) in result.value {
-                info!("\t{}: {}", file, value)
+                info!(
+                    "\t{}: {}",
+                    file,
+                    display_value(&value).replace('\n', "\n\t")
+                )
             }
         }
         _ => match format {
@@ -45,3 +59,59 @@ pub fn handle_result(output: Option<String>, result: RunResult) -> anyhow::Resul
     }
     Ok(())
 }
+
+/// Prints a failed run, JSON output is `{"error": RigzError}` so it can be parsed like a result. An invalid `output`
+/// prints the error instead of replacing it.
+pub fn handle_error(output: Option<String>, error: anyhow::Error) -> anyhow::Result<()> {
+    let format = output_format(output).unwrap_or_else(|e| {
+        warn!("{}, printing the error", e);
+        OutputFormat::PRINT
+    });
+    let error = match error.downcast::<RigzError>() {
+        Ok(e) => e,
+        Err(e) => RigzError::runtime(format!("{:#}", e)),
+    };
+    match format {
+        OutputFormat::PRINT => eprintln!("{}", render_error(&error)),
+        OutputFormat::LOG => error!("{}", render_error(&error)),
+        OutputFormat::JSON => {
+            let contents = serde_json::to_string_pretty(&json!({ "error": error }))?;
+            println!("{}", contents)
+        }
+    }
+    Ok(())
+}
+
+fn display_value(value: &Argument) -> String {
+    match value {
+        Argument::Error(e) => render_error(e),
+        v => v.to_string(),
+    }
+}
+
+/// Multi-line error with its location, causes, and data:
+/// ```text
+/// error[runtime]: Lua Execution Failed
+///   --> main.rigz:3:1
+///   in lua.fail
+/// caused by[runtime]: boom
+/// ```
+pub fn render_error(error: &RigzError) -> String {
+    let mut lines = Vec::new();
+    for (index, e) in error.chain().enumerate() {
+        let label = if index == 0 { "error" } else { "caused by" };
+        lines.push(format!("{}[{}]: {}", label, e.kind, e.message));
+        if let Some(span) = &e.span {
+            lines.push(format!("  --> {}", span));
+        }
+        if let Some(location) = e.location() {
+            lines.push(format!("  in {}", location));
+        }
+        let mut data: Vec<_> = e.data.iter().collect();
+        data.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, value) in data {
+            lines.push(format!("  {}: {}", key, value));
+        }
+    }
+    lines.join("\n")
+}